@@ -1,13 +1,16 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde::Serialize;
 
 #[derive(Deserialize, Debug, Clone)]
-// a1, h8, etc
+// a1, h8, etc. `promotion` ("q", "r", "b" or "n") picks the piece a pawn becomes in a game,
+// a queen by default
 pub struct RequestMove {
     from: String,
     to: String,
+    promotion: Option<String>,
 }
 
 impl RequestMove {
@@ -18,6 +21,1302 @@ impl RequestMove {
     pub fn to(&self) -> String {
         self.to.clone()
     }
+
+    // the move in coordinate notation
+    pub fn uci(&self) -> String {
+        format!(
+            "{}{}{}",
+            self.from,
+            self.to,
+            self.promotion.as_deref().unwrap_or("")
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// asks the engine to play a move for `color` ("white" or "black"),
+// limited by depth, searched nodes or thinking time in milliseconds,
+// searching on `threads` worker threads (one by default) with the `eval`
+// evaluation ("network" by default when a network is loaded, or "classical").
+// `engine` "external" lets the configured external engine move instead, it only
+// honours the depth, nodes and movetime limits. with `ponder` the engine keeps thinking
+// about the expected reply until the next request. `level` (0 to 20) or `elo` weaken
+// the engine for casual play
+pub struct RequestEngine {
+    color: String,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<u64>,
+    threads: Option<usize>,
+    eval: Option<String>,
+    engine: Option<String>,
+    ponder: Option<bool>,
+    level: Option<u32>,
+    elo: Option<u32>,
+}
+
+impl RequestEngine {
+    // an error for a color that is not one
+    pub fn color(&self) -> Result<chess::Kind, ()> {
+        color_or_white(Some(&self.color))
+    }
+
+    pub fn threads(&self) -> usize {
+        threads(self.threads)
+    }
+
+    pub fn classical(&self) -> bool {
+        self.eval.as_deref() == Some("classical")
+    }
+
+    pub fn external(&self) -> bool {
+        self.engine.as_deref() == Some("external")
+    }
+
+    pub fn ponder(&self) -> bool {
+        self.ponder.unwrap_or(false)
+    }
+
+    pub fn skill(&self) -> Skill {
+        skill(self.level, self.elo)
+    }
+
+    pub fn limits(&self) -> Limits {
+        engine_limits(self.depth, self.nodes, self.movetime)
+    }
+}
+
+fn skill(level: Option<u32>, elo: Option<u32>) -> Skill {
+    match (level, elo) {
+        (Some(level), _) => Skill::new(level),
+        (None, Some(elo)) => Skill::from_elo(elo),
+        (None, None) => Skill::default(),
+    }
+}
+
+// one search thread unless asked for more, never more than the machine has cores
+fn threads(threads: Option<usize>) -> usize {
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    threads.unwrap_or(1).clamp(1, cores)
+}
+
+// the engine searches to depth 4 when no limit is given. limits are capped and every search
+// stops after MAX_ENGINE_MOVETIME at the latest, so no request keeps a core busy for long
+fn engine_limits(depth: Option<u32>, nodes: Option<u64>, movetime: Option<u64>) -> Limits {
+    let depth = match (depth, nodes, movetime) {
+        (None, None, None) => Some(4),
+        _ => depth.map(|depth| depth.clamp(1, MAX_ANALYSIS_DEPTH)),
+    };
+    Limits {
+        depth,
+        nodes: nodes.map(|nodes| nodes.clamp(1, MAX_ENGINE_NODES)),
+        movetime: Some(Duration::from_millis(
+            movetime
+                .unwrap_or(MAX_ENGINE_MOVETIME)
+                .clamp(1, MAX_ENGINE_MOVETIME),
+        )),
+        ..Limits::default()
+    }
+}
+
+const MAX_ENGINE_NODES: u64 = 50_000_000;
+// milliseconds
+const MAX_ENGINE_MOVETIME: u64 = 60_000;
+
+#[derive(Deserialize, Debug, Clone)]
+// a new game from the starting position or `fen`. its creator plays `color` (white by
+// default) against `opponent`: "human" (the default, both sides move through the API),
+// "engine" or "external". the engine's strength and limits are given as for `RequestEngine`.
+// `time` starts a clock with that many seconds per side, plus `increment` seconds per move,
+// a `delay` in seconds before the clock runs each move and the base time added again every
+// `moves` moves. engines then play on their clock instead of the limits. `rated` games are
+// between two registered users, each sending their session token as
+// `Authorization: Bearer <token>` to create or join it
+pub struct RequestNewGame {
+    fen: Option<String>,
+    time: Option<f64>,
+    increment: Option<f64>,
+    delay: Option<f64>,
+    moves: Option<u32>,
+    color: Option<String>,
+    opponent: Option<String>,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<u64>,
+    level: Option<u32>,
+    elo: Option<u32>,
+    rated: Option<bool>,
+}
+
+impl RequestNewGame {
+    pub fn state(&self) -> Result<chess::State, anyhow::Error> {
+        match &self.fen {
+            Some(fen) => chess::State::from_fen(fen),
+            None => Ok(chess::State::default()),
+        }
+    }
+
+    // the side the creator plays, an error for a color that is not one
+    pub fn color(&self) -> Result<chess::Kind, ()> {
+        color_or_white(self.color.as_deref())
+    }
+
+    // white and black, none for an unknown opponent or color
+    pub fn players(&self) -> Option<(Player, Player)> {
+        let opponent = match self.opponent.as_deref() {
+            None | Some("human") => Player::Human,
+            Some("engine") => Player::Engine(skill(self.level, self.elo)),
+            Some("external") => Player::External,
+            Some(_) => return None,
+        };
+        match self.color().ok()? {
+            chess::Kind::White => Some((Player::Human, opponent)),
+            chess::Kind::Black => Some((opponent, Player::Human)),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        engine_limits(self.depth, self.nodes, self.movetime)
+    }
+
+    // none for an untimed game or times that are not a number of seconds
+    pub fn time_control(&self) -> Option<TimeControl> {
+        time_control(self.time, self.increment, self.delay, self.moves)
+    }
+
+    // true when a clock was asked for, valid or not
+    pub fn timed(&self) -> bool {
+        self.time.is_some()
+    }
+
+    pub fn rated(&self) -> bool {
+        self.rated.unwrap_or(false)
+    }
+}
+
+fn time_control(
+    time: Option<f64>,
+    increment: Option<f64>,
+    delay: Option<f64>,
+    moves: Option<u32>,
+) -> Option<TimeControl> {
+    let seconds = |s: Option<f64>| Duration::try_from_secs_f64(s.unwrap_or(0.0)).ok();
+    Some(TimeControl {
+        base: seconds(Some(time?))?,
+        increment: seconds(increment)?,
+        delay: seconds(delay)?,
+        moves: moves.filter(|&moves| moves > 0),
+    })
+}
+
+#[derive(Serialize, Debug)]
+// milliseconds left for white and black and whose clock runs, none before both sides moved
+// and after the game
+pub struct ResponseClock {
+    white: u64,
+    black: u64,
+    running: Option<String>,
+}
+
+impl From<Reading> for ResponseClock {
+    fn from(reading: Reading) -> Self {
+        ResponseClock {
+            white: reading.white.as_millis() as u64,
+            black: reading.black.as_millis() as u64,
+            running: reading.running.map(|side| color_name(side).to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// takes the seat of `color` in a game, or any open seat
+pub struct RequestJoin {
+    color: Option<String>,
+}
+
+impl RequestJoin {
+    // none for any seat, or a color that is not one
+    pub fn color(&self) -> Result<Option<chess::Kind>, ()> {
+        parse_color(self.color.as_deref())
+    }
+}
+
+fn parse_color(color: Option<&str>) -> Result<Option<chess::Kind>, ()> {
+    match color {
+        None => Ok(None),
+        Some("white") => Ok(Some(chess::Kind::White)),
+        Some("black") => Ok(Some(chess::Kind::Black)),
+        Some(_) => Err(()),
+    }
+}
+
+// white when no color is given
+fn color_or_white(color: Option<&str>) -> Result<chess::Kind, ()> {
+    parse_color(color).map(|color| color.unwrap_or(chess::Kind::White))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// the seat's token for the game socket, which is read-only without it, or a session token
+// that shows the spectators' chat on the socket and the event stream. the session token for
+// the lobby's
+pub struct RequestToken {
+    token: Option<String>,
+}
+
+impl RequestToken {
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+#[derive(Serialize, Debug)]
+// the seat a player took and the secret token that moves it, sent as
+// `Authorization: Bearer <token>`
+pub struct ResponseSeat {
+    color: String,
+    token: String,
+}
+
+impl ResponseSeat {
+    pub fn new(side: chess::Kind, token: String) -> Self {
+        ResponseSeat {
+            color: color_name(side).to_string(),
+            token,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a new game and the creator's seat in it
+pub struct ResponseNewGame {
+    #[serde(flatten)]
+    game: ResponseGame,
+    #[serde(flatten)]
+    seat: ResponseSeat,
+}
+
+impl ResponseNewGame {
+    pub fn new(game: &Game, seat: ResponseSeat) -> Self {
+        ResponseNewGame {
+            game: ResponseGame::from(game),
+            seat,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a game with its moves in coordinate notation, the human seats nobody took yet and the
+// registered users seated, the result and its reason once it is over
+pub struct ResponseGame {
+    id: u64,
+    fen: String,
+    white: String,
+    black: String,
+    rated: bool,
+    white_user: Option<String>,
+    black_user: Option<String>,
+    open_seats: Vec<String>,
+    turn: String,
+    moves: Vec<String>,
+    clock: Option<ResponseClock>,
+    // the side whose draw offer stands
+    draw_offer: Option<String>,
+    result: Option<String>,
+    reason: Option<String>,
+}
+
+impl From<&Game> for ResponseGame {
+    fn from(game: &Game) -> Self {
+        let ending = game.ending();
+        ResponseGame {
+            id: game.id(),
+            fen: game.state().to_fen(),
+            white: game.player(chess::Kind::White).name().to_string(),
+            black: game.player(chess::Kind::Black).name().to_string(),
+            rated: game.rated(),
+            white_user: game.user(chess::Kind::White).map(|user| user.to_string()),
+            black_user: game.user(chess::Kind::Black).map(|user| user.to_string()),
+            open_seats: game
+                .open_seats()
+                .into_iter()
+                .map(|side| color_name(side).to_string())
+                .collect(),
+            turn: color_name(game.state().side).to_string(),
+            moves: game.moves().iter().map(|mv| mv.to_string()).collect(),
+            clock: game.clock_reading().map(ResponseClock::from),
+            draw_offer: game.draw_offer().map(|side| color_name(side).to_string()),
+            result: ending.map(|e| e.result().to_string()),
+            reason: ending.map(|e| e.reason.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// the board after `ply` moves of a game, the current one by default
+pub struct RequestPosition {
+    ply: Option<usize>,
+}
+
+impl RequestPosition {
+    pub fn ply(&self) -> Option<usize> {
+        self.ply
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a move of a game in SAN and coordinate notation, the position and clock after it and when
+// it was played in unix milliseconds
+pub struct ResponseHistoryMove {
+    ply: usize,
+    san: String,
+    uci: String,
+    fen: String,
+    clock: Option<ResponseClock>,
+    at: u64,
+}
+
+#[derive(Serialize, Debug)]
+// a game's moves from its starting position, with the result once it is over
+pub struct ResponseHistory {
+    id: u64,
+    start: String,
+    moves: Vec<ResponseHistoryMove>,
+    result: Option<String>,
+    reason: Option<String>,
+}
+
+impl From<&Game> for ResponseHistory {
+    fn from(game: &Game) -> Self {
+        let moves = game
+            .history()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Move {
+                    ply,
+                    mv,
+                    san,
+                    fen,
+                    clock,
+                    at,
+                } => Some(ResponseHistoryMove {
+                    ply: *ply,
+                    san: san.clone(),
+                    uci: mv.to_string(),
+                    fen: fen.clone(),
+                    clock: clock.map(ResponseClock::from),
+                    at: *at,
+                }),
+                _ => None,
+            })
+            .collect();
+        let ending = game.ending();
+        ResponseHistory {
+            id: game.id(),
+            start: game.start().to_fen(),
+            moves,
+            result: ending.map(|e| e.result().to_string()),
+            reason: ending.map(|e| e.reason.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+// what the game socket sends: the game and its board when connecting, then every move
+// with the board after it, draw offers and their answers, chat messages, the end of the
+// game and errors about the client's own messages
+pub enum ResponseEvent {
+    Game(ResponseGame),
+    Board {
+        board: Board,
+        clock: Option<ResponseClock>,
+    },
+    Move {
+        ply: usize,
+        from: String,
+        to: String,
+        uci: String,
+        san: String,
+        fen: String,
+        clock: Option<ResponseClock>,
+        at: u64,
+    },
+    End {
+        result: String,
+        reason: String,
+        clock: Option<ResponseClock>,
+    },
+    Join {
+        color: String,
+    },
+    Offer {
+        color: String,
+    },
+    Decline {
+        color: String,
+    },
+    Chat(ResponseChatMessage),
+    Error {
+        message: String,
+    },
+}
+
+impl ResponseEvent {
+    pub fn board(state: &chess::State, clock: Option<Reading>) -> Self {
+        ResponseEvent::Board {
+            board: Board::from(*state.board),
+            clock: clock.map(ResponseClock::from),
+        }
+    }
+
+    // the name of the event's type, as in its `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ResponseEvent::Game(_) => "game",
+            ResponseEvent::Board { .. } => "board",
+            ResponseEvent::Move { .. } => "move",
+            ResponseEvent::End { .. } => "end",
+            ResponseEvent::Join { .. } => "join",
+            ResponseEvent::Offer { .. } => "offer",
+            ResponseEvent::Decline { .. } => "decline",
+            ResponseEvent::Chat(_) => "chat",
+            ResponseEvent::Error { .. } => "error",
+        }
+    }
+}
+
+impl From<&Event> for ResponseEvent {
+    fn from(event: &Event) -> Self {
+        match event {
+            Event::Move {
+                ply,
+                mv,
+                san,
+                fen,
+                clock,
+                at,
+            } => ResponseEvent::Move {
+                ply: *ply,
+                from: chess::square_name(mv.from),
+                to: chess::square_name(mv.to),
+                uci: mv.to_string(),
+                san: san.clone(),
+                fen: fen.clone(),
+                clock: clock.map(ResponseClock::from),
+                at: *at,
+            },
+            Event::End { ending, clock } => ResponseEvent::End {
+                result: ending.result().to_string(),
+                reason: ending.reason.clone(),
+                clock: clock.map(ResponseClock::from),
+            },
+            Event::Join { side } => ResponseEvent::Join {
+                color: color_name(*side).to_string(),
+            },
+            Event::Offer { side } => ResponseEvent::Offer {
+                color: color_name(*side).to_string(),
+            },
+            Event::Decline { side } => ResponseEvent::Decline {
+                color: color_name(*side).to_string(),
+            },
+            Event::Chat(message) => ResponseEvent::Chat(ResponseChatMessage::from(message)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a chat message with the color of a player's
+pub struct ResponseChatMessage {
+    room: String,
+    author: String,
+    color: Option<String>,
+    text: String,
+    at: u64,
+}
+
+impl From<&Message> for ResponseChatMessage {
+    fn from(message: &Message) -> Self {
+        ResponseChatMessage {
+            room: message.room.name().to_string(),
+            author: message.author.clone(),
+            color: message.side.map(|side| color_name(side).to_string()),
+            text: message.text.clone(),
+            at: message.at,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequestChat {
+    text: String,
+}
+
+impl RequestChat {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// the chat room to read, the players' by default
+pub struct RequestChatRoom {
+    room: Option<String>,
+}
+
+impl RequestChatRoom {
+    // none for a room that is not one
+    pub fn room(&self) -> Option<Room> {
+        match self.room.as_deref() {
+            None => Some(Room::Players),
+            Some(name) => Room::parse(name),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+// what clients send over the game socket: moves as for `POST /games/{id}/move`, allowed when
+// the socket was opened with the token of the side to move, and resigning, aborting and draw
+// offers, answers and claims ("offer", "accept", "decline" or "claim") of either seat. chat
+// messages go to the players' room from a seat and to the spectators' from a session
+pub enum RequestSocket {
+    Move(RequestMove),
+    Resign,
+    Abort,
+    Draw { action: String },
+    Chat { text: String },
+}
+
+// the draw action named as in `/games/{id}/draw/{action}`
+pub fn draw_action(name: &str) -> Option<Action> {
+    match name {
+        "offer" => Some(Action::OfferDraw),
+        "accept" => Some(Action::AcceptDraw),
+        "decline" => Some(Action::DeclineDraw),
+        "claim" => Some(Action::ClaimDraw),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// registers a user or logs one in. names are up to 32 letters, digits, '-' and '_', passwords
+// have at least 8 characters
+pub struct RequestUser {
+    name: String,
+    password: String,
+}
+
+impl RequestUser {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a logged in user and the session token sent as `Authorization: Bearer <token>`
+pub struct ResponseSession {
+    name: String,
+    token: String,
+}
+
+impl ResponseSession {
+    pub fn new(name: &str, token: String) -> Self {
+        ResponseSession {
+            name: name.to_string(),
+            token,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a Glicko-2 rating, deviation and volatility
+pub struct ResponseRating {
+    rating: f64,
+    deviation: f64,
+    volatility: f64,
+}
+
+impl From<Rating> for ResponseRating {
+    fn from(rating: Rating) -> Self {
+        // a tenth of a point is as exact as ratings get
+        let round = |x: f64| (x * 10.0).round() / 10.0;
+        ResponseRating {
+            rating: round(rating.rating),
+            deviation: round(rating.deviation),
+            volatility: rating.volatility,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// the rating after a rated game that ended `at`, in unix milliseconds
+pub struct ResponseRatingChange {
+    game: u64,
+    #[serde(flatten)]
+    rating: ResponseRating,
+    at: u64,
+}
+
+#[derive(Serialize, Debug)]
+// a finished game with the registered users who played it, ended `at` as above
+pub struct ResponsePlayed {
+    id: u64,
+    white: Option<String>,
+    black: Option<String>,
+    result: String,
+    reason: String,
+    rated: bool,
+    at: u64,
+}
+
+#[derive(Serialize, Debug)]
+// a user with their current rating, its history and the most recent games, newest first
+pub struct ResponseUser {
+    name: String,
+    #[serde(flatten)]
+    rating: ResponseRating,
+    history: Vec<ResponseRatingChange>,
+    games: Vec<ResponsePlayed>,
+}
+
+const RECENT_GAMES: usize = 20;
+
+impl From<&User> for ResponseUser {
+    fn from(user: &User) -> Self {
+        ResponseUser {
+            name: user.name.clone(),
+            rating: ResponseRating::from(user.rating),
+            history: user
+                .history
+                .iter()
+                .map(|change| ResponseRatingChange {
+                    game: change.game,
+                    rating: ResponseRating::from(change.rating),
+                    at: change.at,
+                })
+                .collect(),
+            games: user
+                .games
+                .iter()
+                .rev()
+                .take(RECENT_GAMES)
+                .map(|played| ResponsePlayed {
+                    id: played.game,
+                    white: played.white.clone(),
+                    black: played.black.clone(),
+                    result: played.result.clone(),
+                    reason: played.reason.clone(),
+                    rated: played.rated,
+                    at: played.at,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// a seek for a game in the lobby, or a challenge to the user `to`. the clock is given as for
+// `RequestNewGame`, `color` is the one wanted (either by default) and opponents are only
+// paired within `min_rating` and `max_rating`
+pub struct RequestSeek {
+    time: Option<f64>,
+    increment: Option<f64>,
+    delay: Option<f64>,
+    moves: Option<u32>,
+    color: Option<String>,
+    rated: Option<bool>,
+    min_rating: Option<f64>,
+    max_rating: Option<f64>,
+    to: Option<String>,
+}
+
+impl RequestSeek {
+    // none for a clock or color that is not one
+    pub fn terms(&self) -> Option<Terms> {
+        let time_control = time_control(self.time, self.increment, self.delay, self.moves);
+        if time_control.is_some() != self.time.is_some() {
+            return None;
+        }
+        Some(Terms {
+            time_control,
+            color: parse_color(self.color.as_deref()).ok()?,
+            rated: self.rated.unwrap_or(false),
+            min_rating: self.min_rating,
+            max_rating: self.max_rating,
+            to: self.to.clone(),
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a time control in seconds, as it is asked for
+pub struct ResponseTimeControl {
+    time: f64,
+    increment: f64,
+    delay: f64,
+    moves: Option<u32>,
+}
+
+impl From<&TimeControl> for ResponseTimeControl {
+    fn from(control: &TimeControl) -> Self {
+        ResponseTimeControl {
+            time: control.base.as_secs_f64(),
+            increment: control.increment.as_secs_f64(),
+            delay: control.delay.as_secs_f64(),
+            moves: control.moves,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a seek or challenge in the lobby. once it started a game its owner sees the game with
+// their seat
+pub struct ResponseSeek {
+    id: u64,
+    user: String,
+    rating: f64,
+    clock: Option<ResponseTimeControl>,
+    color: Option<String>,
+    rated: bool,
+    min_rating: Option<f64>,
+    max_rating: Option<f64>,
+    to: Option<String>,
+    game: Option<u64>,
+    seat: Option<ResponseSeat>,
+}
+
+impl ResponseSeek {
+    // the seek as `viewer` sees it
+    pub fn new(seek: &Seek, viewer: Option<&str>) -> Self {
+        let owner = viewer == Some(seek.user.as_str());
+        ResponseSeek {
+            id: seek.id,
+            user: seek.user.clone(),
+            rating: seek.rating.round(),
+            clock: seek
+                .terms
+                .time_control
+                .as_ref()
+                .map(ResponseTimeControl::from),
+            color: seek.terms.color.map(|side| color_name(side).to_string()),
+            rated: seek.terms.rated,
+            min_rating: seek.terms.min_rating,
+            max_rating: seek.terms.max_rating,
+            to: seek.terms.to.clone(),
+            game: seek.started.as_ref().map(|started| started.game),
+            seat: seek
+                .started
+                .as_ref()
+                .filter(|_| owner)
+                .map(|started| ResponseSeat::new(started.color, started.token.clone())),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+// what the lobby socket sends: the open seeks when connecting, then new seeks, seeks that
+// went away and the games the user was seated in with their seat's token
+pub enum ResponseLobbyEvent {
+    Seeks {
+        seeks: Vec<ResponseSeek>,
+    },
+    Seek(ResponseSeek),
+    Remove {
+        id: u64,
+    },
+    Start {
+        seek: Option<u64>,
+        game: u64,
+        color: String,
+        token: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ResponseLobbyEvent {
+    // the event as `viewer` sees it, none for events about others they do not see
+    pub fn new(event: &LobbyEvent, viewer: Option<&str>) -> Option<Self> {
+        match event {
+            LobbyEvent::Seek(seek) => match &seek.terms.to {
+                Some(to) if viewer != Some(to.as_str()) && viewer != Some(seek.user.as_str()) => {
+                    None
+                }
+                _ => Some(ResponseLobbyEvent::Seek(ResponseSeek::new(seek, viewer))),
+            },
+            LobbyEvent::Remove { id } => Some(ResponseLobbyEvent::Remove { id: *id }),
+            LobbyEvent::Start {
+                user,
+                seek,
+                game,
+                color,
+                token,
+            } => (viewer == Some(user.as_str())).then(|| ResponseLobbyEvent::Start {
+                seek: *seek,
+                game: *game,
+                color: color_name(*color).to_string(),
+                token: token.clone(),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+// what clients send over the lobby socket: seeks as for `POST /lobby/seeks`, taking up a seek
+// and withdrawing one's own seek or declining a challenge
+pub enum RequestLobbySocket {
+    Seek(RequestSeek),
+    Accept { id: u64 },
+    Cancel { id: u64 },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// a new tournament: a "round-robin" by default or a "swiss" of `rounds` rounds, with a clock
+// and engine limits as for a game
+pub struct RequestTournament {
+    name: String,
+    format: Option<String>,
+    rounds: Option<usize>,
+    time: Option<f64>,
+    increment: Option<f64>,
+    delay: Option<f64>,
+    moves: Option<u32>,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<u64>,
+    rated: Option<bool>,
+}
+
+impl RequestTournament {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // none for an unknown format or a Swiss without rounds
+    pub fn format(&self) -> Option<Format> {
+        match self.format.as_deref() {
+            None | Some("round-robin") => Some(Format::RoundRobin),
+            Some("swiss") => Some(Format::Swiss(self.rounds?)),
+            Some(_) => None,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        engine_limits(self.depth, self.nodes, self.movetime)
+    }
+
+    // none for untimed games or times that are not a number of seconds
+    pub fn time_control(&self) -> Option<TimeControl> {
+        time_control(self.time, self.increment, self.delay, self.moves)
+    }
+
+    // true when a clock was asked for, valid or not
+    pub fn timed(&self) -> bool {
+        self.time.is_some()
+    }
+
+    pub fn rated(&self) -> bool {
+        self.rated.unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// the server's engine entering a tournament under `name` at a `level` or `elo`
+pub struct RequestEngineEntrant {
+    name: String,
+    level: Option<u32>,
+    elo: Option<u32>,
+}
+
+impl RequestEngineEntrant {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn skill(&self) -> Skill {
+        skill(self.level, self.elo)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseEntrant {
+    name: String,
+    engine: bool,
+    level: Option<u32>,
+    rating: f64,
+}
+
+#[derive(Serialize, Debug)]
+// a board of a round, the users playing it see their seat
+pub struct ResponsePairing {
+    board: usize,
+    white: String,
+    black: Option<String>,
+    game: Option<u64>,
+    // "1-0", "0-1", "1/2-1/2" or "bye"
+    result: Option<String>,
+    seat: Option<ResponseSeat>,
+}
+
+#[derive(Serialize, Debug)]
+// a tournament with its entrants by seed once it started and the boards of every round
+pub struct ResponseTournament {
+    id: u64,
+    name: String,
+    owner: String,
+    format: String,
+    rounds: usize,
+    clock: Option<ResponseTimeControl>,
+    rated: bool,
+    // "open" for entries, "playing" or "finished"
+    status: String,
+    entrants: Vec<ResponseEntrant>,
+    pairings: Vec<Vec<ResponsePairing>>,
+}
+
+impl ResponseTournament {
+    // the tournament as `viewer` sees it
+    pub fn new(tournament: &Tournament, viewer: Option<&str>) -> Self {
+        let name = |entrant: usize| tournament.entrants[entrant].name.clone();
+        let pairings = tournament
+            .rounds
+            .iter()
+            .map(|round| {
+                round
+                    .iter()
+                    .enumerate()
+                    .map(|(board, pairing)| {
+                        let seats = [
+                            (chess::Kind::White, Some(pairing.white)),
+                            (chess::Kind::Black, pairing.black),
+                        ];
+                        let seat = seats.into_iter().zip(&pairing.tokens).find_map(
+                            |((side, entrant), token)| {
+                                let playing = entrant.is_some_and(|entrant| {
+                                    viewer == Some(tournament.entrants[entrant].name.as_str())
+                                });
+                                let token = token.clone().filter(|_| playing)?;
+                                Some(ResponseSeat::new(side, token))
+                            },
+                        );
+                        ResponsePairing {
+                            board: board + 1,
+                            white: name(pairing.white),
+                            black: pairing.black.map(name),
+                            game: pairing.game,
+                            result: match (pairing.black, pairing.result) {
+                                (None, _) => Some("bye".to_string()),
+                                (Some(_), Some(result)) => Some(score_name(result).to_string()),
+                                (Some(_), None) => None,
+                            },
+                            seat,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        ResponseTournament {
+            id: tournament.id,
+            name: tournament.name.clone(),
+            owner: tournament.owner.clone(),
+            format: tournament.format.name().to_string(),
+            rounds: tournament.total_rounds(),
+            clock: tournament
+                .time_control
+                .as_ref()
+                .map(ResponseTimeControl::from),
+            rated: tournament.rated,
+            status: if tournament.finished() {
+                "finished"
+            } else if tournament.started() {
+                "playing"
+            } else {
+                "open"
+            }
+            .to_string(),
+            entrants: tournament
+                .entrants
+                .iter()
+                .map(|entrant| ResponseEntrant {
+                    name: entrant.name.clone(),
+                    engine: entrant.level.is_some(),
+                    level: entrant.level,
+                    rating: entrant.rating.round(),
+                })
+                .collect(),
+            pairings,
+        }
+    }
+}
+
+// a result from white's score
+fn score_name(score: f64) -> &'static str {
+    if score >= 1.0 {
+        "1-0"
+    } else if score <= 0.0 {
+        "0-1"
+    } else {
+        "1/2-1/2"
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseStanding {
+    rank: usize,
+    name: String,
+    points: f64,
+    buchholz: f64,
+    sonneborn_berger: f64,
+}
+
+// the standings of a tournament, best first
+pub fn standings(tournament: &Tournament) -> Vec<ResponseStanding> {
+    tournament
+        .standings()
+        .into_iter()
+        .enumerate()
+        .map(|(i, standing)| ResponseStanding {
+            rank: i + 1,
+            name: tournament.entrants[standing.entrant].name.clone(),
+            points: standing.points,
+            buchholz: standing.buchholz,
+            sonneborn_berger: standing.sonneborn_berger,
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+// an entrant's game of a round: the opponent by rank, none for a bye, the color and the score
+pub struct ResponseCrosstableGame {
+    opponent: Option<usize>,
+    color: Option<String>,
+    score: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseCrosstableRow {
+    #[serde(flatten)]
+    standing: ResponseStanding,
+    // none for the rounds the entrant was not paired in
+    games: Vec<Option<ResponseCrosstableGame>>,
+}
+
+#[derive(Serialize, Debug)]
+// the entrants in the order of the standings with their games round by round
+pub struct ResponseCrosstable {
+    rounds: usize,
+    rows: Vec<ResponseCrosstableRow>,
+}
+
+impl From<&Tournament> for ResponseCrosstable {
+    fn from(tournament: &Tournament) -> Self {
+        let order: Vec<usize> = tournament
+            .standings()
+            .iter()
+            .map(|standing| standing.entrant)
+            .collect();
+        let mut rank = vec![0; order.len()];
+        for (i, &entrant) in order.iter().enumerate() {
+            rank[entrant] = i + 1;
+        }
+        let rows = standings(tournament)
+            .into_iter()
+            .zip(order)
+            .map(|(standing, entrant)| ResponseCrosstableRow {
+                standing,
+                games: tournament
+                    .encounters(entrant)
+                    .into_iter()
+                    .map(|encounter| {
+                        encounter.map(|encounter| ResponseCrosstableGame {
+                            opponent: encounter.opponent.map(|opponent| rank[opponent]),
+                            color: encounter.color.map(|side| color_name(side).to_string()),
+                            score: encounter.score,
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+        ResponseCrosstable {
+            rounds: tournament.total_rounds(),
+            rows,
+        }
+    }
+}
+
+fn color_name(kind: chess::Kind) -> &'static str {
+    match kind {
+        chess::Kind::White => "white",
+        chess::Kind::Black => "black",
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// analysis of the current board with `color` to move (white by default),
+// the `multipv` best lines searched to `depth`, `eval` as for `RequestEngine`
+pub struct RequestAnalysis {
+    color: Option<String>,
+    depth: Option<u32>,
+    multipv: Option<usize>,
+    threads: Option<usize>,
+    eval: Option<String>,
+}
+
+impl RequestAnalysis {
+    pub fn color(&self) -> Result<chess::Kind, ()> {
+        color_or_white(self.color.as_deref())
+    }
+
+    pub fn multipv(&self) -> usize {
+        self.multipv.unwrap_or(1).clamp(1, MAX_MULTIPV)
+    }
+
+    pub fn threads(&self) -> usize {
+        threads(self.threads)
+    }
+
+    pub fn classical(&self) -> bool {
+        self.eval.as_deref() == Some("classical")
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits::depth(
+            self.depth
+                .unwrap_or(DEFAULT_ANALYSIS_DEPTH)
+                .clamp(1, MAX_ANALYSIS_DEPTH),
+        )
+    }
+}
+
+const DEFAULT_ANALYSIS_DEPTH: u32 = 8;
+// deeper searches would tie up the server for minutes
+const MAX_ANALYSIS_DEPTH: u32 = 20;
+const MAX_MULTIPV: usize = 16;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+// centipawns from the point of view of the side to move, or moves to mate (negative when mated)
+pub enum Score {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl From<i32> for Score {
+    fn from(score: i32) -> Self {
+        match mate_in(score) {
+            Some(moves) => Score::Mate(moves),
+            None => Score::Cp(score),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// principal variation in standard algebraic notation and coordinate notation
+pub struct AnalysisLine {
+    score: Score,
+    depth: u32,
+    san: Vec<String>,
+    pv: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseAnalysis {
+    fen: String,
+    depth: u32,
+    nodes: u64,
+    // milliseconds
+    time: u64,
+    lines: Vec<AnalysisLine>,
+}
+
+impl ResponseAnalysis {
+    pub fn new(state: &chess::State, result: &SearchResult) -> Self {
+        let lines = result
+            .lines
+            .iter()
+            .map(|line| {
+                let mut state = state.clone();
+                let san = line
+                    .pv
+                    .iter()
+                    .map(|mv| {
+                        let san = state.san(mv);
+                        state.make_move(*mv);
+                        san
+                    })
+                    .collect();
+                AnalysisLine {
+                    score: Score::from(line.score),
+                    depth: result.depth,
+                    san,
+                    pv: line.pv.iter().map(|mv| mv.to_string()).collect(),
+                }
+            })
+            .collect();
+        ResponseAnalysis {
+            fen: state.to_fen(),
+            depth: result.depth,
+            nodes: result.nodes,
+            time: result.time.as_millis() as u64,
+            lines,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+// tablebase lookup of the current board with `color` to move (white by default)
+pub struct RequestTablebase {
+    color: Option<String>,
+}
+
+impl RequestTablebase {
+    pub fn color(&self) -> Result<chess::Kind, ()> {
+        color_or_white(self.color.as_deref())
+    }
+}
+
+#[derive(Serialize, Debug)]
+// win, draw or loss for the side to move and plies to the next capture or pawn move,
+// dtz is missing when only the WDL tables are available
+pub struct ResponseTablebase {
+    fen: String,
+    wdl: String,
+    dtz: Option<i32>,
+}
+
+impl ResponseTablebase {
+    pub fn new(state: &chess::State, wdl: Wdl, dtz: Option<i32>) -> Self {
+        ResponseTablebase {
+            fen: state.to_fen(),
+            wdl: wdl.name().to_string(),
+            dtz,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+// a1, h8, etc, and the piece a pawn became ("q", "r", "b" or "n")
+pub struct ResponseMove {
+    from: String,
+    to: String,
+    promotion: Option<String>,
+}
+
+impl From<chess::Move> for ResponseMove {
+    fn from(mv: chess::Move) -> Self {
+        ResponseMove {
+            from: chess::square_name(mv.from),
+            to: chess::square_name(mv.to),
+            promotion: mv.promotion.map(|_| mv.to_string()[4..].to_string()),
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -32,8 +1331,19 @@ pub struct Board {
     board: HashMap<String, BoardItem>,
 }
 
+use crate::chat::{Message, Room};
 use crate::chess;
 use crate::chess::Pair;
+use crate::clock::{Reading, TimeControl};
+use crate::games::{Action, Event, Game, Player};
+use crate::lobby::{LobbyEvent, Seek, Terms};
+use crate::rating::Rating;
+use crate::search::{mate_in, SearchResult};
+use crate::skill::Skill;
+use crate::tablebase::Wdl;
+use crate::timeman::Limits;
+use crate::tournament::{Format, Tournament};
+use crate::users::User;
 
 // TODO: chess::Board() is a wrapper but as its inside mutex, dereferencing mutexguard causes it to be dereferenced and type is missing after that when invoking this trait
 impl From<[[Option<Pair>; 8]; 8]> for Board {
@@ -0,0 +1,871 @@
+use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+
+use crate::chess::{Kind, Move, State};
+use crate::external::{EngineSpec, ExternalEngine};
+use crate::nnue::Network;
+use crate::search::{SearchConfig, Searcher};
+use crate::timeman::Limits;
+use crate::uci;
+
+// engine-vs-engine matches to measure whether a change helps.
+//
+//   chess match --engine <spec> --engine <spec> [options]
+//
+// an engine spec is a comma separated list of key=value pairs: `name` names the engine,
+// `cmd` runs an external UCI engine (this engine plays in-process without it) and every
+// other key is a UCI option, e.g. `--engine name=base --engine name=nolmr,LMR=false` or
+// `--engine cmd=/usr/bin/stockfish,Threads=1`. options:
+//
+//   --games N              games to play, two per opening with colors swapped (default 100)
+//   --tc BASE+INC          clock in seconds (default 10+0.1)
+//   --depth N, --nodes N, --movetime MS
+//                          fixed limit per move instead of a clock
+//   --openings FILE        a FEN or a line of moves from the start position per line
+//   --pgn FILE             appends every finished game
+//   --sprt ELO0,ELO1       stops once the test accepts one of the hypotheses
+//   --alpha A, --beta B    error rates of the test (default 0.05 each)
+//   --resign MOVES,CP      adjudicates a win once both engines agree for that many moves
+//                          (default 4,1000, 0 moves switches it off)
+//   --draw AFTER,MOVES,CP  adjudicates a draw from move AFTER on while both engines see
+//                          the game level for that many moves (default 40,8,10)
+
+const DEFAULT_GAMES: usize = 100;
+// games running into this many plies are drawn
+const MAX_PLIES: usize = 600;
+// external engines get this much slack past their clock before they are given up on
+const TIMEOUT_SLACK: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq)]
+enum TimeControl {
+    Clock { base: Duration, increment: Duration },
+    Depth(u32),
+    Nodes(u64),
+    Movetime(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Adjudication {
+    resign_moves: usize,
+    resign_score: i32,
+    draw_after: usize,
+    draw_moves: usize,
+    draw_score: i32,
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Adjudication {
+            resign_moves: 4,
+            resign_score: 1000,
+            draw_after: 40,
+            draw_moves: 8,
+            draw_score: 10,
+        }
+    }
+}
+
+struct Settings {
+    games: usize,
+    time_control: TimeControl,
+    // start position and moves of each opening
+    openings: Vec<(String, Vec<String>)>,
+    pgn: Option<String>,
+    sprt: Option<Sprt>,
+    adjudication: Adjudication,
+}
+
+fn parse_settings(args: &[String]) -> Result<(Vec<EngineSpec>, Settings), anyhow::Error> {
+    let mut engines = Vec::new();
+    let mut settings = Settings {
+        games: DEFAULT_GAMES,
+        time_control: TimeControl::Clock {
+            base: Duration::from_secs(10),
+            increment: Duration::from_millis(100),
+        },
+        openings: Vec::new(),
+        pgn: None,
+        sprt: None,
+        adjudication: Adjudication::default(),
+    };
+    let (mut elo0, mut elo1, mut alpha, mut beta) = (None, None, 0.05, 0.05);
+    let numbers = |value: &str| -> Result<Vec<f64>, anyhow::Error> {
+        value
+            .split(',')
+            .map(|n| {
+                n.parse::<f64>()
+                    .map_err(|_| anyhow!("Invalid number {}", n))
+            })
+            .collect()
+    };
+
+    for option in args.chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (name.as_str(), value.as_str()),
+            _ => return Err(anyhow!("Missing value for {}", option[0])),
+        };
+        match name {
+            "--engine" => engines.push(EngineSpec::parse(value)?),
+            "--games" => settings.games = value.parse()?,
+            "--tc" => {
+                let (base, increment) = value.split_once('+').unwrap_or((value, "0"));
+                settings.time_control = TimeControl::Clock {
+                    base: Duration::try_from_secs_f64(base.parse()?)?,
+                    increment: Duration::try_from_secs_f64(increment.parse()?)?,
+                };
+            }
+            "--depth" => settings.time_control = TimeControl::Depth(value.parse()?),
+            "--nodes" => settings.time_control = TimeControl::Nodes(value.parse()?),
+            "--movetime" => {
+                settings.time_control = TimeControl::Movetime(Duration::from_millis(value.parse()?))
+            }
+            "--openings" => settings.openings = parse_openings(&fs::read_to_string(value)?)?,
+            "--pgn" => settings.pgn = Some(value.to_string()),
+            "--sprt" => match numbers(value)?[..] {
+                [e0, e1] => (elo0, elo1) = (Some(e0), Some(e1)),
+                _ => return Err(anyhow!("--sprt expects ELO0,ELO1")),
+            },
+            "--alpha" => alpha = value.parse()?,
+            "--beta" => beta = value.parse()?,
+            "--resign" => match numbers(value)?[..] {
+                [moves, score] => {
+                    settings.adjudication.resign_moves = moves as usize;
+                    settings.adjudication.resign_score = score as i32;
+                }
+                _ => return Err(anyhow!("--resign expects MOVES,CP")),
+            },
+            "--draw" => match numbers(value)?[..] {
+                [after, moves, score] => {
+                    settings.adjudication.draw_after = after as usize;
+                    settings.adjudication.draw_moves = moves as usize;
+                    settings.adjudication.draw_score = score as i32;
+                }
+                _ => return Err(anyhow!("--draw expects AFTER,MOVES,CP")),
+            },
+            _ => return Err(anyhow!("Unknown option {}", name)),
+        }
+    }
+
+    if engines.len() != 2 {
+        return Err(anyhow!("A match needs exactly two --engine specs"));
+    }
+    if let (Some(elo0), Some(elo1)) = (elo0, elo1) {
+        settings.sprt = Some(Sprt {
+            elo0,
+            elo1,
+            alpha,
+            beta,
+        });
+    }
+    if settings.openings.is_empty() {
+        settings
+            .openings
+            .push((State::default().to_fen(), Vec::new()));
+    }
+    Ok((engines, settings))
+}
+
+// one opening per line, blank lines and lines starting with `#` are skipped
+fn parse_openings(text: &str) -> Result<Vec<(String, Vec<String>)>, anyhow::Error> {
+    let mut openings = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let opening = if tokens[0].contains('/') {
+            // EPD lines carry operations after the four position fields
+            let counters =
+                tokens.len() >= 6 && tokens[4..6].iter().all(|t| t.parse::<u32>().is_ok());
+            let fen = tokens[..if counters { 6 } else { 4.min(tokens.len()) }].join(" ");
+            (State::from_fen(&fen)?.to_fen(), Vec::new())
+        } else {
+            (
+                State::default().to_fen(),
+                tokens.iter().map(|t| t.to_string()).collect(),
+            )
+        };
+        let mut state = State::from_fen(&opening.0)?;
+        for s in &opening.1 {
+            let mv = state
+                .parse_move(s)
+                .ok_or_else(|| anyhow!("Illegal opening move {} in {}", s, line))?;
+            state.make_move(mv);
+        }
+        openings.push(opening);
+    }
+    Ok(openings)
+}
+
+// either side of a match
+enum Player {
+    Internal {
+        name: String,
+        searcher: Box<Searcher>,
+    },
+    External {
+        name: Option<String>,
+        engine: ExternalEngine,
+    },
+}
+
+// what a player decided: the move as sent and its score from the mover's point of view
+struct Decision {
+    best_move: String,
+    score: Option<i32>,
+}
+
+impl Player {
+    fn start(spec: &EngineSpec, index: usize) -> Result<Player, anyhow::Error> {
+        match &spec.command {
+            Some(_) => Ok(Player::External {
+                name: spec.name.clone(),
+                engine: ExternalEngine::from_spec(spec)?,
+            }),
+            None => {
+                let mut searcher = Searcher::new(State::default(), SearchConfig::default());
+                for (name, value) in &spec.options {
+                    match name.to_lowercase().as_str() {
+                        "evalfile" => searcher.set_network(Some(Arc::new(Network::load(value)?))),
+                        name => uci::apply_option(&mut searcher, name, value)?,
+                    }
+                }
+                let name = spec
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("engine{}", index + 1));
+                Ok(Player::Internal {
+                    name,
+                    searcher: Box::new(searcher),
+                })
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Player::Internal { name, .. } => name,
+            // the name the engine gives itself unless the spec has one
+            Player::External { name, engine } => name.as_deref().unwrap_or(engine.name()),
+        }
+    }
+
+    fn new_game(&mut self) -> Result<(), anyhow::Error> {
+        match self {
+            Player::Internal { searcher, .. } => {
+                searcher.new_game();
+                Ok(())
+            }
+            Player::External { engine, .. } => engine.new_game(),
+        }
+    }
+
+    // `clocks` are the time left for white and black
+    fn think(
+        &mut self,
+        start_fen: &str,
+        moves: &[String],
+        state: &State,
+        time_control: &TimeControl,
+        clocks: [Duration; 2],
+    ) -> Result<Decision, anyhow::Error> {
+        match self {
+            Player::Internal { searcher, .. } => {
+                let limits = match time_control {
+                    TimeControl::Clock { increment, .. } => Limits {
+                        time: Some(clocks[state.side.index()]),
+                        increment: *increment,
+                        ..Limits::default()
+                    },
+                    TimeControl::Depth(depth) => Limits::depth(*depth),
+                    TimeControl::Nodes(nodes) => Limits {
+                        nodes: Some(*nodes),
+                        ..Limits::default()
+                    },
+                    TimeControl::Movetime(movetime) => Limits {
+                        movetime: Some(*movetime),
+                        ..Limits::default()
+                    },
+                };
+                searcher.set_state(state.clone());
+                let result = searcher.search(&limits);
+                let best_move = result
+                    .best_move
+                    .ok_or_else(|| anyhow!("No move from the engine"))?;
+                Ok(Decision {
+                    best_move: best_move.to_string(),
+                    score: Some(result.score),
+                })
+            }
+            Player::External { engine, .. } => {
+                let position = if moves.is_empty() {
+                    format!("fen {}", start_fen)
+                } else {
+                    format!("fen {} moves {}", start_fen, moves.join(" "))
+                };
+                let (go, timeout) = match time_control {
+                    TimeControl::Clock { increment, .. } => (
+                        format!(
+                            "wtime {} btime {} winc {} binc {}",
+                            clocks[0].as_millis(),
+                            clocks[1].as_millis(),
+                            increment.as_millis(),
+                            increment.as_millis()
+                        ),
+                        Some(clocks[state.side.index()] + TIMEOUT_SLACK),
+                    ),
+                    TimeControl::Depth(depth) => (format!("depth {}", depth), None),
+                    TimeControl::Nodes(nodes) => (format!("nodes {}", nodes), None),
+                    TimeControl::Movetime(movetime) => (
+                        format!("movetime {}", movetime.as_millis()),
+                        Some(*movetime + TIMEOUT_SLACK),
+                    ),
+                };
+                let reply = engine.go(&position, &go, timeout)?;
+                Ok(Decision {
+                    best_move: reply.best_move,
+                    score: reply.score,
+                })
+            }
+        }
+    }
+}
+
+struct Game {
+    white: String,
+    black: String,
+    round: usize,
+    start_fen: String,
+    san: Vec<String>,
+    // the first move's side and number
+    first_side: Kind,
+    first_number: u32,
+    result: &'static str,
+    termination: String,
+}
+
+// counts consecutive plies for resign and draw adjudication
+#[derive(Default)]
+struct Adjudicator {
+    // plies in a row where the engines agree that the side with this sign wins
+    resign_plies: usize,
+    resign_sign: i32,
+    draw_plies: usize,
+}
+
+impl Adjudicator {
+    // `score` is from white's point of view, returns the adjudicated result
+    fn update(
+        &mut self,
+        rules: &Adjudication,
+        score: Option<i32>,
+        fullmove: u32,
+    ) -> Option<(&'static str, &'static str)> {
+        let score = match score {
+            Some(score) => score,
+            None => {
+                *self = Adjudicator::default();
+                return None;
+            }
+        };
+
+        if score.abs() >= rules.resign_score && score.signum() == self.resign_sign {
+            self.resign_plies += 1;
+        } else if score.abs() >= rules.resign_score {
+            self.resign_sign = score.signum();
+            self.resign_plies = 1;
+        } else {
+            self.resign_plies = 0;
+        }
+        if rules.resign_moves > 0 && self.resign_plies >= 2 * rules.resign_moves {
+            let result = if self.resign_sign > 0 { "1-0" } else { "0-1" };
+            return Some((result, "Adjudicated as a win"));
+        }
+
+        if fullmove as usize >= rules.draw_after && score.abs() <= rules.draw_score {
+            self.draw_plies += 1;
+        } else {
+            self.draw_plies = 0;
+        }
+        if rules.draw_moves > 0 && self.draw_plies >= 2 * rules.draw_moves {
+            return Some(("1/2-1/2", "Adjudicated as a draw"));
+        }
+        None
+    }
+}
+
+fn loss_for(side: Kind) -> &'static str {
+    match side {
+        Kind::White => "0-1",
+        Kind::Black => "1-0",
+    }
+}
+
+// plays one game, `players` indexed by color
+fn play_game(
+    players: [&mut Player; 2],
+    opening: &(String, Vec<String>),
+    settings: &Settings,
+    round: usize,
+) -> Game {
+    let [white, black] = players;
+    let mut game = Game {
+        white: white.name().to_string(),
+        black: black.name().to_string(),
+        round,
+        start_fen: opening.0.clone(),
+        san: Vec::new(),
+        first_side: Kind::White,
+        first_number: 1,
+        result: "*",
+        termination: String::new(),
+    };
+    let mut players = [white, black];
+    for player in players.iter_mut() {
+        if let Err(e) = player.new_game() {
+            game.result = "*";
+            game.termination = e.to_string();
+            return game;
+        }
+    }
+
+    let mut state = State::from_fen(&opening.0).expect("Openings are validated");
+    game.first_side = state.side;
+    game.first_number = state.fullmove;
+    let mut moves: Vec<String> = Vec::new();
+    for s in &opening.1 {
+        let mv = state.parse_move(s).expect("Openings are validated");
+        game.san.push(state.san(&mv));
+        moves.push(mv.to_string());
+        state.make_move(mv);
+    }
+
+    let mut clocks = match settings.time_control {
+        TimeControl::Clock { base, .. } => [base; 2],
+        _ => [Duration::ZERO; 2],
+    };
+    let mut adjudicator = Adjudicator::default();
+    loop {
+        if let Some(outcome) = state.outcome().or(state.claimable_draw()) {
+            game.result = outcome.result();
+            game.termination = outcome.reason().to_string();
+            return game;
+        }
+        if moves.len() >= MAX_PLIES {
+            game.result = "1/2-1/2";
+            game.termination = "Move limit".to_string();
+            return game;
+        }
+
+        let side = state.side;
+        let color = if side == Kind::White {
+            "White"
+        } else {
+            "Black"
+        };
+        let started = Instant::now();
+        let decision =
+            players[side.index()].think(&opening.0, &moves, &state, &settings.time_control, clocks);
+        let elapsed = started.elapsed();
+
+        if let TimeControl::Clock { increment, .. } = settings.time_control {
+            let clock = &mut clocks[side.index()];
+            if elapsed > *clock {
+                game.result = loss_for(side);
+                game.termination = format!("{} loses on time", color);
+                return game;
+            }
+            *clock = *clock - elapsed + increment;
+        }
+        let decision = match decision {
+            Ok(decision) => decision,
+            Err(e) => {
+                game.result = loss_for(side);
+                game.termination = format!("{} forfeits: {}", color, e);
+                return game;
+            }
+        };
+        let mv: Move = match state.parse_move(&decision.best_move) {
+            Some(mv) => mv,
+            None => {
+                game.result = loss_for(side);
+                game.termination =
+                    format!("{} plays an illegal move {}", color, decision.best_move);
+                return game;
+            }
+        };
+
+        game.san.push(state.san(&mv));
+        moves.push(mv.to_string());
+        let fullmove = state.fullmove;
+        state.make_move(mv);
+
+        let score = decision.score.map(|score| match side {
+            Kind::White => score,
+            Kind::Black => -score,
+        });
+        if let Some((result, reason)) = adjudicator.update(&settings.adjudication, score, fullmove)
+        {
+            game.result = result;
+            game.termination = reason.to_string();
+            return game;
+        }
+    }
+}
+
+// the game in PGN, movetext wrapped at 80 columns
+fn to_pgn(game: &Game, time_control: &TimeControl) -> String {
+    let mut pgn = String::new();
+    let mut tag = |name: &str, value: &str| {
+        pgn += &format!(
+            "[{} \"{}\"]\n",
+            name,
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+    };
+    tag("Event", "Engine match");
+    tag("Site", "?");
+    tag("Date", &today());
+    tag("Round", &game.round.to_string());
+    tag("White", &game.white);
+    tag("Black", &game.black);
+    tag("Result", game.result);
+    if game.start_fen != State::default().to_fen() {
+        tag("SetUp", "1");
+        tag("FEN", &game.start_fen);
+    }
+    let tc = match time_control {
+        TimeControl::Clock { base, increment } => {
+            format!("{}+{}", base.as_secs_f64(), increment.as_secs_f64())
+        }
+        _ => "-".to_string(),
+    };
+    tag("TimeControl", &tc);
+    tag("PlyCount", &game.san.len().to_string());
+    tag("Termination", &game.termination);
+    pgn.push('\n');
+
+    let mut words = Vec::new();
+    let mut number = game.first_number;
+    let mut side = game.first_side;
+    for (i, san) in game.san.iter().enumerate() {
+        match side {
+            Kind::White => words.push(format!("{}.", number)),
+            Kind::Black if i == 0 => words.push(format!("{}...", number)),
+            Kind::Black => (),
+        }
+        words.push(san.clone());
+        if side == Kind::Black {
+            number += 1;
+        }
+        side = side.opposite();
+    }
+    if !game.termination.is_empty() {
+        words.push(format!("{{{}}}", game.termination));
+    }
+    words.push(game.result.to_string());
+
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > 80 {
+            pgn += &line;
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += &word;
+    }
+    pgn += &line;
+    pgn += "\n\n";
+    pgn
+}
+
+// YYYY.MM.DD in UTC
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    // days since 1970-01-01 to a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+// match score from the first engine's point of view
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tally {
+    wins: u32,
+    losses: u32,
+    draws: u32,
+}
+
+impl Tally {
+    fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // per game variance of the score
+    fn variance(&self) -> f64 {
+        let n = self.games() as f64;
+        let s = self.score();
+        (self.wins as f64 * (1.0 - s).powi(2)
+            + self.losses as f64 * s.powi(2)
+            + self.draws as f64 * (0.5 - s).powi(2))
+            / n
+    }
+
+    // elo difference and the half width of its 95% confidence interval
+    fn elo(&self) -> Option<(f64, f64)> {
+        if self.games() == 0 {
+            return None;
+        }
+        let s = self.score();
+        let deviation = (self.variance() / self.games() as f64).sqrt();
+        let low = score_to_elo(s - 1.96 * deviation);
+        let high = score_to_elo(s + 1.96 * deviation);
+        Some((score_to_elo(s), (high - low) / 2.0))
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// sequential probability ratio test of H0: elo = elo0 against H1: elo = elo1
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Sprt {
+    // log likelihood ratio, in the normal approximation of the score distribution
+    fn llr(&self, tally: &Tally) -> f64 {
+        if tally.games() == 0 {
+            return 0.0;
+        }
+        // results without any spread, such as nothing but wins, count one more win and loss
+        // so the variance is not zero
+        let tally = &if tally.variance() == 0.0 {
+            Tally {
+                wins: tally.wins + 1,
+                losses: tally.losses + 1,
+                ..*tally
+            }
+        } else {
+            *tally
+        };
+        let n = tally.games() as f64;
+        let (s0, s1) = (elo_to_score(self.elo0), elo_to_score(self.elo1));
+        n * (s1 - s0) * (2.0 * tally.score() - s0 - s1) / (2.0 * tally.variance())
+    }
+
+    fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), anyhow::Error> {
+    let (specs, settings) = parse_settings(args)?;
+    let mut first = Player::start(&specs[0], 0)?;
+    let mut second = Player::start(&specs[1], 1)?;
+    let (first_name, second_name) = (first.name().to_string(), second.name().to_string());
+
+    let mut tally = Tally::default();
+    for round in 1..=settings.games {
+        let opening = &settings.openings[(round - 1) / 2 % settings.openings.len()];
+        // the first engine has white in odd rounds
+        let first_white = round % 2 == 1;
+        let game = if first_white {
+            play_game([&mut first, &mut second], opening, &settings, round)
+        } else {
+            play_game([&mut second, &mut first], opening, &settings, round)
+        };
+        println!(
+            "Finished game {} ({} vs {}): {} {{{}}}",
+            round, game.white, game.black, game.result, game.termination
+        );
+
+        match (game.result, first_white) {
+            ("1-0", true) | ("0-1", false) => tally.wins += 1,
+            ("1-0", false) | ("0-1", true) => tally.losses += 1,
+            ("1/2-1/2", _) => tally.draws += 1,
+            _ => {
+                return Err(anyhow!(
+                    "Game {} could not be played: {}",
+                    round,
+                    game.termination
+                ))
+            }
+        }
+        if let Some(path) = &settings.pgn {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(to_pgn(&game, &settings.time_control).as_bytes())?;
+        }
+
+        println!(
+            "Score of {} vs {}: {} - {} - {} [{:.3}] {}",
+            first_name,
+            second_name,
+            tally.wins,
+            tally.losses,
+            tally.draws,
+            tally.score(),
+            tally.games()
+        );
+        if let Some((elo, margin)) = tally.elo() {
+            println!("Elo difference: {:.1} +/- {:.1}", elo, margin);
+        }
+        if let Some(sprt) = &settings.sprt {
+            let llr = sprt.llr(&tally);
+            let (lower, upper) = sprt.bounds();
+            println!("SPRT: llr {:.2} ({:.2}, {:.2})", llr, lower, upper);
+            if llr >= upper {
+                println!("H1 was accepted");
+                break;
+            }
+            if llr <= lower {
+                println!("H0 was accepted");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_play_game() {
+        let (specs, settings) = parse_settings(&[
+            "--engine".to_string(),
+            "name=a".to_string(),
+            "--engine".to_string(),
+            "name=b,NullMove=false".to_string(),
+            "--depth".to_string(),
+            "3".to_string(),
+        ])
+        .unwrap();
+        let mut a = Player::start(&specs[0], 0).unwrap();
+        let mut b = Player::start(&specs[1], 1).unwrap();
+
+        // white mates at once
+        let opening = ("k7/8/1K6/8/8/8/8/7R w - - 0 1".to_string(), Vec::new());
+        let game = play_game([&mut a, &mut b], &opening, &settings, 1);
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.san, vec!["Rh8#"]);
+
+        let pgn = to_pgn(&game, &settings.time_control);
+        assert!(pgn.contains("[White \"a\"]\n[Black \"b\"]\n[Result \"1-0\"]\n"));
+        assert!(pgn.contains("[FEN \"k7/8/1K6/8/8/8/8/7R w - - 0 1\"]"));
+        assert!(pgn.ends_with("1. Rh8# {White mates} 1-0\n\n"));
+
+        let tc = |tc: &str| {
+            let args = ["--engine", "name=a", "--engine", "name=b", "--tc", tc];
+            parse_settings(&args.map(|arg| arg.to_string()))
+        };
+        assert!(tc("10+0.1").is_ok());
+        assert!(tc("-1+0").is_err());
+        assert!(tc("inf").is_err());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let even = Tally {
+            wins: 10,
+            losses: 10,
+            draws: 20,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 0.0);
+
+        let strong = Tally {
+            wins: 300,
+            losses: 100,
+            draws: 100,
+        };
+        // scoring 70% is about 147 elo
+        assert!((strong.elo().unwrap().0 - 147.2).abs() < 0.5);
+        let sprt = Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        assert!(sprt.llr(&strong) > sprt.bounds().1);
+        let weak = Tally {
+            wins: 100,
+            losses: 300,
+            draws: 100,
+        };
+        assert!(sprt.llr(&weak) < sprt.bounds().0);
+        // a lopsided match stops too
+        let lopsided = Tally {
+            wins: 40,
+            ..Tally::default()
+        };
+        assert!(sprt.llr(&lopsided) > sprt.bounds().1);
+        assert_eq!(sprt.llr(&Tally::default()), 0.0);
+    }
+
+    #[test]
+    fn test_adjudication() {
+        let rules = Adjudication::default();
+        let mut adjudicator = Adjudicator::default();
+        for _ in 0..7 {
+            assert_eq!(adjudicator.update(&rules, Some(-1200), 20), None);
+        }
+        assert_eq!(
+            adjudicator.update(&rules, Some(-1500), 21),
+            Some(("0-1", "Adjudicated as a win"))
+        );
+
+        let mut adjudicator = Adjudicator::default();
+        for _ in 0..15 {
+            assert_eq!(adjudicator.update(&rules, Some(3), 45), None);
+        }
+        assert_eq!(
+            adjudicator.update(&rules, Some(0), 46),
+            Some(("1/2-1/2", "Adjudicated as a draw"))
+        );
+        // too early for a draw
+        let mut adjudicator = Adjudicator::default();
+        for _ in 0..20 {
+            assert_eq!(adjudicator.update(&rules, Some(0), 10), None);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::chess::Kind;
+
+// the chat of a game has two rooms: the players', which everyone following the game reads,
+// and the spectators', which the players do not see. every message passes the server's
+// filter first, which may keep it, mask some of it or drop it
+
+// messages longer than this many characters are refused
+pub const MAX_LENGTH: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Room {
+    Players,
+    Spectators,
+}
+
+impl Room {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Room::Players => "players",
+            Room::Spectators => "spectators",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Room> {
+        match name {
+            "players" => Some(Room::Players),
+            "spectators" => Some(Room::Spectators),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub room: Room,
+    // the user's name, or the color of a player without an account
+    pub author: String,
+    // the side of a player
+    pub side: Option<Kind>,
+    pub text: String,
+    // unix time in milliseconds
+    pub at: u64,
+}
+
+// what a filter makes of a message
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Keep,
+    // the message is shown with this text instead
+    Mask(String),
+    Drop,
+}
+
+// decides on every chat message before anyone sees it. a closure taking the message and
+// returning the verdict is a filter as well
+pub trait ChatFilter: Send + Sync {
+    fn check(&self, message: &Message) -> Verdict;
+}
+
+impl<F: Fn(&Message) -> Verdict + Send + Sync> ChatFilter for F {
+    fn check(&self, message: &Message) -> Verdict {
+        self(message)
+    }
+}
+
+// filters applied in turn, each one seeing the text the ones before left. the first to
+// drop a message ends it
+#[derive(Default)]
+pub struct Filters {
+    filters: Vec<Box<dyn ChatFilter>>,
+}
+
+impl Filters {
+    pub fn push(&mut self, filter: impl ChatFilter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+}
+
+impl ChatFilter for Filters {
+    fn check(&self, message: &Message) -> Verdict {
+        let mut masked: Option<String> = None;
+        for filter in &self.filters {
+            let seen = Message {
+                text: masked.clone().unwrap_or_else(|| message.text.clone()),
+                ..message.clone()
+            };
+            match filter.check(&seen) {
+                Verdict::Keep => (),
+                Verdict::Mask(text) => masked = Some(text),
+                Verdict::Drop => return Verdict::Drop,
+            }
+        }
+        masked.map_or(Verdict::Keep, Verdict::Mask)
+    }
+}
+
+// masks some words with asterisks and drops messages with others. words match whole and
+// regardless of case
+#[derive(Clone, Debug, Default)]
+pub struct WordFilter {
+    mask: Vec<String>,
+    drop: Vec<String>,
+}
+
+impl WordFilter {
+    // the words to mask and those to drop messages for, each as a comma separated list
+    pub fn from_lists(mask: &str, drop: &str) -> WordFilter {
+        let words = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect()
+        };
+        WordFilter {
+            mask: words(mask),
+            drop: words(drop),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mask.is_empty() && self.drop.is_empty()
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn check(&self, message: &Message) -> Verdict {
+        let mut text = String::new();
+        let mut masked = false;
+        let mut word = String::new();
+        // a character that is not part of a word ends the one before it
+        for c in message.text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            let lower = word.to_lowercase();
+            if self.drop.contains(&lower) {
+                return Verdict::Drop;
+            }
+            if self.mask.contains(&lower) {
+                text.extend(word.chars().map(|_| '*'));
+                masked = true;
+            } else {
+                text.push_str(&word);
+            }
+            word.clear();
+            text.push(c);
+        }
+        text.pop();
+        if masked {
+            Verdict::Mask(text)
+        } else {
+            Verdict::Keep
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> Message {
+        Message {
+            room: Room::Players,
+            author: "alice".to_string(),
+            side: Some(Kind::White),
+            text: text.to_string(),
+            at: 0,
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        let words = WordFilter::from_lists("darn, Heck", "spam");
+        assert_eq!(words.check(&message("good luck")), Verdict::Keep);
+        assert_eq!(
+            words.check(&message("Darn it, what the heck!")),
+            Verdict::Mask("**** it, what the ****!".to_string())
+        );
+        // whole words only
+        assert_eq!(words.check(&message("darned")), Verdict::Keep);
+        assert_eq!(words.check(&message("buy SPAM now")), Verdict::Drop);
+
+        let mut filters = Filters::default();
+        filters.push(words);
+        filters.push(|message: &Message| {
+            if message.text.contains("****") {
+                Verdict::Mask(message.text.replace("****", "[masked]"))
+            } else {
+                Verdict::Keep
+            }
+        });
+        assert_eq!(
+            filters.check(&message("darn")),
+            Verdict::Mask("[masked]".to_string())
+        );
+        assert_eq!(filters.check(&message("spam darn")), Verdict::Drop);
+        assert_eq!(Filters::default().check(&message("spam")), Verdict::Keep);
+    }
+}
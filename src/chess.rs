@@ -6,7 +6,7 @@ use std::{
 };
 
 // TODO: instead of option make it an enum of Piece
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Piece {
     Rook,
     Knight,
@@ -16,13 +16,43 @@ pub enum Piece {
     Pawn,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Piece {
+    // index used by lookup tables: pawn, knight, bishop, rook, queen, king
+    pub fn index(&self) -> usize {
+        match self {
+            Piece::Pawn => 0,
+            Piece::Knight => 1,
+            Piece::Bishop => 2,
+            Piece::Rook => 3,
+            Piece::Queen => 4,
+            Piece::King => 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Kind {
     White,
     Black,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Kind {
+    pub fn opposite(&self) -> Kind {
+        match self {
+            Kind::White => Kind::Black,
+            Kind::Black => Kind::White,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        match self {
+            Kind::White => 0,
+            Kind::Black => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Pair {
     pub kind: Kind,
     pub piece: Piece,
@@ -80,7 +110,27 @@ impl DerefMut for Board {
     }
 }
 
-type Position = (usize, usize);
+// (row, col) where row 0 is the 8th rank and col 0 is the a file
+pub type Position = (usize, usize);
+
+// converts "e4" into board coordinates
+pub fn parse_square(s: &str) -> Option<Position> {
+    let mut chars = s.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((
+        7 - (rank as usize - '1' as usize),
+        file as usize - 'a' as usize,
+    ))
+}
+
+// converts board coordinates into "e4"
+pub fn square_name(pos: Position) -> String {
+    format!("{}{}", (b'a' + pos.1 as u8) as char, 8 - pos.0)
+}
 
 impl Board {
     pub fn new() -> Board {
@@ -101,7 +151,7 @@ impl Board {
 
     // get all valid moves for a piece
     pub fn all_moves(&self, pos: Position) -> Vec<Position> {
-        let pair = self[pos.0][pos.1].clone().expect("Piece must be present");
+        let pair = self[pos.0][pos.1].expect("Piece must be present");
 
         enum piece_match {
             None,
@@ -599,8 +649,8 @@ impl Board {
                             }
                         }
 
-                        // two squares up
-                        if pos.0 == 6 {
+                        // two squares up, the square in between has to be empty as well
+                        if pos.0 == 6 && self[pos.0 - 1][pos.1].is_none() {
                             match checker((pos.0 - 2, pos.1)) {
                                 piece_match::None => moves.push((pos.0 - 2, pos.1)),
                                 piece_match::Different => (),
@@ -642,8 +692,8 @@ impl Board {
                             }
                         }
 
-                        // two squares down
-                        if pos.0 == 1 {
+                        // two squares down, the square in between has to be empty as well
+                        if pos.0 == 1 && self[pos.0 + 1][pos.1].is_none() {
                             match checker((pos.0 + 2, pos.1)) {
                                 piece_match::None => moves.push((pos.0 + 2, pos.1)),
                                 piece_match::Different => (),
@@ -666,7 +716,7 @@ impl Board {
 
         match self[from.0][from.1] {
             Some(_) => {
-                self[to.0][to.1] = self[from.0][from.1].clone();
+                self[to.0][to.1] = self[from.0][from.1];
                 self[from.0][from.1] = None;
             }
             None => {
@@ -774,6 +824,948 @@ impl Default for Board {
     }
 }
 
+// a single move, promotion is set when a pawn reaches the last rank.
+// castling is encoded as the king moving two squares and en passant as a pawn
+// capturing onto the en passant square
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Position,
+    pub to: Position,
+    pub promotion: Option<Piece>,
+}
+
+impl Move {
+    pub fn new(from: Position, to: Position) -> Move {
+        Move {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    // 16 bit encoding: from square, to square and promotion piece. 0 is never a move
+    pub fn pack(&self) -> u16 {
+        let promotion = match self.promotion {
+            Some(Piece::Knight) => 1,
+            Some(Piece::Bishop) => 2,
+            Some(Piece::Rook) => 3,
+            Some(Piece::Queen) => 4,
+            _ => 0,
+        };
+        ((self.from.0 * 8 + self.from.1) | (self.to.0 * 8 + self.to.1) << 6 | promotion << 12)
+            as u16
+    }
+
+    pub fn unpack(packed: u16) -> Option<Move> {
+        if packed == 0 {
+            return None;
+        }
+        let from = (packed & 63) as usize;
+        let to = (packed >> 6 & 63) as usize;
+        let promotion = match packed >> 12 {
+            1 => Some(Piece::Knight),
+            2 => Some(Piece::Bishop),
+            3 => Some(Piece::Rook),
+            4 => Some(Piece::Queen),
+            _ => None,
+        };
+        Some(Move {
+            from: (from / 8, from % 8),
+            to: (to / 8, to % 8),
+            promotion,
+        })
+    }
+}
+
+// coordinate notation, e.g. "e2e4" or "e7e8q"
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&square_name(self.from))?;
+        f.write_str(&square_name(self.to))?;
+        match self.promotion {
+            Some(Piece::Queen) => f.write_str("q"),
+            Some(Piece::Rook) => f.write_str("r"),
+            Some(Piece::Bishop) => f.write_str("b"),
+            Some(Piece::Knight) => f.write_str("n"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Castling {
+    pub white_king: bool,
+    pub white_queen: bool,
+    pub black_king: bool,
+    pub black_queen: bool,
+}
+
+impl Castling {
+    pub fn all() -> Castling {
+        Castling {
+            white_king: true,
+            white_queen: true,
+            black_king: true,
+            black_queen: true,
+        }
+    }
+
+    pub fn none() -> Castling {
+        Castling {
+            white_king: false,
+            white_queen: false,
+            black_king: false,
+            black_queen: false,
+        }
+    }
+}
+
+// zobrist keys: 12 pieces * 64 squares, side to move, 4 castling rights, 8 en passant files
+const ZOBRIST_SIDE: usize = 768;
+const ZOBRIST_CASTLING: usize = 769;
+const ZOBRIST_EN_PASSANT: usize = 773;
+
+const fn zobrist_keys() -> [u64; 781] {
+    // splitmix64 so keys are the same on every run
+    let mut keys = [0u64; 781];
+    let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < keys.len() {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    keys
+}
+
+static ZOBRIST: [u64; 781] = zobrist_keys();
+
+fn piece_key(pair: Pair, pos: Position) -> u64 {
+    ZOBRIST[(pair.kind.index() * 6 + pair.piece.index()) * 64 + pos.0 * 8 + pos.1]
+}
+
+fn castling_key(castling: Castling) -> u64 {
+    let mut key = 0;
+    let rights = [
+        castling.white_king,
+        castling.white_queen,
+        castling.black_king,
+        castling.black_queen,
+    ];
+    for (i, right) in rights.iter().enumerate() {
+        if *right {
+            key ^= ZOBRIST[ZOBRIST_CASTLING + i];
+        }
+    }
+    key
+}
+
+fn en_passant_key(en_passant: Option<Position>) -> u64 {
+    match en_passant {
+        Some(pos) => ZOBRIST[ZOBRIST_EN_PASSANT + pos.1],
+        None => 0,
+    }
+}
+
+// returns true if any piece of `by` attacks `pos`
+fn is_attacked(board: &[[Option<Pair>; 8]; 8], pos: Position, by: Kind) -> bool {
+    let (row, col) = (pos.0 as i32, pos.1 as i32);
+    let at = |r: i32, c: i32| -> Option<Pair> {
+        if (0..8).contains(&r) && (0..8).contains(&c) {
+            board[r as usize][c as usize]
+        } else {
+            None
+        }
+    };
+    let is = |r: i32, c: i32, piece: Piece| at(r, c) == Some(Pair { kind: by, piece });
+
+    // white pawns attack towards row 0 so they sit one row below the attacked square
+    let pawn_row = if by == Kind::White { row + 1 } else { row - 1 };
+    if is(pawn_row, col - 1, Piece::Pawn) || is(pawn_row, col + 1, Piece::Pawn) {
+        return true;
+    }
+
+    let knight = [
+        (-2, -1),
+        (-2, 1),
+        (2, -1),
+        (2, 1),
+        (-1, -2),
+        (1, -2),
+        (-1, 2),
+        (1, 2),
+    ];
+    if knight
+        .iter()
+        .any(|(dr, dc)| is(row + dr, col + dc, Piece::Knight))
+    {
+        return true;
+    }
+
+    let straight = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    let diagonal = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+    if straight
+        .iter()
+        .chain(diagonal.iter())
+        .any(|(dr, dc)| is(row + dr, col + dc, Piece::King))
+    {
+        return true;
+    }
+
+    let slides = |dirs: &[(i32, i32)], slider: Piece| {
+        dirs.iter().any(|(dr, dc)| {
+            let (mut r, mut c) = (row + dr, col + dc);
+            while (0..8).contains(&r) && (0..8).contains(&c) {
+                if let Some(p) = board[r as usize][c as usize] {
+                    return p.kind == by && (p.piece == slider || p.piece == Piece::Queen);
+                }
+                r += dr;
+                c += dc;
+            }
+            false
+        })
+    };
+
+    slides(&straight, Piece::Rook) || slides(&diagonal, Piece::Bishop)
+}
+
+fn find_king(board: &[[Option<Pair>; 8]; 8], kind: Kind) -> Option<Position> {
+    let king = Some(Pair {
+        kind,
+        piece: Piece::King,
+    });
+    for (r, row) in board.iter().enumerate() {
+        for (c, square) in row.iter().enumerate() {
+            if *square == king {
+                return Some((r, c));
+            }
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    // holds the winner
+    Checkmate(Kind),
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoves,
+    Repetition,
+}
+
+impl Outcome {
+    pub fn winner(&self) -> Option<Kind> {
+        match self {
+            Outcome::Checkmate(winner) => Some(*winner),
+            _ => None,
+        }
+    }
+
+    // result in PGN notation
+    pub fn result(&self) -> &'static str {
+        match self.winner() {
+            Some(Kind::White) => "1-0",
+            Some(Kind::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Outcome::Checkmate(Kind::White) => "White mates",
+            Outcome::Checkmate(Kind::Black) => "Black mates",
+            Outcome::Stalemate => "Stalemate",
+            Outcome::InsufficientMaterial => "Insufficient material",
+            Outcome::FiftyMoves => "Fifty move rule",
+            Outcome::Repetition => "Threefold repetition",
+        }
+    }
+}
+
+// everything needed to take a move back
+#[derive(Clone, Debug)]
+pub struct Undo {
+    mv: Move,
+    piece: Pair,
+    captured: Option<(Position, Pair)>,
+    castling: Castling,
+    en_passant: Option<Position>,
+    halfmove: u32,
+    hash: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct NullUndo {
+    en_passant: Option<Position>,
+    halfmove: u32,
+    hash: u64,
+}
+
+// full game state on top of the board: side to move, castling rights, en passant
+// square and move counters, plus a hash history for repetition detection
+#[derive(Debug)]
+pub struct State {
+    pub board: Board,
+    pub side: Kind,
+    pub castling: Castling,
+    pub en_passant: Option<Position>,
+    pub halfmove: u32,
+    pub fullmove: u32,
+    hash: u64,
+    history: Vec<u64>,
+}
+
+impl Clone for State {
+    fn clone(&self) -> Self {
+        State {
+            board: Board::from_data(*self.board),
+            side: self.side,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+            fullmove: self.fullmove,
+            hash: self.hash,
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::from_board(Board::default(), Kind::White, Castling::all())
+    }
+}
+
+impl State {
+    pub fn from_board(board: Board, side: Kind, castling: Castling) -> State {
+        let mut state = State {
+            board,
+            side,
+            castling,
+            en_passant: None,
+            halfmove: 0,
+            fullmove: 1,
+            hash: 0,
+            history: Vec::new(),
+        };
+        state.hash = state.compute_hash();
+        state
+    }
+
+    pub fn from_fen(fen: &str) -> Result<State, anyhow::Error> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or_else(|| anyhow!("Empty FEN"))?;
+
+        let mut board = Board::new();
+        let rows: Vec<&str> = placement.split('/').collect();
+        if rows.len() != 8 {
+            return Err(anyhow!("FEN must have 8 ranks"));
+        }
+        for (r, row) in rows.iter().enumerate() {
+            let mut c = 0;
+            for ch in row.chars() {
+                if let Some(skip) = ch.to_digit(10) {
+                    c += skip as usize;
+                    continue;
+                }
+                let kind = if ch.is_ascii_uppercase() {
+                    Kind::White
+                } else {
+                    Kind::Black
+                };
+                let piece = match ch.to_ascii_lowercase() {
+                    'p' => Piece::Pawn,
+                    'n' => Piece::Knight,
+                    'b' => Piece::Bishop,
+                    'r' => Piece::Rook,
+                    'q' => Piece::Queen,
+                    'k' => Piece::King,
+                    _ => return Err(anyhow!("Invalid piece '{}' in FEN", ch)),
+                };
+                if c > 7 {
+                    return Err(anyhow!("Too many squares in rank {}", 8 - r));
+                }
+                board[r][c] = Some(Pair { kind, piece });
+                c += 1;
+            }
+            if c != 8 {
+                return Err(anyhow!("Rank {} does not have 8 squares", 8 - r));
+            }
+        }
+
+        let side = match fields.next().unwrap_or("w") {
+            "w" => Kind::White,
+            "b" => Kind::Black,
+            s => return Err(anyhow!("Invalid side to move '{}'", s)),
+        };
+
+        let mut castling = Castling::none();
+        for ch in fields.next().unwrap_or("-").chars() {
+            match ch {
+                'K' => castling.white_king = true,
+                'Q' => castling.white_queen = true,
+                'k' => castling.black_king = true,
+                'q' => castling.black_queen = true,
+                '-' => (),
+                _ => return Err(anyhow!("Invalid castling rights '{}'", ch)),
+            }
+        }
+
+        let en_passant = match fields.next().unwrap_or("-") {
+            "-" => None,
+            s => Some(parse_square(s).ok_or_else(|| anyhow!("Invalid en passant square '{}'", s))?),
+        };
+
+        let halfmove = fields.next().unwrap_or("0").parse()?;
+        let fullmove = fields.next().unwrap_or("1").parse()?;
+
+        if find_king(&board, Kind::White).is_none() || find_king(&board, Kind::Black).is_none() {
+            return Err(anyhow!("Both sides need a king"));
+        }
+
+        let mut state = State::from_board(board, side, castling);
+        state.en_passant = en_passant;
+        state.halfmove = halfmove;
+        state.fullmove = fullmove;
+        state.hash = state.compute_hash();
+        Ok(state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for (r, row) in self.board.iter().enumerate() {
+            let mut empty = 0;
+            for square in row.iter() {
+                match square {
+                    Some(p) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let ch = match p.piece {
+                            Piece::Pawn => 'p',
+                            Piece::Knight => 'n',
+                            Piece::Bishop => 'b',
+                            Piece::Rook => 'r',
+                            Piece::Queen => 'q',
+                            Piece::King => 'k',
+                        };
+                        fen.push(if p.kind == Kind::White {
+                            ch.to_ascii_uppercase()
+                        } else {
+                            ch
+                        });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if r < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.side == Kind::White {
+            " w "
+        } else {
+            " b "
+        });
+
+        let mut castling = String::new();
+        if self.castling.white_king {
+            castling.push('K');
+        }
+        if self.castling.white_queen {
+            castling.push('Q');
+        }
+        if self.castling.black_king {
+            castling.push('k');
+        }
+        if self.castling.black_queen {
+            castling.push('q');
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        match self.en_passant {
+            Some(pos) => fen.push_str(&format!(" {}", square_name(pos))),
+            None => fen.push_str(" -"),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove, self.fullmove));
+        fen
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (r, row) in self.board.iter().enumerate() {
+            for (c, square) in row.iter().enumerate() {
+                if let Some(p) = square {
+                    hash ^= piece_key(*p, (r, c));
+                }
+            }
+        }
+        if self.side == Kind::Black {
+            hash ^= ZOBRIST[ZOBRIST_SIDE];
+        }
+        hash ^ castling_key(self.castling) ^ en_passant_key(self.en_passant)
+    }
+
+    pub fn in_check(&self) -> bool {
+        match find_king(&self.board, self.side) {
+            Some(king) => is_attacked(&self.board, king, self.side.opposite()),
+            None => false,
+        }
+    }
+
+    pub fn is_capture(&self, mv: &Move) -> bool {
+        self.board[mv.to.0][mv.to.1].is_some() || self.is_en_passant(mv)
+    }
+
+    fn is_en_passant(&self, mv: &Move) -> bool {
+        self.en_passant == Some(mv.to)
+            && mv.from.1 != mv.to.1
+            && matches!(self.board[mv.from.0][mv.from.1], Some(p) if p.piece == Piece::Pawn)
+    }
+
+    // piece standing on the target square, or the pawn taken en passant
+    pub fn captured_piece(&self, mv: &Move) -> Option<Piece> {
+        if self.is_en_passant(mv) {
+            return Some(Piece::Pawn);
+        }
+        self.board[mv.to.0][mv.to.1].map(|p| p.piece)
+    }
+
+    // true if the side has anything besides pawns and the king, used to guard against zugzwang
+    pub fn has_non_pawn_material(&self, kind: Kind) -> bool {
+        self.board
+            .iter()
+            .flatten()
+            .flatten()
+            .any(|p| p.kind == kind && p.piece != Piece::Pawn && p.piece != Piece::King)
+    }
+
+    // true if the current position already occurred since the last irreversible move
+    pub fn is_repetition(&self) -> bool {
+        self.history
+            .iter()
+            .rev()
+            .take(self.halfmove as usize)
+            .skip(1)
+            .step_by(2)
+            .any(|&h| h == self.hash)
+    }
+
+    // number of times the current position occurred, including now
+    pub fn repetition_count(&self) -> usize {
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(self.halfmove as usize)
+            .filter(|&&h| h == self.hash)
+            .count()
+    }
+
+    // neither side can mate with what is left: bare kings, a single minor piece,
+    // or only bishops all standing on the same square color
+    pub fn insufficient_material(&self) -> bool {
+        let mut minors = Vec::new();
+        for (r, row) in self.board.iter().enumerate() {
+            for (c, square) in row.iter().enumerate() {
+                match square {
+                    None => (),
+                    Some(p) if p.piece == Piece::King => (),
+                    Some(p) if p.piece == Piece::Knight || p.piece == Piece::Bishop => {
+                        minors.push((p.piece, (r + c) % 2))
+                    }
+                    Some(_) => return false,
+                }
+            }
+        }
+        minors.len() <= 1
+            || minors
+                .iter()
+                .all(|(p, color)| *p == Piece::Bishop && *color == minors[0].1)
+    }
+
+    // whether `side` could mate at all: anything beyond a bare king or a king with a single
+    // minor piece, as used when the opponent's time runs out
+    pub fn has_mating_material(&self, side: Kind) -> bool {
+        let mut minors = 0;
+        for square in self.board.iter().flatten().flatten() {
+            if square.kind != side {
+                continue;
+            }
+            match square.piece {
+                Piece::King => (),
+                Piece::Knight | Piece::Bishop => minors += 1,
+                _ => return true,
+            }
+        }
+        minors >= 2
+    }
+
+    // games end by themselves on mate, stalemate and insufficient material
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.legal_moves().is_empty() {
+            return Some(if self.in_check() {
+                Outcome::Checkmate(self.side.opposite())
+            } else {
+                Outcome::Stalemate
+            });
+        }
+        if self.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        None
+    }
+
+    // draws that a player may claim, fifty moves without capture or pawn move and threefold repetition
+    pub fn claimable_draw(&self) -> Option<Outcome> {
+        if self.halfmove >= 100 {
+            Some(Outcome::FiftyMoves)
+        } else if self.repetition_count() >= 3 {
+            Some(Outcome::Repetition)
+        } else {
+            None
+        }
+    }
+
+    // all pseudo legal moves, king safety is checked in `legal_moves`
+    fn pseudo_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for (r, row) in self.board.iter().enumerate() {
+            for (c, square) in row.iter().enumerate() {
+                let pair = match square {
+                    Some(p) if p.kind == self.side => *p,
+                    _ => continue,
+                };
+
+                for to in self.board.all_moves((r, c)) {
+                    // kings are never captured, positions leading to it are illegal
+                    if matches!(self.board[to.0][to.1], Some(p) if p.piece == Piece::King) {
+                        continue;
+                    }
+                    if pair.piece == Piece::Pawn && (to.0 == 0 || to.0 == 7) {
+                        for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight] {
+                            moves.push(Move {
+                                from: (r, c),
+                                to,
+                                promotion: Some(promotion),
+                            });
+                        }
+                    } else {
+                        moves.push(Move::new((r, c), to));
+                    }
+                }
+
+                match pair.piece {
+                    Piece::Pawn => {
+                        if let Some(ep) = self.en_passant {
+                            let forward = if self.side == Kind::White { -1 } else { 1 };
+                            if ep.0 as i32 == r as i32 + forward
+                                && (ep.1 as i32 - c as i32).abs() == 1
+                            {
+                                moves.push(Move::new((r, c), ep));
+                            }
+                        }
+                    }
+                    Piece::King => self.castling_moves((r, c), &mut moves),
+                    _ => (),
+                }
+            }
+        }
+        moves
+    }
+
+    fn castling_moves(&self, king: Position, moves: &mut Vec<Move>) {
+        let (row, king_side, queen_side) = match self.side {
+            Kind::White => (7, self.castling.white_king, self.castling.white_queen),
+            Kind::Black => (0, self.castling.black_king, self.castling.black_queen),
+        };
+        if king != (row, 4) {
+            return;
+        }
+
+        let enemy = self.side.opposite();
+        let rook = Some(Pair {
+            kind: self.side,
+            piece: Piece::Rook,
+        });
+        let empty = |cols: &[usize]| cols.iter().all(|&c| self.board[row][c].is_none());
+        let safe = |cols: &[usize]| {
+            cols.iter()
+                .all(|&c| !is_attacked(&self.board, (row, c), enemy))
+        };
+
+        if king_side && self.board[row][7] == rook && empty(&[5, 6]) && safe(&[4, 5, 6]) {
+            moves.push(Move::new(king, (row, 6)));
+        }
+        if queen_side && self.board[row][0] == rook && empty(&[1, 2, 3]) && safe(&[4, 3, 2]) {
+            moves.push(Move::new(king, (row, 2)));
+        }
+    }
+
+    // checks that the move does not leave own king in check
+    fn is_safe(&self, mv: &Move) -> bool {
+        let mut board = *self.board;
+        if self.is_en_passant(mv) {
+            board[mv.from.0][mv.to.1] = None;
+        }
+        board[mv.to.0][mv.to.1] = board[mv.from.0][mv.from.1];
+        board[mv.from.0][mv.from.1] = None;
+
+        match find_king(&board, self.side) {
+            Some(king) => !is_attacked(&board, king, self.side.opposite()),
+            None => true,
+        }
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = self.pseudo_moves();
+        moves.retain(|mv| self.is_safe(mv));
+        moves
+    }
+
+    // parses coordinate notation against the legal moves of the position
+    pub fn parse_move(&self, s: &str) -> Option<Move> {
+        self.legal_moves()
+            .into_iter()
+            .find(|mv| mv.to_string() == s)
+    }
+
+    // standard algebraic notation of a legal move, e.g. "Nbd7", "exd5", "e8=Q+" or "O-O"
+    pub fn san(&self, mv: &Move) -> String {
+        let piece = self.board[mv.from.0][mv.from.1]
+            .expect("Piece must be present")
+            .piece;
+        let mut san = String::new();
+        if piece == Piece::King && mv.from.1.abs_diff(mv.to.1) == 2 {
+            san.push_str(if mv.to.1 == 6 { "O-O" } else { "O-O-O" });
+        } else if piece == Piece::Pawn {
+            if self.is_capture(mv) {
+                san.push((b'a' + mv.from.1 as u8) as char);
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(piece_letter(promotion));
+            }
+        } else {
+            san.push(piece_letter(piece));
+            // other pieces of the same type that can reach the target square
+            let rivals: Vec<Position> = self
+                .legal_moves()
+                .into_iter()
+                .filter(|other| other.to == mv.to && other.from != mv.from)
+                .filter(|other| matches!(self.board[other.from.0][other.from.1], Some(p) if p.piece == piece))
+                .map(|other| other.from)
+                .collect();
+            if !rivals.is_empty() {
+                let name = square_name(mv.from);
+                if rivals.iter().all(|from| from.1 != mv.from.1) {
+                    san.push_str(&name[..1]);
+                } else if rivals.iter().all(|from| from.0 != mv.from.0) {
+                    san.push_str(&name[1..]);
+                } else {
+                    san.push_str(&name);
+                }
+            }
+            if self.is_capture(mv) {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+        }
+
+        let mut after = self.clone();
+        after.make_move(*mv);
+        if after.in_check() {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+
+    // plays a legal move, returns what is needed to take it back with `unmake_move`
+    pub fn make_move(&mut self, mv: Move) -> Undo {
+        let piece = self.board[mv.from.0][mv.from.1].expect("Piece must be present");
+        let captured = if self.is_en_passant(&mv) {
+            let pos = (mv.from.0, mv.to.1);
+            Some((
+                pos,
+                self.board[pos.0][pos.1].expect("En passant pawn must be present"),
+            ))
+        } else {
+            self.board[mv.to.0][mv.to.1].map(|p| (mv.to, p))
+        };
+
+        let undo = Undo {
+            mv,
+            piece,
+            captured,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+            hash: self.hash,
+        };
+        self.history.push(self.hash);
+
+        let mut hash = self.hash ^ castling_key(self.castling) ^ en_passant_key(self.en_passant);
+
+        if let Some((pos, p)) = captured {
+            self.board[pos.0][pos.1] = None;
+            hash ^= piece_key(p, pos);
+        }
+
+        let placed = Pair {
+            kind: piece.kind,
+            piece: mv.promotion.unwrap_or(piece.piece),
+        };
+        self.board[mv.from.0][mv.from.1] = None;
+        self.board[mv.to.0][mv.to.1] = Some(placed);
+        hash ^= piece_key(piece, mv.from) ^ piece_key(placed, mv.to);
+
+        // castling moves the rook as well
+        if piece.piece == Piece::King && (mv.to.1 as i32 - mv.from.1 as i32).abs() == 2 {
+            let (rook_from, rook_to) = if mv.to.1 == 6 {
+                ((mv.from.0, 7), (mv.from.0, 5))
+            } else {
+                ((mv.from.0, 0), (mv.from.0, 3))
+            };
+            let rook = self.board[rook_from.0][rook_from.1]
+                .take()
+                .expect("Rook must be present");
+            self.board[rook_to.0][rook_to.1] = Some(rook);
+            hash ^= piece_key(rook, rook_from) ^ piece_key(rook, rook_to);
+        }
+
+        // moving the king or a rook, or capturing a rook, loses castling rights
+        for pos in [mv.from, mv.to] {
+            match pos {
+                (7, 4) => {
+                    self.castling.white_king = false;
+                    self.castling.white_queen = false;
+                }
+                (0, 4) => {
+                    self.castling.black_king = false;
+                    self.castling.black_queen = false;
+                }
+                (7, 7) => self.castling.white_king = false,
+                (7, 0) => self.castling.white_queen = false,
+                (0, 7) => self.castling.black_king = false,
+                (0, 0) => self.castling.black_queen = false,
+                _ => (),
+            }
+        }
+
+        self.en_passant =
+            if piece.piece == Piece::Pawn && (mv.to.0 as i32 - mv.from.0 as i32).abs() == 2 {
+                Some(((mv.from.0 + mv.to.0) / 2, mv.from.1))
+            } else {
+                None
+            };
+
+        if piece.piece == Piece::Pawn || captured.is_some() {
+            self.halfmove = 0;
+        } else {
+            self.halfmove += 1;
+        }
+        if self.side == Kind::Black {
+            self.fullmove += 1;
+        }
+        self.side = self.side.opposite();
+
+        hash ^=
+            castling_key(self.castling) ^ en_passant_key(self.en_passant) ^ ZOBRIST[ZOBRIST_SIDE];
+        self.hash = hash;
+
+        undo
+    }
+
+    pub fn unmake_move(&mut self, undo: Undo) {
+        let mv = undo.mv;
+        self.side = self.side.opposite();
+        if self.side == Kind::Black {
+            self.fullmove -= 1;
+        }
+
+        self.board[mv.to.0][mv.to.1] = None;
+        self.board[mv.from.0][mv.from.1] = Some(undo.piece);
+        if let Some((pos, p)) = undo.captured {
+            self.board[pos.0][pos.1] = Some(p);
+        }
+
+        if undo.piece.piece == Piece::King && (mv.to.1 as i32 - mv.from.1 as i32).abs() == 2 {
+            let (rook_from, rook_to) = if mv.to.1 == 6 {
+                ((mv.from.0, 7), (mv.from.0, 5))
+            } else {
+                ((mv.from.0, 0), (mv.from.0, 3))
+            };
+            self.board[rook_from.0][rook_from.1] = self.board[rook_to.0][rook_to.1].take();
+        }
+
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove = undo.halfmove;
+        self.hash = undo.hash;
+        self.history.pop();
+    }
+
+    // passes the turn, only used by the search
+    pub fn make_null(&mut self) -> NullUndo {
+        let undo = NullUndo {
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+            hash: self.hash,
+        };
+        self.history.push(self.hash);
+        self.hash ^= en_passant_key(self.en_passant) ^ ZOBRIST[ZOBRIST_SIDE];
+        self.en_passant = None;
+        // repetitions are not looked up across a null move
+        self.halfmove = 0;
+        self.side = self.side.opposite();
+        undo
+    }
+
+    pub fn unmake_null(&mut self, undo: NullUndo) {
+        self.side = self.side.opposite();
+        self.en_passant = undo.en_passant;
+        self.halfmove = undo.halfmove;
+        self.hash = undo.hash;
+        self.history.pop();
+    }
+}
+
+// the uppercase letter of `piece` in SAN
+fn piece_letter(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
 // test chess board by moving it
 #[cfg(test)]
 mod tests {
@@ -1011,4 +2003,86 @@ mod tests {
             assert_eq!(board.all_moves((4, 0)), vec![(3, 0)]);
         }
     }
+
+    fn perft(state: &mut State, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut nodes = 0;
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            assert_eq!(state.hash(), state.compute_hash(), "hash after {}", mv);
+            nodes += perft(state, depth - 1);
+            state.unmake_move(undo);
+        }
+        nodes
+    }
+
+    #[test]
+    fn test_perft_start_position() {
+        let mut state = State::default();
+        assert_eq!(perft(&mut state, 1), 20);
+        assert_eq!(perft(&mut state, 2), 400);
+        assert_eq!(perft(&mut state, 3), 8902);
+    }
+
+    #[test]
+    fn test_perft_castling_en_passant_promotion() {
+        // "kiwipete" and two more positions from the chess programming wiki
+        let mut state =
+            State::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(perft(&mut state, 1), 48);
+        assert_eq!(perft(&mut state, 2), 2039);
+
+        let mut state = State::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        assert_eq!(perft(&mut state, 3), 2812);
+
+        let mut state =
+            State::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1")
+                .unwrap();
+        assert_eq!(perft(&mut state, 2), 264);
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq e3 4 17";
+        assert_eq!(State::from_fen(fen).unwrap().to_fen(), fen);
+        assert_eq!(
+            State::default().to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_san() {
+        let san = |fen: &str, mv: &str| {
+            let state = State::from_fen(fen).unwrap();
+            state.san(&state.parse_move(mv).unwrap())
+        };
+        let state = State::default();
+        assert_eq!(state.san(&state.parse_move("g1f3").unwrap()), "Nf3");
+        assert_eq!(state.san(&state.parse_move("e2e4").unwrap()), "e4");
+
+        // two rooks and two knights can reach the same square
+        assert_eq!(san("k7/8/8/8/8/8/7K/R6R w - - 0 1", "a1d1"), "Rad1");
+        assert_eq!(san("3k4/8/8/8/8/8/8/1N3N1K w - - 0 1", "b1d2"), "Nbd2");
+        assert_eq!(san("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4"), "R1a4");
+
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"), "O-O-O");
+        assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q+");
+        assert_eq!(
+            san(
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+                "e4d5"
+            ),
+            "exd5"
+        );
+        assert_eq!(
+            san("3rr1k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1", "e2e8"),
+            "Rxe8+"
+        );
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/4R1K1 w - - 0 1", "e1e8"), "Re8#");
+    }
 }
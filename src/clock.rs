@@ -0,0 +1,189 @@
+use std::time::{Duration, Instant};
+
+use crate::chess::Kind;
+
+// a time control: `base` on each clock at the start, and again after every `moves` moves
+// when the game is played in periods, `increment` added after every move and `delay` at
+// the start of every move that passes before the clock runs (simple delay)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+    pub delay: Duration,
+    pub moves: Option<u32>,
+}
+
+// what the clock shows at a moment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub white: Duration,
+    pub black: Duration,
+    pub running: Option<Kind>,
+}
+
+// the server's clock of a game. it only starts running once both sides made their first
+// move, from then on the clock of the side to move runs
+#[derive(Clone, Debug)]
+pub struct Clock {
+    control: TimeControl,
+    // time left for white and black when their clock last stopped
+    remaining: [Duration; 2],
+    // moves made by white and black
+    moves: [u32; 2],
+    // the side whose clock runs and since when
+    running: Option<(Kind, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Clock {
+        Clock {
+            control,
+            remaining: [control.base; 2],
+            moves: [0; 2],
+            running: None,
+        }
+    }
+
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn running(&self) -> Option<Kind> {
+        self.running.map(|(side, _)| side)
+    }
+
+    // time left for `side` at `now`
+    pub fn remaining(&self, side: Kind, now: Instant) -> Duration {
+        let left = self.remaining[side.index()];
+        match self.running {
+            Some((running, since)) if running == side => {
+                let used = now.saturating_duration_since(since);
+                left.saturating_sub(used.saturating_sub(self.control.delay))
+            }
+            _ => left,
+        }
+    }
+
+    pub fn read(&self, now: Instant) -> Reading {
+        Reading {
+            white: self.remaining(Kind::White, now),
+            black: self.remaining(Kind::Black, now),
+            running: self.running(),
+        }
+    }
+
+    // true once the running clock of `side` reached zero
+    pub fn flagged(&self, side: Kind, now: Instant) -> bool {
+        self.running() == Some(side) && self.remaining(side, now).is_zero()
+    }
+
+    // moves `side` has left to make in the current period
+    pub fn moves_to_go(&self, side: Kind) -> Option<u32> {
+        self.control
+            .moves
+            .map(|period| period - self.moves[side.index()] % period)
+    }
+
+    // stops the clock of `side` after its move at `now` and starts the opponent's
+    pub fn punch(&mut self, side: Kind, now: Instant) {
+        let i = side.index();
+        if self.running() == Some(side) {
+            self.remaining[i] = self.remaining(side, now) + self.control.increment;
+        }
+        self.moves[i] += 1;
+        if let Some(period) = self.control.moves {
+            if self.moves[i].is_multiple_of(period) {
+                self.remaining[i] += self.control.base;
+            }
+        }
+        if self.moves[0] + self.moves[1] >= 2 {
+            self.running = Some((side.opposite(), now));
+        }
+    }
+
+    // sets the time left, as when a game is restored
+    pub fn set_remaining(&mut self, white: Duration, black: Duration) {
+        let now = Instant::now();
+        self.remaining = [white, black];
+        if let Some((side, _)) = self.running {
+            self.running = Some((side, now));
+        }
+    }
+
+    // stops the clock for good at the end of the game
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((side, _)) = self.running {
+            self.remaining[side.index()] = self.remaining(side, now);
+            self.running = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut clock = Clock::new(TimeControl {
+            base: seconds(60),
+            increment: seconds(2),
+            delay: seconds(0),
+            moves: None,
+        });
+        let start = Instant::now();
+        // the first move of each side is free
+        clock.punch(Kind::White, start + seconds(30));
+        assert_eq!(clock.running(), None);
+        clock.punch(Kind::Black, start + seconds(40));
+        assert_eq!(clock.running(), Some(Kind::White));
+        assert_eq!(
+            clock.remaining(Kind::White, start + seconds(50)),
+            seconds(50)
+        );
+
+        clock.punch(Kind::White, start + seconds(50));
+        assert_eq!(
+            clock.remaining(Kind::White, start + seconds(100)),
+            seconds(52)
+        );
+        assert!(!clock.flagged(Kind::Black, start + seconds(109)));
+        assert!(clock.flagged(Kind::Black, start + seconds(110)));
+
+        clock.stop(start + seconds(70));
+        assert_eq!(
+            clock.remaining(Kind::Black, start + seconds(500)),
+            seconds(40)
+        );
+    }
+
+    #[test]
+    fn test_delay_and_periods() {
+        let mut clock = Clock::new(TimeControl {
+            base: seconds(60),
+            increment: seconds(0),
+            delay: seconds(5),
+            moves: Some(2),
+        });
+        let start = Instant::now();
+        clock.punch(Kind::White, start);
+        clock.punch(Kind::Black, start);
+        assert_eq!(clock.moves_to_go(Kind::White), Some(1));
+        // the delay is not taken off the clock
+        assert_eq!(
+            clock.remaining(Kind::White, start + seconds(5)),
+            seconds(60)
+        );
+        // the second move ends the period and adds the base again
+        clock.punch(Kind::White, start + seconds(15));
+        assert_eq!(
+            clock.remaining(Kind::White, start + seconds(15)),
+            seconds(110)
+        );
+        assert_eq!(clock.moves_to_go(Kind::White), Some(2));
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::chess::{Kind, Piece, State};
+
+// piece-square tables are written from white's point of view with the 8th rank first,
+// which matches the board layout so white pieces index them directly by (row, col)
+#[rustfmt::skip]
+const PAWN: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+// game phase weight of each piece, a full board adds up to `MAX_PHASE`
+const PHASE: [i32; 6] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: i32 = 24;
+
+// tunable evaluation terms, indexed by `Piece::index`.
+// middlegame and endgame scores are blended by the amount of material left
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalParams {
+    pub material_mg: [i32; 6],
+    pub material_eg: [i32; 6],
+    pub pst_mg: [[i32; 64]; 6],
+    pub pst_eg: [[i32; 64]; 6],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        EvalParams {
+            material_mg: [100, 320, 330, 500, 900, 0],
+            material_eg: [100, 320, 330, 500, 900, 0],
+            pst_mg: [PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING_MIDDLEGAME],
+            pst_eg: [PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING_ENDGAME],
+        }
+    }
+}
+
+const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+// parameter files are plain text: a term's name followed by its values, whitespace is free
+// and lines starting with `#` are comments. piece-square tables are named `pst_mg_<piece>`
+// and `pst_eg_<piece>` and are laid out like the tables above. terms that are left out
+// keep their default value
+impl EvalParams {
+    pub fn load(path: impl AsRef<Path>) -> Result<EvalParams, anyhow::Error> {
+        EvalParams::from_text(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+
+    pub fn from_text(text: &str) -> Result<EvalParams, anyhow::Error> {
+        let mut params = EvalParams::default();
+        let mut tokens = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split_whitespace());
+
+        while let Some(name) = tokens.next() {
+            let values: &mut [i32] = match name {
+                "material_mg" => &mut params.material_mg,
+                "material_eg" => &mut params.material_eg,
+                _ => {
+                    let (phase, piece) = name
+                        .strip_prefix("pst_")
+                        .and_then(|rest| rest.split_once('_'))
+                        .ok_or_else(|| anyhow!("Unknown parameter {}", name))?;
+                    let piece = PIECE_NAMES
+                        .iter()
+                        .position(|&p| p == piece)
+                        .ok_or_else(|| anyhow!("Unknown parameter {}", name))?;
+                    match phase {
+                        "mg" => &mut params.pst_mg[piece],
+                        "eg" => &mut params.pst_eg[piece],
+                        _ => return Err(anyhow!("Unknown parameter {}", name)),
+                    }
+                }
+            };
+            for value in values.iter_mut() {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| anyhow!("Too few values for {}", name))?;
+                *value = token
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid value {} for {}", token, name))?;
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn to_text(&self) -> String {
+        let join = |values: &[i32]| {
+            values
+                .iter()
+                .map(|v| format!("{:4}", v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut text = String::from("# evaluation parameters, see `EvalParams` in src/eval.rs\n");
+        text += &format!("material_mg {}\n", join(&self.material_mg));
+        text += &format!("material_eg {}\n", join(&self.material_eg));
+        for (phase, tables) in [("mg", &self.pst_mg), ("eg", &self.pst_eg)] {
+            for (name, table) in PIECE_NAMES.iter().zip(tables.iter()) {
+                text += &format!("\npst_{}_{}\n", phase, name);
+                for row in table.chunks(8) {
+                    text += &join(row);
+                    text += "\n";
+                }
+            }
+        }
+        text
+    }
+
+    // every term in one list: material_mg, material_eg, then the pst_mg and pst_eg tables
+    pub fn to_vec(&self) -> Vec<i32> {
+        let mut values = Vec::with_capacity(PARAM_COUNT);
+        values.extend_from_slice(&self.material_mg);
+        values.extend_from_slice(&self.material_eg);
+        for tables in [&self.pst_mg, &self.pst_eg] {
+            for table in tables.iter() {
+                values.extend_from_slice(table);
+            }
+        }
+        values
+    }
+
+    pub fn from_slice(values: &[i32]) -> EvalParams {
+        let mut params = EvalParams::default();
+        let mut values = values.iter().copied();
+        let targets = params
+            .material_mg
+            .iter_mut()
+            .chain(params.material_eg.iter_mut())
+            .chain(params.pst_mg.iter_mut().flatten())
+            .chain(params.pst_eg.iter_mut().flatten());
+        for (target, value) in targets.zip(&mut values) {
+            *target = value;
+        }
+        params
+    }
+}
+
+// length of `EvalParams::to_vec`
+pub const PARAM_COUNT: usize = 12 + 2 * 6 * 64;
+
+// static evaluation in centipawns from the point of view of the side to move
+pub fn evaluate(state: &State, params: &EvalParams) -> i32 {
+    let mut mg = 0;
+    let mut eg = 0;
+
+    for (r, row) in state.board.iter().enumerate() {
+        for (c, square) in row.iter().enumerate() {
+            let pair = match square {
+                Some(p) => p,
+                None => continue,
+            };
+            let piece = pair.piece.index();
+            // black pieces read the tables mirrored vertically
+            let (sq, sign) = match pair.kind {
+                Kind::White => (r * 8 + c, 1),
+                Kind::Black => ((7 - r) * 8 + c, -1),
+            };
+
+            mg += sign * (params.material_mg[piece] + params.pst_mg[piece][sq]);
+            eg += sign * (params.material_eg[piece] + params.pst_eg[piece][sq]);
+        }
+    }
+
+    let phase = game_phase(state);
+    let score = (mg * phase + eg * (MAX_PHASE - phase)) / MAX_PHASE;
+
+    match state.side {
+        Kind::White => score,
+        Kind::Black => -score,
+    }
+}
+
+// 0 for bare kings up to `MAX_PHASE` for the full set of pieces
+pub fn game_phase(state: &State) -> i32 {
+    let phase: i32 = state
+        .board
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|pair| PHASE[pair.piece.index()])
+        .sum();
+    phase.min(MAX_PHASE)
+}
+
+// material value used for move ordering and pruning margins
+pub fn piece_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => 100,
+        Piece::Knight => 320,
+        Piece::Bishop => 330,
+        Piece::Rook => 500,
+        Piece::Queen => 900,
+        Piece::King => 20000,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_text_round_trip() {
+        let mut params = EvalParams::default();
+        params.material_eg[0] = 123;
+        params.pst_eg[5][63] = -7;
+        assert_eq!(EvalParams::from_text(&params.to_text()).unwrap(), params);
+        assert_eq!(EvalParams::from_slice(&params.to_vec()), params);
+
+        let partial =
+            EvalParams::from_text("# pawns only\nmaterial_mg 90 320 330 500 900 0").unwrap();
+        assert_eq!(partial.material_mg[0], 90);
+        assert_eq!(partial.pst_mg, EvalParams::default().pst_mg);
+        assert!(EvalParams::from_text("pst_mg_dragon 1").is_err());
+        assert!(EvalParams::from_text("material_mg 1 2").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use crate::search::MATE;
+use crate::timeman::Limits;
+
+// how long an engine may take to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// another UCI engine running as a child process
+pub struct ExternalEngine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    // lines of the engine's output, read on a separate thread so waits can time out
+    lines: Receiver<String>,
+}
+
+// an engine as given on the command line or in the environment: comma separated key=value
+// pairs where `name` names the engine, `cmd` is the command that runs it and every other
+// key is a UCI option
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineSpec {
+    pub name: Option<String>,
+    pub command: Option<String>,
+    pub options: Vec<(String, String)>,
+}
+
+impl EngineSpec {
+    pub fn parse(spec: &str) -> Result<EngineSpec, anyhow::Error> {
+        let mut engine = EngineSpec::default();
+        for pair in spec.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value in engine spec, got {}", pair))?;
+            match key {
+                "name" => engine.name = Some(value.to_string()),
+                "cmd" => engine.command = Some(value.to_string()),
+                _ => engine.options.push((key.to_string(), value.to_string())),
+            }
+        }
+        Ok(engine)
+    }
+}
+
+// the engine's answer to `go`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineReply {
+    // the move as the engine sent it, in coordinate notation
+    pub best_move: String,
+    // last reported score from the engine's point of view, mates as in `search`
+    pub score: Option<i32>,
+}
+
+impl ExternalEngine {
+    // starts `command` (split on whitespace into program and arguments) and waits for `uciok`
+    pub fn start(command: &str) -> Result<ExternalEngine, anyhow::Error> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| anyhow!("Empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Cannot start {}: {}", program, e))?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = ExternalEngine {
+            name: program.to_string(),
+            child,
+            stdin,
+            lines,
+        };
+        engine.send("uci")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine.next_line(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+        Ok(engine)
+    }
+
+    // starts the engine of `spec` and sets its options
+    pub fn from_spec(spec: &EngineSpec) -> Result<ExternalEngine, anyhow::Error> {
+        let command = spec
+            .command
+            .as_deref()
+            .ok_or_else(|| anyhow!("Engine spec without cmd"))?;
+        let mut engine = ExternalEngine::start(command)?;
+        for (name, value) in &spec.options {
+            engine.set_option(name, value)?;
+        }
+        engine.ready()?;
+        Ok(engine)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.send(&format!("setoption name {} value {}", name, value))
+    }
+
+    pub fn new_game(&mut self) -> Result<(), anyhow::Error> {
+        self.send("ucinewgame")?;
+        self.ready()
+    }
+
+    pub fn ready(&mut self) -> Result<(), anyhow::Error> {
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.next_line(deadline)?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    // sends `position <position>` and `go <go>` and waits for `bestmove`. an engine that
+    // has not answered by `timeout` is told to stop and the wait fails
+    pub fn go(
+        &mut self,
+        position: &str,
+        go: &str,
+        timeout: Option<Duration>,
+    ) -> Result<EngineReply, anyhow::Error> {
+        self.send(&format!("position {}", position))?;
+        self.send(&format!("go {}", go))?;
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut score = None;
+        loop {
+            let line = match deadline {
+                Some(deadline) => match self.next_line(deadline) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        self.send("stop").ok();
+                        return Err(e);
+                    }
+                },
+                None => self
+                    .lines
+                    .recv()
+                    .map_err(|_| anyhow!("{} exited", self.name))?,
+            };
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    if let Some(s) = parse_score(&line) {
+                        score = Some(s);
+                    }
+                }
+                Some("bestmove") => {
+                    let best_move = tokens
+                        .next()
+                        .ok_or_else(|| anyhow!("{} sent an empty bestmove", self.name))?;
+                    return Ok(EngineReply {
+                        best_move: best_move.to_string(),
+                        score,
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), anyhow::Error> {
+        writeln!(self.stdin, "{}", command)?;
+        Ok(self.stdin.flush()?)
+    }
+
+    fn next_line(&self, deadline: Instant) -> Result<String, anyhow::Error> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.lines.recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(anyhow!("{} did not answer in time", self.name)),
+            Err(RecvTimeoutError::Disconnected) => Err(anyhow!("{} exited", self.name)),
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        self.send("quit").ok();
+        // give it a moment to exit on its own
+        for _ in 0..20 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+// how long an opponent searching to a depth or node count may take, and how far past its
+// move time it may go
+const OPPONENT_TIMEOUT: Duration = Duration::from_secs(60);
+const TIMEOUT_SLACK: Duration = Duration::from_secs(5);
+
+// an external engine playing against the server's users. it is started on first use and
+// restarted after it crashed or hung
+pub struct Opponent {
+    spec: EngineSpec,
+    engine: Option<ExternalEngine>,
+}
+
+impl Opponent {
+    pub fn new(spec: EngineSpec) -> Opponent {
+        Opponent { spec, engine: None }
+    }
+
+    // asks for a move in `position` (the arguments of the UCI position command), searching
+    // to the depth, node count or move time of `limits`, or on its clock. only its own clock
+    // is known here, it is sent for both sides
+    pub fn play(&mut self, position: &str, limits: &Limits) -> Result<EngineReply, anyhow::Error> {
+        let engine = match self.engine.as_mut() {
+            Some(engine) => engine,
+            None => self.engine.insert(ExternalEngine::from_spec(&self.spec)?),
+        };
+
+        let mut go = Vec::new();
+        if let Some(depth) = limits.depth {
+            go.push(format!("depth {}", depth));
+        }
+        if let Some(nodes) = limits.nodes {
+            go.push(format!("nodes {}", nodes));
+        }
+        let timeout = match (limits.movetime, limits.time) {
+            (Some(movetime), _) => {
+                go.push(format!("movetime {}", movetime.as_millis()));
+                movetime + TIMEOUT_SLACK
+            }
+            (None, Some(time)) => {
+                let (time, increment) = (time.as_millis(), limits.increment.as_millis());
+                go.push(format!(
+                    "wtime {} btime {} winc {} binc {}",
+                    time, time, increment, increment
+                ));
+                if let Some(moves_to_go) = limits.moves_to_go {
+                    go.push(format!("movestogo {}", moves_to_go));
+                }
+                Duration::from_millis(time as u64) + TIMEOUT_SLACK
+            }
+            (None, None) => OPPONENT_TIMEOUT,
+        };
+
+        let reply = engine.go(position, &go.join(" "), Some(timeout));
+        if reply.is_err() {
+            self.engine = None;
+        }
+        reply
+    }
+}
+
+// "score cp <x>" or "score mate <n>" of an info line
+fn parse_score(line: &str) -> Option<i32> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let at = tokens.iter().position(|&t| t == "score")?;
+    let value: i32 = tokens.get(at + 2)?.parse().ok()?;
+    match *tokens.get(at + 1)? {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(MATE - 2 * value + 1),
+        "mate" => Some(-MATE - 2 * value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::mate_in;
+
+    #[test]
+    fn test_engine_spec() {
+        let spec = EngineSpec::parse("name=sf,cmd=/usr/bin/stockfish,Skill Level=3").unwrap();
+        assert_eq!(spec.name.as_deref(), Some("sf"));
+        assert_eq!(spec.command.as_deref(), Some("/usr/bin/stockfish"));
+        assert_eq!(
+            spec.options,
+            vec![("Skill Level".to_string(), "3".to_string())]
+        );
+        assert!(EngineSpec::parse("stockfish").is_err());
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(
+            parse_score("info depth 5 score cp -31 nodes 1000 pv e2e4"),
+            Some(-31)
+        );
+        let mate = parse_score("info depth 9 score mate 3 pv a1a8").unwrap();
+        assert_eq!(mate_in(mate), Some(3));
+        let mated = parse_score("info depth 9 score mate -2 pv a1a8").unwrap();
+        assert_eq!(mate_in(mated), Some(-2));
+        assert_eq!(parse_score("info string hello"), None);
+    }
+}
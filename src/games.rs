@@ -0,0 +1,1126 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use tokio::sync::broadcast;
+
+use crate::chat::{self, ChatFilter, Filters, Message, Room, Verdict};
+use crate::chess::{Kind, Move, Outcome, Position, State};
+use crate::clock::{Clock, Reading, TimeControl};
+use crate::eval::EvalParams;
+use crate::external::Opponent;
+use crate::nnue::Network;
+use crate::search::{SearchConfig, Searcher};
+use crate::skill::Skill;
+use crate::storage::{self, Storage, StoredGame};
+use crate::tablebase::Tablebase;
+use crate::timeman::Limits;
+use crate::users::Users;
+
+// who makes the moves of one side of a game
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Player {
+    // moves are posted through the API
+    Human,
+    // the server's own engine at a strength level
+    Engine(Skill),
+    // the engine configured with EXTERNAL_ENGINE
+    External,
+}
+
+impl Player {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Player::Human => "human",
+            Player::Engine(_) => "engine",
+            Player::External => "external",
+        }
+    }
+}
+
+// events buffered for slow followers, those further behind catch up from the game's log
+const EVENT_BUFFER: usize = 64;
+
+// how a game ended
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ending {
+    // none for a draw
+    pub winner: Option<Kind>,
+    pub reason: String,
+    // aborted games have no result and do not count for ratings
+    pub aborted: bool,
+}
+
+impl Ending {
+    // a win for `winner`, or a draw
+    fn new(winner: Option<Kind>, reason: String) -> Ending {
+        Ending {
+            winner,
+            reason,
+            aborted: false,
+        }
+    }
+
+    pub fn result(&self) -> &'static str {
+        match self.winner {
+            _ if self.aborted => "*",
+            Some(Kind::White) => "1-0",
+            Some(Kind::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+impl From<Outcome> for Ending {
+    fn from(outcome: Outcome) -> Self {
+        Ending::new(outcome.winner(), outcome.reason().to_string())
+    }
+}
+
+// what a seated player can do in a game besides moving
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Resign,
+    // ends a game before both sides moved, without a result
+    Abort,
+    // an offer stands until the opponent moves, and offering back accepts it
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    // claims a draw by threefold repetition or the fifty move rule
+    ClaimDraw,
+}
+
+// what happens in a game, sent to everyone following it, with the clock of games that
+// have one
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // the move of ply `ply` (the first move is ply 1), the position after it and when it
+    // was played in unix milliseconds
+    Move {
+        ply: usize,
+        mv: Move,
+        san: String,
+        fen: String,
+        clock: Option<Reading>,
+        at: u64,
+    },
+    End {
+        ending: Ending,
+        clock: Option<Reading>,
+    },
+    // a player took the seat of `side`
+    Join {
+        side: Kind,
+    },
+    // `side` offered a draw
+    Offer {
+        side: Kind,
+    },
+    // `side` declined the draw offered
+    Decline {
+        side: Kind,
+    },
+    // a message in one of the game's chat rooms
+    Chat(Message),
+}
+
+// a game on the server: where it started, the moves played since and who plays each side
+pub struct Game {
+    id: u64,
+    start: State,
+    state: State,
+    moves: Vec<Move>,
+    white: Player,
+    black: Player,
+    // what the engines of this game search per move when it has no clock
+    limits: Limits,
+    clock: Option<Clock>,
+    ending: Option<Ending>,
+    // every event so far, an event's id is its place in the log counting from 1
+    log: Vec<Event>,
+    events: broadcast::Sender<(usize, Event)>,
+    // where the events are recorded, if anywhere
+    storage: Option<Arc<Storage>>,
+    // the secret tokens of the players seated at white and black, and the names of the
+    // registered users among them
+    seats: [Option<String>; 2],
+    users: [Option<String>; 2],
+    // rated games are between two registered users, whose ratings change once it ends
+    rated: bool,
+    // where finished games go into the players' histories, if anywhere
+    accounts: Option<Arc<Users>>,
+    // the side whose draw offer stands
+    draw_offer: Option<Kind>,
+}
+
+impl Game {
+    pub fn new(
+        id: u64,
+        start: State,
+        white: Player,
+        black: Player,
+        limits: Limits,
+        time_control: Option<TimeControl>,
+    ) -> Game {
+        Game {
+            id,
+            state: start.clone(),
+            ending: start.outcome().map(Ending::from),
+            start,
+            moves: Vec::new(),
+            white,
+            black,
+            limits,
+            clock: time_control.map(Clock::new),
+            log: Vec::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            storage: None,
+            seats: [None, None],
+            users: [None, None],
+            rated: false,
+            accounts: None,
+            draw_offer: None,
+        }
+    }
+
+    // the game as it was recorded, its clock as it was after the last move. the time the
+    // server was down is not taken off the clock
+    fn restore(stored: StoredGame) -> Result<Game, anyhow::Error> {
+        let mut game = Game::new(
+            stored.id,
+            stored.start,
+            stored.white,
+            stored.black,
+            stored.limits,
+            stored.time_control,
+        );
+        game.seats = stored.seats;
+        game.users = stored.users;
+        game.rated = stored.rated;
+        // joins, draw offers, messages and the end go back between the moves they came between
+        let mut events = stored.events.into_iter().peekable();
+        for (ply, stored) in stored.moves.iter().enumerate() {
+            while let Some((_, event)) = events.next_if(|(sent, _)| *sent <= ply) {
+                game.replay(event);
+            }
+            game.play_at(&stored.uci, stored.at)?;
+            if let (Some(clock), Some([white, black])) = (game.clock.as_mut(), stored.clock) {
+                clock.set_remaining(white, black);
+                // the move's event shows the clock as it was recorded, also when it ended the game
+                let reading = clock.read(Instant::now());
+                let played = game.log.iter_mut().rev();
+                if let Some(Event::Move { clock, .. }) = played
+                    .into_iter()
+                    .find(|event| matches!(event, Event::Move { .. }))
+                {
+                    *clock = Some(reading);
+                }
+            }
+        }
+        for (_, event) in events {
+            game.replay(event);
+        }
+        Ok(game)
+    }
+
+    // sends a recorded event again, a draw offer stands until it is declined or moved past.
+    // a game ended by its last move ended again when that move was replayed
+    fn replay(&mut self, event: Event) {
+        match event {
+            Event::Offer { side } => self.draw_offer = Some(side),
+            Event::Decline { .. } => self.draw_offer = None,
+            Event::End { ending, .. } => {
+                if self.ending.is_none() {
+                    self.end(ending);
+                }
+                return;
+            }
+            _ => (),
+        }
+        self.emit(event);
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn start(&self) -> &State {
+        &self.start
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn time_control(&self) -> Option<&TimeControl> {
+        self.clock.as_ref().map(|clock| clock.control())
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn player(&self, side: Kind) -> Player {
+        match side {
+            Kind::White => self.white,
+            Kind::Black => self.black,
+        }
+    }
+
+    pub fn rated(&self) -> bool {
+        self.rated
+    }
+
+    // the registered user seated at `side`
+    pub fn user(&self, side: Kind) -> Option<&str> {
+        self.users[side.index()].as_deref()
+    }
+
+    pub fn draw_offer(&self) -> Option<Kind> {
+        self.draw_offer
+    }
+
+    pub fn ending(&self) -> Option<&Ending> {
+        self.ending.as_ref()
+    }
+
+    pub fn clock_reading(&self) -> Option<Reading> {
+        self.clock.as_ref().map(|clock| clock.read(Instant::now()))
+    }
+
+    // true while the side to move is played through the API
+    pub fn human_to_move(&self) -> bool {
+        self.player(self.state.side) == Player::Human && self.ending.is_none()
+    }
+
+    // human sides nobody sat down at yet
+    pub fn open_seats(&self) -> Vec<Kind> {
+        [Kind::White, Kind::Black]
+            .into_iter()
+            .filter(|&side| {
+                self.player(side) == Player::Human && self.seats[side.index()].is_none()
+            })
+            .collect()
+    }
+
+    // seats a player, the registered `user` if logged in, at `side` or at the first open
+    // seat and returns the side and the token that lets them move it
+    pub fn join(
+        &mut self,
+        side: Option<Kind>,
+        user: Option<&str>,
+    ) -> Result<(Kind, String), anyhow::Error> {
+        if self.rated {
+            match user {
+                None => return Err(anyhow!("Rated games are for registered users")),
+                Some(user) if self.users.iter().any(|u| u.as_deref() == Some(user)) => {
+                    return Err(anyhow!("You already play in this game"))
+                }
+                Some(_) => (),
+            }
+        }
+        let open = self.open_seats();
+        let side = match side {
+            Some(side) if open.contains(&side) => side,
+            Some(_) => return Err(anyhow!("The seat is taken or played by an engine")),
+            None => *open.first().ok_or_else(|| anyhow!("No open seat"))?,
+        };
+        let token = secret_token();
+        self.seats[side.index()] = Some(token.clone());
+        self.users[side.index()] = user.map(|user| user.to_string());
+        if let Some(storage) = &self.storage {
+            storage.record_join(self.id, side, &token, user);
+        }
+        self.emit(Event::Join { side });
+        Ok((side, token))
+    }
+
+    // the side `token` was given for
+    pub fn seat(&self, token: &str) -> Option<Kind> {
+        [Kind::White, Kind::Black]
+            .into_iter()
+            .find(|side| self.seats[side.index()].as_deref() == Some(token))
+    }
+
+    // what an engine searches for its move: the game's limits, or its clock once it has one
+    pub fn engine_limits(&self) -> Limits {
+        let clock = match &self.clock {
+            Some(clock) => clock,
+            None => return self.limits.clone(),
+        };
+        let side = self.state.side;
+        Limits {
+            time: Some(clock.remaining(side, Instant::now())),
+            increment: clock.control().increment,
+            moves_to_go: clock.moves_to_go(side),
+            ..Limits::default()
+        }
+    }
+
+    // ends the game when the side to move ran out of time. it loses, unless the opponent
+    // could not mate anyway
+    pub fn check_flag(&mut self) -> bool {
+        let side = self.state.side;
+        let flagged = self.ending.is_none()
+            && self
+                .clock
+                .as_ref()
+                .is_some_and(|clock| clock.flagged(side, Instant::now()));
+        if flagged {
+            let color = title(side);
+            let ending = if self.state.has_mating_material(side.opposite()) {
+                Ending::new(Some(side.opposite()), format!("{} loses on time", color))
+            } else {
+                let reason = format!("{} ran out of time against insufficient material", color);
+                Ending::new(None, reason)
+            };
+            self.end(ending);
+        }
+        flagged
+    }
+
+    // resigns, aborts or offers, answers or claims a draw for the player seated at `side`
+    pub fn act(&mut self, side: Kind, action: Action) -> Result<(), anyhow::Error> {
+        self.check_flag();
+        if let Some(ending) = &self.ending {
+            return Err(anyhow!("The game is over: {}", ending.reason));
+        }
+        let offered = self.draw_offer == Some(side.opposite());
+        match action {
+            Action::Resign => {
+                let reason = format!("{} resigns", title(side));
+                self.end(Ending::new(Some(side.opposite()), reason));
+            }
+            Action::Abort => {
+                if self.moves.len() >= 2 {
+                    return Err(anyhow!(
+                        "Only games where a side has not moved can be aborted"
+                    ));
+                }
+                self.end(Ending {
+                    aborted: true,
+                    ..Ending::new(None, format!("{} aborts", title(side)))
+                });
+            }
+            Action::OfferDraw | Action::AcceptDraw if offered => {
+                self.end(Ending::new(None, "Draw agreed".to_string()));
+            }
+            Action::OfferDraw => {
+                if self.draw_offer == Some(side) {
+                    return Err(anyhow!("The draw offer stands"));
+                }
+                self.draw_offer = Some(side);
+                self.emit(Event::Offer { side });
+            }
+            Action::AcceptDraw => return Err(anyhow!("No draw was offered")),
+            Action::DeclineDraw => {
+                if !offered {
+                    return Err(anyhow!("No draw was offered"));
+                }
+                self.draw_offer = None;
+                self.emit(Event::Decline { side });
+            }
+            Action::ClaimDraw => {
+                let outcome = self
+                    .state
+                    .claimable_draw()
+                    .ok_or_else(|| anyhow!("There is no draw to claim"))?;
+                self.end(Ending::from(outcome));
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self, ending: Ending) {
+        if let Some(clock) = self.clock.as_mut() {
+            clock.stop(Instant::now());
+        }
+        self.ending = Some(ending.clone());
+        self.draw_offer = None;
+        if let Some(accounts) = &self.accounts {
+            accounts.finish(self.id, &self.users, self.rated, &ending);
+        }
+        self.emit(Event::End {
+            ending,
+            clock: self.clock_reading(),
+        });
+    }
+
+    // the events from now on with their ids
+    pub fn subscribe(&self) -> broadcast::Receiver<(usize, Event)> {
+        self.events.subscribe()
+    }
+
+    // the events after the one with id `last`, all of them for 0
+    pub fn events_since(&self, last: usize) -> Vec<(usize, Event)> {
+        self.log
+            .iter()
+            .enumerate()
+            .skip(last)
+            .map(|(i, event)| (i + 1, event.clone()))
+            .collect()
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(storage) = &self.storage {
+            storage.record_event(self.id, &event);
+        }
+        self.log.push(event.clone());
+        // nobody following the game is not an error
+        self.events.send((self.log.len(), event)).ok();
+    }
+
+    // plays `mv` in coordinate notation for the side to move. a pawn reaching the last
+    // rank becomes a queen unless the move names another piece
+    pub fn play(&mut self, mv: &str) -> Result<Move, anyhow::Error> {
+        self.play_at(mv, storage::now())
+    }
+
+    // plays `mv` as if at unix time `at` in milliseconds
+    fn play_at(&mut self, mv: &str, at: u64) -> Result<Move, anyhow::Error> {
+        self.check_flag();
+        if let Some(ending) = &self.ending {
+            return Err(anyhow!("The game is over: {}", ending.reason));
+        }
+        let parsed = self
+            .state
+            .parse_move(mv)
+            .or_else(|| self.state.parse_move(&format!("{}q", mv)))
+            .ok_or_else(|| anyhow!("Illegal move {}", mv))?;
+        let san = self.state.san(&parsed);
+        let side = self.state.side;
+        self.state.make_move(parsed);
+        self.moves.push(parsed);
+        // moving declines the opponent's draw offer
+        if self.draw_offer == Some(side.opposite()) {
+            self.draw_offer = None;
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.punch(side, Instant::now());
+        }
+
+        self.emit(Event::Move {
+            ply: self.moves.len(),
+            mv: parsed,
+            san,
+            fen: self.state.to_fen(),
+            clock: self.clock_reading(),
+            at,
+        });
+        if let Some(outcome) = self.state.outcome() {
+            self.end(Ending::from(outcome));
+        }
+        Ok(parsed)
+    }
+
+    // the position after `ply` moves, none past the last move
+    pub fn position(&self, ply: usize) -> Option<State> {
+        let mut state = self.start.clone();
+        for mv in self.moves.get(..ply)? {
+            state.make_move(*mv);
+        }
+        Some(state)
+    }
+
+    // adds a message to the game's chat as it is, `Registry::chat` filters it first
+    pub fn say(&mut self, message: Message) {
+        self.emit(Event::Chat(message));
+    }
+
+    // the messages of `room`, oldest first
+    pub fn chat(&self, room: Room) -> Vec<&Message> {
+        self.log
+            .iter()
+            .filter_map(|event| match event {
+                Event::Chat(message) if message.room == room => Some(message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // the events of the moves played so far
+    pub fn history(&self) -> Vec<&Event> {
+        self.log
+            .iter()
+            .filter(|event| matches!(event, Event::Move { .. }))
+            .collect()
+    }
+
+    // squares the piece on `from` can legally move to
+    pub fn destinations(&self, from: Position) -> Vec<Position> {
+        let mut destinations: Vec<Position> = self
+            .state
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| mv.from == from)
+            .map(|mv| mv.to)
+            .collect();
+        // promotions list the same square once per piece
+        destinations.dedup();
+        destinations
+    }
+
+    // arguments of the UCI position command for the current position
+    pub fn uci_position(&self) -> String {
+        let start = self.start.to_fen();
+        let mut position = if start == State::default().to_fen() {
+            "startpos".to_string()
+        } else {
+            format!("fen {}", start)
+        };
+        if !self.moves.is_empty() {
+            let moves: Vec<String> = self.moves.iter().map(|mv| mv.to_string()).collect();
+            position.push_str(" moves ");
+            position.push_str(&moves.join(" "));
+        }
+        position
+    }
+}
+
+// what the server's engines play with, shared by all games
+#[derive(Clone, Default)]
+pub struct Engines {
+    pub tablebase: Option<Arc<Tablebase>>,
+    pub network: Option<Arc<Network>>,
+    pub params: Arc<EvalParams>,
+    pub external: Option<Arc<Mutex<Opponent>>>,
+}
+
+impl Engines {
+    // when an engine has the move it thinks on a thread of its own and plays once done,
+    // which may hand the move to the other engine of an engine game
+    fn advance(&self, game: &Arc<Mutex<Game>>) {
+        let (id, player, state, position, limits, ply) = {
+            let game = game.lock().unwrap();
+            let player = game.player(game.state.side);
+            if player == Player::Human || game.ending.is_some() {
+                return;
+            }
+            (
+                game.id,
+                player,
+                game.state.clone(),
+                game.uci_position(),
+                game.engine_limits(),
+                game.moves.len(),
+            )
+        };
+
+        let engines = self.clone();
+        let game = game.clone();
+        thread::spawn(move || {
+            let best_move = engines.best_move(player, &state, &position, &limits);
+            let mut locked = game.lock().unwrap();
+            // humans cannot move while an engine has the move, but the game may still
+            // have changed by the time it answers
+            if locked.moves.len() != ply {
+                return;
+            }
+            match best_move.and_then(|mv| locked.play(&mv)) {
+                Ok(mv) => {
+                    info!("game {}: {} plays {}", id, player.name(), mv);
+                    drop(locked);
+                    engines.advance(&game);
+                }
+                Err(e) => warn!("game {}: {}", id, e),
+            }
+        });
+    }
+
+    // the move `player` chooses in `state`, in coordinate notation
+    fn best_move(
+        &self,
+        player: Player,
+        state: &State,
+        position: &str,
+        limits: &Limits,
+    ) -> Result<String, anyhow::Error> {
+        match player {
+            Player::Human => Err(anyhow!("Humans move through the API")),
+            Player::Engine(skill) => {
+                let mut searcher = Searcher::new(state.clone(), SearchConfig::default());
+                searcher.set_tablebase(self.tablebase.clone());
+                searcher.set_network(self.network.clone());
+                searcher.set_params((*self.params).clone());
+                let result = skill.search(&mut searcher, limits);
+                result
+                    .best_move
+                    .map(|mv| mv.to_string())
+                    .ok_or_else(|| anyhow!("The engine found no move"))
+            }
+            Player::External => {
+                let external = self
+                    .external
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No external engine is configured"))?;
+                let reply = external.lock().unwrap().play(position, limits)?;
+                Ok(reply.best_move)
+            }
+        }
+    }
+}
+
+// "White" or "Black", as reasons start
+fn title(side: Kind) -> &'static str {
+    match side {
+        Kind::White => "White",
+        Kind::Black => "Black",
+    }
+}
+
+// a secret for a seat: 128 bits from the OS's random number generator
+pub fn secret_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("The OS provides random numbers");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// all games of the server by id
+pub struct Registry {
+    games: Mutex<HashMap<u64, Arc<Mutex<Game>>>>,
+    next_id: AtomicU64,
+    engines: Engines,
+    storage: Option<Arc<Storage>>,
+    users: Arc<Users>,
+    // what every chat message passes before it is shown
+    chat_filter: Filters,
+}
+
+impl Registry {
+    pub fn new(engines: Engines) -> Registry {
+        Registry {
+            games: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            engines,
+            storage: None,
+            users: Arc::new(Users::default()),
+            chat_filter: Filters::default(),
+        }
+    }
+
+    // adds a filter after the ones chat messages already pass
+    pub fn add_chat_filter(&mut self, filter: impl ChatFilter + 'static) {
+        self.chat_filter.push(filter);
+    }
+
+    // a registry recording its games in `storage` and its players' results in `users`, with
+    // the games recorded there before. engines with the move in unfinished ones pick up where
+    // they were
+    pub fn with_storage(
+        engines: Engines,
+        storage: Storage,
+        users: Users,
+    ) -> Result<Registry, anyhow::Error> {
+        let storage = Arc::new(storage);
+        let stored = storage.load()?;
+        let registry = Registry {
+            next_id: AtomicU64::new(stored.iter().map(|game| game.id + 1).max().unwrap_or(1)),
+            storage: Some(storage.clone()),
+            users: Arc::new(users),
+            ..Registry::new(engines)
+        };
+        for stored in stored {
+            let id = stored.id;
+            match Game::restore(stored) {
+                Ok(mut game) => {
+                    game.storage = Some(storage.clone());
+                    game.accounts = Some(registry.users.clone());
+                    let game = Arc::new(Mutex::new(game));
+                    registry.games.lock().unwrap().insert(id, game.clone());
+                    registry.advance(&game);
+                }
+                Err(e) => warn!("game {}: {}", id, e),
+            }
+        }
+        Ok(registry)
+    }
+
+    // adds a game and lets an engine start it when it has the first move
+    pub fn create(
+        &self,
+        start: State,
+        white: Player,
+        black: Player,
+        limits: Limits,
+        time_control: Option<TimeControl>,
+        rated: bool,
+    ) -> Result<Arc<Mutex<Game>>, anyhow::Error> {
+        if self.engines.external.is_none() && [white, black].contains(&Player::External) {
+            return Err(anyhow!("No external engine is configured"));
+        }
+        if rated && (white != Player::Human || black != Player::Human) {
+            return Err(anyhow!("Rated games are between humans"));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut game = Game::new(id, start, white, black, limits, time_control);
+        game.rated = rated;
+        game.accounts = Some(self.users.clone());
+        if let Some(storage) = &self.storage {
+            storage.record_game(&game);
+            game.storage = Some(storage.clone());
+        }
+        let game = Arc::new(Mutex::new(game));
+        self.games.lock().unwrap().insert(id, game.clone());
+        self.advance(&game);
+        Ok(game)
+    }
+
+    pub fn users(&self) -> &Arc<Users> {
+        &self.users
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Mutex<Game>>> {
+        self.games.lock().unwrap().get(&id).cloned()
+    }
+
+    // all games, oldest first
+    pub fn list(&self) -> Vec<Arc<Mutex<Game>>> {
+        let games = self.games.lock().unwrap();
+        let mut ids: Vec<&u64> = games.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| games[id].clone()).collect()
+    }
+
+    // plays a move for the player holding `token`, an engine opponent answers in the
+    // background
+    pub fn play(
+        &self,
+        game: &Arc<Mutex<Game>>,
+        token: &str,
+        mv: &str,
+    ) -> Result<Move, anyhow::Error> {
+        let played = {
+            let mut game = game.lock().unwrap();
+            if !game.human_to_move() {
+                return Err(anyhow!("It is not a human's move"));
+            }
+            if game.seat(token) != Some(game.state.side) {
+                return Err(anyhow!("It is not your move"));
+            }
+            game.play(mv)?
+        };
+        self.advance(game);
+        Ok(played)
+    }
+
+    // resigns, aborts or handles a draw for the player holding `token`
+    pub fn act(
+        &self,
+        game: &Arc<Mutex<Game>>,
+        token: &str,
+        action: Action,
+    ) -> Result<(), anyhow::Error> {
+        let mut game = game.lock().unwrap();
+        let side = game
+            .seat(token)
+            .ok_or_else(|| anyhow!("You have no seat in this game"))?;
+        game.act(side, action)
+    }
+
+    // who holds `token` in the chat of `game`: a player's seat token, or the session of a
+    // player's user, speaks in the players' room as that side, another user's session in
+    // the spectators'. none for anyone else
+    pub fn speaker(&self, game: &Game, token: &str) -> Option<(Room, String, Option<Kind>)> {
+        let user = self.users.session(token);
+        let side = game.seat(token).or_else(|| {
+            [Kind::White, Kind::Black]
+                .into_iter()
+                .find(|&side| user.is_some() && game.user(side) == user.as_deref())
+        });
+        match (side, user) {
+            (Some(side), _) => {
+                let author = game.user(side).unwrap_or(title(side)).to_string();
+                Some((Room::Players, author, Some(side)))
+            }
+            (None, Some(user)) => Some((Room::Spectators, user, None)),
+            (None, None) => None,
+        }
+    }
+
+    // posts `text` to the chat of `game` for whoever holds `token`, and returns the message
+    // as the filter let it through
+    pub fn chat(
+        &self,
+        game: &Arc<Mutex<Game>>,
+        token: &str,
+        text: &str,
+    ) -> Result<Message, anyhow::Error> {
+        let text = text.trim();
+        if text.is_empty() || text.chars().count() > chat::MAX_LENGTH {
+            return Err(anyhow!("Messages are 1 to {} characters", chat::MAX_LENGTH));
+        }
+        let (room, author, side) = self
+            .speaker(&game.lock().unwrap(), token)
+            .ok_or_else(|| anyhow!("Log in to chat"))?;
+        let mut message = Message {
+            room,
+            author,
+            side,
+            text: text.to_string(),
+            at: storage::now(),
+        };
+        // filters may take a while, the game is not locked meanwhile
+        match self.chat_filter.check(&message) {
+            Verdict::Keep => (),
+            Verdict::Mask(text) => message.text = text,
+            Verdict::Drop => return Err(anyhow!("The message was not accepted")),
+        }
+        game.lock().unwrap().say(message.clone());
+        Ok(message)
+    }
+
+    // ends the games whose side to move ran out of time, the server calls this regularly
+    // since nobody may move in a game that is lost on time
+    pub fn check_clocks(&self) {
+        for game in self.list() {
+            game.lock().unwrap().check_flag();
+        }
+    }
+
+    // lets an engine answer when it has the move in `game`
+    pub fn advance(&self, game: &Arc<Mutex<Game>>) {
+        self.engines.advance(game);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_play() {
+        let start = State::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let mut game = Game::new(
+            1,
+            start,
+            Player::Human,
+            Player::Human,
+            Limits::default(),
+            None,
+        );
+        let mut events = game.subscribe();
+        assert_eq!(game.destinations((1, 0)), vec![(0, 0)]);
+        assert!(game.play("a7a6").is_err());
+        // promotes to a queen unless told otherwise
+        assert_eq!(game.play("a7a8").unwrap().to_string(), "a7a8q");
+        game.play("e8d7").unwrap();
+        assert!(game.play("d7d6").is_err());
+        assert!(matches!(
+            events.try_recv(),
+            Ok((1, Event::Move { ply: 1, .. }))
+        ));
+        assert_eq!(
+            game.uci_position(),
+            "fen 4k3/P7/8/8/8/8/8/4K3 w - - 0 1 moves a7a8q e8d7"
+        );
+        // a follower that saw the first move catches up on the second
+        let missed = game.events_since(1);
+        assert_eq!(missed.len(), 1);
+        assert!(matches!(missed[0], (2, Event::Move { ply: 2, ref san, .. }) if san == "Kd7"));
+
+        assert_eq!(game.history().len(), 2);
+        assert_eq!(
+            game.position(1).unwrap().to_fen(),
+            "Q3k3/8/8/8/8/8/8/4K3 b - - 0 1"
+        );
+        assert_eq!(game.position(0).unwrap().to_fen(), game.start().to_fen());
+        assert!(game.position(3).is_none());
+    }
+
+    #[test]
+    fn test_seats() {
+        let registry = Registry::new(Engines::default());
+        let game = registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+                false,
+            )
+            .unwrap();
+        let (side, black) = game.lock().unwrap().join(Some(Kind::Black), None).unwrap();
+        assert_eq!(side, Kind::Black);
+        assert!(game.lock().unwrap().join(Some(Kind::Black), None).is_err());
+        assert_eq!(game.lock().unwrap().open_seats(), vec![Kind::White]);
+
+        // only the side to move's token moves
+        assert!(registry.play(&game, &black, "e2e4").is_err());
+        let (_, white) = game.lock().unwrap().join(None, None).unwrap();
+        assert_ne!(white, black);
+        registry.play(&game, &white, "e2e4").unwrap();
+        assert!(registry.play(&game, "guess", "e7e5").is_err());
+        registry.play(&game, &black, "e7e5").unwrap();
+        assert!(game.lock().unwrap().join(None, None).is_err());
+    }
+
+    #[test]
+    fn test_chat() {
+        let mut registry = Registry::new(Engines::default());
+        registry.add_chat_filter(chat::WordFilter::from_lists("darn", "spam"));
+        registry.users().register("carol", "password").unwrap();
+        let carol = registry.users().login("carol", "password").unwrap();
+        let game = registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+                false,
+            )
+            .unwrap();
+        let (_, white) = game.lock().unwrap().join(Some(Kind::White), None).unwrap();
+
+        let message = registry.chat(&game, &white, " good luck ").unwrap();
+        assert_eq!(message.room, Room::Players);
+        assert_eq!(message.side, Some(Kind::White));
+        assert_eq!(message.text, "good luck");
+        registry.chat(&game, &carol, "darn, nice move").unwrap();
+        assert!(registry.chat(&game, &carol, "spam").is_err());
+        assert!(registry.chat(&game, "guess", "hello").is_err());
+        assert!(registry.chat(&game, &white, " ").is_err());
+
+        let game = game.lock().unwrap();
+        assert_eq!(game.chat(Room::Players).len(), 1);
+        let spectators = game.chat(Room::Spectators);
+        assert_eq!(spectators.len(), 1);
+        assert_eq!(spectators[0].author, "carol");
+        assert_eq!(spectators[0].text, "****, nice move");
+    }
+
+    #[test]
+    fn test_resign_and_draws() {
+        let human_game = |fen: &str| {
+            let start = State::from_fen(fen).unwrap();
+            Game::new(
+                1,
+                start,
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+            )
+        };
+        let mut game = human_game("4k3/8/8/8/8/8/8/R3K3 w - - 99 60");
+        game.act(Kind::White, Action::OfferDraw).unwrap();
+        assert!(game.act(Kind::White, Action::AcceptDraw).is_err());
+        // the offer stands while white moves and goes once black does
+        game.play("a1a2").unwrap();
+        assert_eq!(game.draw_offer(), Some(Kind::White));
+        game.act(Kind::Black, Action::DeclineDraw).unwrap();
+        assert!(game.act(Kind::Black, Action::DeclineDraw).is_err());
+        game.act(Kind::White, Action::OfferDraw).unwrap();
+        game.play("e8d8").unwrap();
+        assert_eq!(game.draw_offer(), None);
+        assert!(game.act(Kind::White, Action::Abort).is_err());
+        // the fifty moves are up
+        game.act(Kind::Black, Action::ClaimDraw).unwrap();
+        assert_eq!(game.ending().unwrap().reason, "Fifty move rule");
+        assert!(game.act(Kind::White, Action::Resign).is_err());
+
+        let mut game = human_game("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        assert!(game.act(Kind::White, Action::ClaimDraw).is_err());
+        game.act(Kind::Black, Action::OfferDraw).unwrap();
+        // offering back accepts
+        game.act(Kind::White, Action::OfferDraw).unwrap();
+        assert_eq!(game.ending().unwrap().result(), "1/2-1/2");
+
+        let mut game = human_game("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        game.play("a1a2").unwrap();
+        game.act(Kind::Black, Action::Abort).unwrap();
+        assert_eq!(game.ending().unwrap().result(), "*");
+
+        let mut game = human_game("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        game.act(Kind::White, Action::Resign).unwrap();
+        assert_eq!(game.ending().unwrap().winner, Some(Kind::Black));
+        assert!(matches!(game.events_since(0)[0], (1, Event::End { .. })));
+    }
+
+    #[test]
+    fn test_flag_fall() {
+        let control = TimeControl {
+            base: Duration::from_millis(20),
+            increment: Duration::ZERO,
+            delay: Duration::ZERO,
+            moves: None,
+        };
+        for (fen, winner) in [
+            ("4k3/8/8/8/8/8/8/r3K3 w - - 0 1", Some(Kind::Black)),
+            // a bare king cannot win on time
+            ("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1", None),
+        ] {
+            let start = State::from_fen(fen).unwrap();
+            let mut game = Game::new(
+                1,
+                start,
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                Some(control),
+            );
+            let moves: Vec<String> = game
+                .state()
+                .legal_moves()
+                .iter()
+                .map(|mv| mv.to_string())
+                .collect();
+            game.play(&moves[0]).unwrap();
+            let reply = game.state().legal_moves()[0].to_string();
+            game.play(&reply).unwrap();
+            assert!(!game.check_flag());
+
+            thread::sleep(Duration::from_millis(30));
+            assert!(game.play(&moves[0]).is_err());
+            assert_eq!(game.ending().unwrap().winner, winner);
+            assert!(!game.human_to_move());
+        }
+    }
+
+    #[test]
+    fn test_engine_answers() {
+        let registry = Registry::new(Engines::default());
+        let game = registry
+            .create(
+                State::default(),
+                Player::Engine(Skill::default()),
+                Player::Human,
+                Limits::depth(1),
+                None,
+                false,
+            )
+            .unwrap();
+        assert!(registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::External,
+                Limits::default(),
+                None,
+                false,
+            )
+            .is_err());
+
+        let started = Instant::now();
+        while game.lock().unwrap().moves().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let id = game.lock().unwrap().id();
+        assert_eq!(
+            registry.get(id).unwrap().lock().unwrap().state().side,
+            Kind::Black
+        );
+        assert_eq!(registry.list().len(), 1);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use tokio::sync::broadcast;
+
+use crate::chess::{Kind, State};
+use crate::clock::TimeControl;
+use crate::games::{Game, Player, Registry};
+use crate::rating::Rating;
+use crate::timeman::Limits;
+
+// lobby events buffered for slow followers, those further behind get the seeks again
+const EVENT_BUFFER: usize = 64;
+
+// the game a player is looking for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Terms {
+    pub time_control: Option<TimeControl>,
+    // the color the player wants to play, either when none
+    pub color: Option<Kind>,
+    pub rated: bool,
+    // the opponents' ratings the player accepts
+    pub min_rating: Option<f64>,
+    pub max_rating: Option<f64>,
+    // the user challenged, none for a seek open to everyone
+    pub to: Option<String>,
+}
+
+// the game a seek started and the seat its owner was given
+#[derive(Clone, Debug, PartialEq)]
+pub struct Started {
+    pub game: u64,
+    pub color: Kind,
+    pub token: String,
+}
+
+// a registered user looking for a game, with their rating when they posted it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Seek {
+    pub id: u64,
+    pub user: String,
+    pub rating: f64,
+    pub terms: Terms,
+    pub started: Option<Started>,
+}
+
+impl Seek {
+    // true when `user` rated `rating` may take the seek up
+    pub fn accepts(&self, user: &str, rating: f64) -> bool {
+        self.user != user
+            && self.terms.to.as_deref().is_none_or(|to| to == user)
+            && self.terms.min_rating.is_none_or(|min| rating >= min)
+            && self.terms.max_rating.is_none_or(|max| rating <= max)
+    }
+
+    // true when two open seeks want the same game, each within the other's ratings
+    fn matches(&self, other: &Seek) -> bool {
+        self.terms.to.is_none()
+            && other.terms.to.is_none()
+            && self.terms.rated == other.terms.rated
+            && self.terms.time_control == other.terms.time_control
+            && (self.terms.color.is_none() || self.terms.color != other.terms.color)
+            && self.accepts(&other.user, other.rating)
+            && other.accepts(&self.user, self.rating)
+    }
+}
+
+// what happens in the lobby, sent to everyone in it
+#[derive(Clone, Debug, PartialEq)]
+pub enum LobbyEvent {
+    // a new open seek or challenge
+    Seek(Seek),
+    // a seek was cancelled, declined or taken
+    Remove {
+        id: u64,
+    },
+    // `user` was seated in a new game, by their seek `seek` if it was theirs
+    Start {
+        user: String,
+        seek: Option<u64>,
+        game: u64,
+        color: Kind,
+        token: String,
+    },
+}
+
+// open seeks and challenges and the games they started. seeks are kept in memory only,
+// the games they start are stored like any other
+pub struct Lobby {
+    registry: Arc<Registry>,
+    seeks: Mutex<BTreeMap<u64, Seek>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<LobbyEvent>,
+}
+
+impl Lobby {
+    pub fn new(registry: Arc<Registry>) -> Lobby {
+        Lobby {
+            registry,
+            seeks: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LobbyEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Seek> {
+        self.seeks.lock().unwrap().get(&id).cloned()
+    }
+
+    // the open seeks, oldest first: those open to everyone and the challenges from or to
+    // `user`
+    pub fn list(&self, user: Option<&str>) -> Vec<Seek> {
+        self.seeks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|seek| seek.started.is_none())
+            .filter(|seek| match &seek.terms.to {
+                None => true,
+                Some(to) => user.is_some_and(|user| user == to || user == seek.user),
+            })
+            .cloned()
+            .collect()
+    }
+
+    // the current rating of `user`
+    pub fn rating(&self, user: &str) -> f64 {
+        self.registry
+            .users()
+            .user(user)
+            .map_or(Rating::default(), |user| user.rating)
+            .rating
+    }
+
+    // posts a seek or challenge for `user`. a seek starts a game right away when an open
+    // seek matches it, the seek returned then says where
+    pub fn seek(&self, user: &str, terms: Terms) -> Result<Seek, anyhow::Error> {
+        if let Some(to) = &terms.to {
+            if to == user || self.registry.users().user(to).is_none() {
+                return Err(anyhow!("There is no user {} to challenge", to));
+            }
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let seek = Seek {
+            id,
+            user: user.to_string(),
+            rating: self.rating(user),
+            terms,
+            started: None,
+        };
+        let mut seeks = self.seeks.lock().unwrap();
+        let matched = seeks
+            .values()
+            .find(|other| other.started.is_none() && other.matches(&seek))
+            .map(|other| other.id);
+        seeks.insert(id, seek.clone());
+        match matched {
+            Some(other) => {
+                if let Err(e) = self.start(&mut seeks, other, user, Some(id)) {
+                    // a seek whose game could not start is not left open to match again
+                    seeks.remove(&id);
+                    return Err(e);
+                }
+            }
+            None => {
+                self.events.send(LobbyEvent::Seek(seek)).ok();
+            }
+        }
+        Ok(seeks[&id].clone())
+    }
+
+    // takes up seek `id` for `user`, and returns the game with the side and token of their
+    // seat
+    pub fn accept(
+        &self,
+        id: u64,
+        user: &str,
+    ) -> Result<(Arc<Mutex<Game>>, Kind, String), anyhow::Error> {
+        let mut seeks = self.seeks.lock().unwrap();
+        let seek = seeks.get(&id).ok_or_else(|| anyhow!("No seek {}", id))?;
+        if seek.started.is_some() {
+            return Err(anyhow!("The seek was taken"));
+        }
+        if !seek.accepts(user, self.rating(user)) {
+            return Err(anyhow!("The seek is not open to you"));
+        }
+        self.start(&mut seeks, id, user, None)
+    }
+
+    // withdraws an open seek, or declines a challenge to `user`
+    pub fn cancel(&self, id: u64, user: &str) -> Result<(), anyhow::Error> {
+        let mut seeks = self.seeks.lock().unwrap();
+        match seeks.get(&id) {
+            Some(seek)
+                if seek.started.is_none()
+                    && (seek.user == user || seek.terms.to.as_deref() == Some(user)) =>
+            {
+                seeks.remove(&id);
+                self.events.send(LobbyEvent::Remove { id }).ok();
+                Ok(())
+            }
+            _ => Err(anyhow!("No seek {} of yours", id)),
+        }
+    }
+
+    // starts the game of seek `id` against `accepter`, whose own seek `theirs` matched it
+    // if that is how they came together
+    fn start(
+        &self,
+        seeks: &mut BTreeMap<u64, Seek>,
+        id: u64,
+        accepter: &str,
+        theirs: Option<u64>,
+    ) -> Result<(Arc<Mutex<Game>>, Kind, String), anyhow::Error> {
+        let seek = seeks[&id].clone();
+        let wanted = theirs.and_then(|theirs| seeks[&theirs].terms.color);
+        let color = seek
+            .terms
+            .color
+            .or(wanted.map(|color| color.opposite()))
+            .unwrap_or_else(random_color);
+        let game = self.registry.create(
+            State::default(),
+            Player::Human,
+            Player::Human,
+            Limits::default(),
+            seek.terms.time_control,
+            seek.terms.rated,
+        )?;
+        let (game_id, owner, theirs_token) = {
+            let mut game = game.lock().unwrap();
+            let (_, owner) = game.join(Some(color), Some(&seek.user))?;
+            let (_, accepter) = game.join(Some(color.opposite()), Some(accepter))?;
+            (game.id(), owner, accepter)
+        };
+        info!(
+            "lobby: {} and {} start game {}",
+            seek.user, accepter, game_id
+        );
+
+        self.events.send(LobbyEvent::Remove { id }).ok();
+        for (user, seek, color, token) in [
+            (seek.user.as_str(), Some(id), color, owner),
+            (accepter, theirs, color.opposite(), theirs_token.clone()),
+        ] {
+            if let Some(seek) = seek.and_then(|seek| seeks.get_mut(&seek)) {
+                seek.started = Some(Started {
+                    game: game_id,
+                    color,
+                    token: token.clone(),
+                });
+            }
+            let event = LobbyEvent::Start {
+                user: user.to_string(),
+                seek,
+                game: game_id,
+                color,
+                token,
+            };
+            self.events.send(event).ok();
+        }
+        Ok((game, color.opposite(), theirs_token))
+    }
+}
+
+fn random_color() -> Kind {
+    if RandomState::new().build_hasher().finish().is_multiple_of(2) {
+        Kind::White
+    } else {
+        Kind::Black
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::Engines;
+
+    fn terms(color: Option<Kind>) -> Terms {
+        Terms {
+            time_control: None,
+            color,
+            rated: true,
+            min_rating: None,
+            max_rating: None,
+            to: None,
+        }
+    }
+
+    #[test]
+    fn test_pairing() {
+        let registry = Arc::new(Registry::new(Engines::default()));
+        for name in ["alice", "bob", "carol"] {
+            registry.users().register(name, "password").unwrap();
+        }
+        let lobby = Lobby::new(registry.clone());
+        let mut events = lobby.subscribe();
+
+        let alice = lobby.seek("alice", terms(Some(Kind::White))).unwrap();
+        // both want white, or bob wants a stronger opponent
+        let bob = lobby.seek("bob", terms(Some(Kind::White))).unwrap();
+        let picky = Terms {
+            min_rating: Some(1600.0),
+            ..terms(None)
+        };
+        let picky = lobby.seek("bob", picky).unwrap();
+        assert_eq!(lobby.list(None).len(), 3);
+        lobby.cancel(bob.id, "bob").unwrap();
+        assert!(lobby.cancel(picky.id, "alice").is_err());
+        lobby.cancel(picky.id, "bob").unwrap();
+
+        let bob = lobby.seek("bob", terms(None)).unwrap();
+        let started = bob.started.unwrap();
+        assert_eq!(started.color, Kind::Black);
+        let game = registry.get(started.game).unwrap();
+        let game = game.lock().unwrap();
+        assert!(game.rated());
+        assert_eq!(game.user(Kind::White), Some("alice"));
+        assert_eq!(game.seat(&started.token), Some(Kind::Black));
+        assert!(lobby.get(alice.id).unwrap().started.is_some());
+        assert!(lobby.list(None).is_empty());
+        assert!(matches!(events.try_recv(), Ok(LobbyEvent::Seek(_))));
+    }
+
+    #[test]
+    fn test_challenge() {
+        let registry = Arc::new(Registry::new(Engines::default()));
+        for name in ["alice", "bob", "carol"] {
+            registry.users().register(name, "password").unwrap();
+        }
+        let lobby = Lobby::new(registry);
+        let challenge = Terms {
+            to: Some("bob".to_string()),
+            ..terms(Some(Kind::Black))
+        };
+        assert!(lobby
+            .seek(
+                "alice",
+                Terms {
+                    to: Some("nobody".to_string()),
+                    ..challenge.clone()
+                }
+            )
+            .is_err());
+        let challenge = lobby.seek("alice", challenge).unwrap();
+        // challenges are not paired with seeks, nor seen or taken by others
+        assert!(lobby.seek("carol", terms(None)).unwrap().started.is_none());
+        assert_eq!(lobby.list(Some("carol")).len(), 1);
+        assert_eq!(lobby.list(Some("bob")).len(), 2);
+        assert!(lobby.accept(challenge.id, "carol").is_err());
+
+        let (game, side, token) = lobby.accept(challenge.id, "bob").unwrap();
+        assert_eq!(side, Kind::White);
+        assert_eq!(game.lock().unwrap().seat(&token), Some(Kind::White));
+        assert!(lobby.accept(challenge.id, "bob").is_err());
+    }
+}
@@ -1,14 +1,70 @@
+// the server's filter chain is deeper than rustc follows by default
+#![recursion_limit = "256"]
+
 use chess::Board;
 mod api;
+mod arena;
+mod chat;
 mod chess;
+mod clock;
+mod eval;
+mod external;
+mod games;
+mod lobby;
+mod nnue;
+mod ponder;
+mod rating;
+mod search;
+mod skill;
+mod storage;
+mod tablebase;
+mod timeman;
+mod tournament;
+mod tune;
+mod uci;
+mod users;
+mod xboard;
 
+use anyhow::anyhow;
+use futures_util::{stream::SplitSink, SinkExt};
+use serde::Serialize;
 use std::{
     convert::Infallible,
-    sync::{Arc, Mutex},
+    path::Path,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
 };
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+use warp::ws::{Message, WebSocket};
 use warp::{hyper::StatusCode, Filter};
 
-use crate::api::RequestMove;
+use crate::api::{
+    RequestAnalysis, RequestChat, RequestChatRoom, RequestEngine, RequestEngineEntrant,
+    RequestJoin, RequestLobbySocket, RequestMove, RequestNewGame, RequestPosition, RequestSeek,
+    RequestSocket, RequestTablebase, RequestToken, RequestTournament, RequestUser,
+    ResponseAnalysis, ResponseChatMessage, ResponseCrosstable, ResponseEvent, ResponseGame,
+    ResponseHistory, ResponseLobbyEvent, ResponseMove, ResponseNewGame, ResponseSeat, ResponseSeek,
+    ResponseSession, ResponseTablebase, ResponseTournament, ResponseUser,
+};
+use crate::chat::{Room, WordFilter};
+use crate::chess::{parse_square, square_name, Castling, Kind, Move, Position, State};
+use crate::eval::EvalParams;
+use crate::external::{EngineSpec, Opponent};
+use crate::games::{secret_token, Action, Engines, Event, Game, Player, Registry};
+use crate::lobby::Lobby;
+use crate::nnue::Network;
+use crate::ponder::Ponderer;
+use crate::search::{SearchConfig, Searcher};
+use crate::storage::Storage;
+use crate::tablebase::Tablebase;
+use crate::tournament::Tournaments;
+use crate::users::Users;
+
+// how often the clocks of all games are checked for a fallen flag
+const FLAG_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// how often tournaments take in finished games and pair new rounds
+const TOURNAMENT_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 struct InvalidMove {}
@@ -19,38 +75,1514 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
+fn with_tablebase(
+    tablebase: Option<Arc<Tablebase>>,
+) -> impl Filter<Extract = (Option<Arc<Tablebase>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || tablebase.clone())
+}
+
+fn with_network(
+    network: Option<Arc<Network>>,
+) -> impl Filter<Extract = (Option<Arc<Network>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || network.clone())
+}
+
+fn with_params(
+    params: Arc<EvalParams>,
+) -> impl Filter<Extract = (Arc<EvalParams>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || params.clone())
+}
+
+fn with_engines(
+    engines: Engines,
+) -> impl Filter<Extract = (Engines,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || engines.clone())
+}
+
+fn with_ponderer(
+    ponderer: Arc<Mutex<Ponderer>>,
+) -> impl Filter<Extract = (Arc<Mutex<Ponderer>>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || ponderer.clone())
+}
+
 fn with_board(
-    board: Arc<Mutex<Board>>,
-) -> impl Filter<Extract = (Arc<Mutex<Board>>,), Error = std::convert::Infallible> + Clone {
+    board: Arc<Mutex<SharedBoard>>,
+) -> impl Filter<Extract = (Arc<Mutex<SharedBoard>>,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || board.clone())
 }
 
+fn with_lobby(
+    lobby: Arc<Lobby>,
+) -> impl Filter<Extract = (Arc<Lobby>,), Error = Infallible> + Clone {
+    warp::any().map(move || lobby.clone())
+}
+
+fn with_tournaments(
+    tournaments: Arc<Tournaments>,
+) -> impl Filter<Extract = (Arc<Tournaments>,), Error = Infallible> + Clone {
+    warp::any().map(move || tournaments.clone())
+}
+
+fn with_registry(
+    registry: Arc<Registry>,
+) -> impl Filter<Extract = (Arc<Registry>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || registry.clone())
+}
+
+// the board the routes outside of `/games` work on, the moves played on it since the starting
+// position and the tokens of its white and black seats
+#[derive(Default)]
+struct SharedBoard {
+    board: Board,
+    moves: Vec<Move>,
+    seats: [Option<String>; 2],
+}
+
+impl SharedBoard {
+    // takes the seat of `side`, or the first open one
+    fn join(&mut self, side: Option<Kind>) -> Result<(Kind, String), anyhow::Error> {
+        let side = [Kind::White, Kind::Black]
+            .into_iter()
+            .filter(|&kind| side.is_none_or(|side| side == kind))
+            .find(|kind| self.seats[kind.index()].is_none())
+            .ok_or_else(|| anyhow!("No open seat"))?;
+        let token = secret_token();
+        self.seats[side.index()] = Some(token.clone());
+        Ok((side, token))
+    }
+
+    // the color seated with `token`
+    fn seat(&self, token: &str) -> Option<Kind> {
+        [Kind::White, Kind::Black]
+            .into_iter()
+            .find(|kind| self.seats[kind.index()].as_deref() == Some(token))
+    }
+
+    // validates the move like a human's and records it
+    fn play(&mut self, from: Position, to: Position) -> Result<(), anyhow::Error> {
+        self.board.move_piece(from, to)?;
+        self.moves.push(Move::new(from, to));
+        Ok(())
+    }
+
+    // arguments of the UCI position command with `side` to move: the moves from the
+    // starting position when they replay to the current board, the board itself otherwise
+    fn uci_position(&self, side: Kind) -> String {
+        let mut state = State::default();
+        let replayed = self
+            .moves
+            .iter()
+            .all(|mv| match state.parse_move(&mv.to_string()) {
+                Some(mv) => {
+                    state.make_move(mv);
+                    true
+                }
+                None => false,
+            });
+        if replayed && *state.board == *self.board && state.side == side {
+            let moves: Vec<String> = self.moves.iter().map(|mv| mv.to_string()).collect();
+            if moves.is_empty() {
+                "startpos".to_string()
+            } else {
+                format!("startpos moves {}", moves.join(" "))
+            }
+        } else {
+            let state = State::from_board(Board::from_data(*self.board), side, Castling::none());
+            format!("fen {}", state.to_fen())
+        }
+    }
+}
+
+// takes a seat at the shared board, its token moves that color's pieces
+async fn post_join_route(
+    b: Arc<Mutex<SharedBoard>>,
+    r: RequestJoin,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let side = match r.color() {
+        Ok(side) => side,
+        Err(_) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let joined = b.lock().unwrap().join(side);
+    match joined {
+        Ok((side, token)) => Ok(Box::new(warp::reply::json(&ResponseSeat::new(side, token)))),
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// moves a piece of the color whose seat's token is sent as `Authorization: Bearer <token>`
 async fn post_move_route(
-    b: Arc<Mutex<Board>>,
+    authorization: Option<String>,
+    b: Arc<Mutex<SharedBoard>>,
+    ponderer: Arc<Mutex<Ponderer>>,
     r: RequestMove,
 ) -> Result<impl warp::Reply, Infallible> {
-    let convpos = |s: &str| {
-        let mut chars = s.chars();
-        let col = chars.next().unwrap() as usize - 'a' as usize;
-        let row = 7 - (chars.next().unwrap() as usize - '1' as usize);
-        (row, col)
+    let token = match bearer(authorization) {
+        Some(token) => token,
+        None => return Ok(StatusCode::UNAUTHORIZED),
+    };
+    let (from, to) = match (parse_square(&r.from()), parse_square(&r.to())) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Ok(StatusCode::BAD_REQUEST),
+    };
+    let (res, played) = {
+        let mut board = b.lock().unwrap();
+        let owner = board.board[from.0][from.1].map(|pair| pair.kind);
+        match board.seat(&token) {
+            Some(side) if owner.is_none_or(|owner| owner == side) => {}
+            _ => return Ok(StatusCode::FORBIDDEN),
+        }
+        let res = board.play(from, to);
+        (res, Board::from_data(*board.board))
     };
-
-    let mut board = b.lock().unwrap();
-    let r = r.clone();
-    let res = board.move_piece(convpos(&r.from().clone()), convpos(&r.to().clone()));
 
     match res {
-        Ok(_) => Ok(StatusCode::OK),
+        Ok(_) => {
+            // a ponder search on another position is of no use any more
+            tokio::task::spawn_blocking(move || ponderer.lock().unwrap().moved(&played))
+                .await
+                .unwrap();
+            Ok(StatusCode::OK)
+        }
         Err(_e) => Ok(StatusCode::BAD_REQUEST),
     }
 }
 
+// lets the engine play a move for the requested color on the shared board, for the player
+// seated with that color or against them while it is open. the search runs on a blocking
+// thread and the board is not locked meanwhile
+async fn post_engine_route(
+    authorization: Option<String>,
+    b: Arc<Mutex<SharedBoard>>,
+    engines: Engines,
+    ponderer: Arc<Mutex<Ponderer>>,
+    r: RequestEngine,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let token = match bearer(authorization) {
+        Some(token) => token,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    let color = match r.color() {
+        Ok(color) => color,
+        Err(_) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    {
+        let board = b.lock().unwrap();
+        match board.seat(&token) {
+            Some(side) if side == color || board.seats[color.index()].is_none() => {}
+            _ => return Ok(Box::new(StatusCode::FORBIDDEN)),
+        }
+    }
+    Ok(
+        tokio::task::spawn_blocking(move || engine_move(b, engines, ponderer, color, r))
+            .await
+            .unwrap(),
+    )
+}
+
+fn engine_move(
+    b: Arc<Mutex<SharedBoard>>,
+    engines: Engines,
+    ponderer: Arc<Mutex<Ponderer>>,
+    color: Kind,
+    r: RequestEngine,
+) -> Box<dyn warp::Reply> {
+    if r.external() {
+        return external_move(&b, engines.external, color, &r);
+    }
+    let board = *b.lock().unwrap().board;
+    let mut state = State::from_board(Board::from_data(board), color, Castling::none());
+
+    let mut ponderer = ponderer.lock().unwrap();
+    let result = ponderer.search(&state, &r.limits(), r.skill(), |searcher| {
+        searcher.set_threads(r.threads());
+        searcher.set_tablebase(engines.tablebase);
+        searcher.set_network(engines.network.filter(|_| !r.classical()));
+        searcher.set_params((*engines.params).clone());
+    });
+
+    let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_string()).collect();
+    let skill = r.skill();
+    info!(
+        "engine level {} (~{} elo) depth {} score {} nodes {} pv {} ({})",
+        skill.level(),
+        skill.elo(),
+        result.depth,
+        result.score,
+        result.nodes,
+        pv.join(" "),
+        state.to_fen()
+    );
+
+    let mv = match result.best_move {
+        Some(mv) => mv,
+        None => return Box::new(StatusCode::CONFLICT),
+    };
+    {
+        let mut shared = b.lock().unwrap();
+        // someone moved while the engine was thinking
+        if *shared.board != board {
+            return Box::new(StatusCode::CONFLICT);
+        }
+        state.make_move(mv);
+        shared.board = Board::from_data(*state.board);
+        shared.moves.push(mv);
+    }
+    // think on the expected reply while the user does
+    if let (true, Some(&reply)) = (r.ponder() && r.skill().is_full(), result.pv.get(1)) {
+        let mut predicted = state.clone();
+        predicted.make_move(reply);
+        ponderer.ponder(predicted, r.limits());
+    }
+    Box::new(warp::reply::json(&ResponseMove::from(mv)))
+}
+
+// asks the external engine for a move and plays it like a human's
+fn external_move(
+    b: &Mutex<SharedBoard>,
+    opponent: Option<Arc<Mutex<Opponent>>>,
+    color: Kind,
+    r: &RequestEngine,
+) -> Box<dyn warp::Reply> {
+    let opponent = match opponent {
+        Some(opponent) => opponent,
+        None => return Box::new(StatusCode::SERVICE_UNAVAILABLE),
+    };
+    let (position, board) = {
+        let shared = b.lock().unwrap();
+        (shared.uci_position(color), *shared.board)
+    };
+    let reply = opponent.lock().unwrap().play(&position, &r.limits());
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("external engine: {}", e);
+            return Box::new(StatusCode::BAD_GATEWAY);
+        }
+    };
+    info!(
+        "external engine bestmove {} score {:?} (position {})",
+        reply.best_move, reply.score, position
+    );
+
+    let mut shared = b.lock().unwrap();
+    if *shared.board != board {
+        return Box::new(StatusCode::CONFLICT);
+    }
+    // the whole move, so promotions keep the piece the engine chose
+    let mut state = State::from_board(Board::from_data(board), color, Castling::none());
+    match state.parse_move(&reply.best_move) {
+        Some(mv) => {
+            state.make_move(mv);
+            shared.board = Board::from_data(*state.board);
+            shared.moves.push(mv);
+            Box::new(warp::reply::json(&ResponseMove::from(mv)))
+        }
+        None => {
+            warn!("external engine played an invalid move {}", reply.best_move);
+            Box::new(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+// searcher for the analysis of a snapshot of the shared board, none for a color that is not one
+fn analysis_searcher(
+    b: &Arc<Mutex<SharedBoard>>,
+    tablebase: Option<Arc<Tablebase>>,
+    network: Option<Arc<Network>>,
+    params: Arc<EvalParams>,
+    r: &RequestAnalysis,
+) -> Option<Searcher> {
+    let color = r.color().ok()?;
+    let board = *b.lock().unwrap().board;
+    let state = State::from_board(Board::from_data(board), color, Castling::none());
+    let mut searcher = Searcher::new(state, SearchConfig::default());
+    searcher.set_multipv(r.multipv());
+    searcher.set_threads(r.threads());
+    searcher.set_tablebase(tablebase);
+    searcher.set_network(network.filter(|_| !r.classical()));
+    searcher.set_params((*params).clone());
+    Some(searcher)
+}
+
+// runs the analysis off the async runtime and replies with the final lines
+async fn get_analysis_route(
+    b: Arc<Mutex<SharedBoard>>,
+    tablebase: Option<Arc<Tablebase>>,
+    network: Option<Arc<Network>>,
+    params: Arc<EvalParams>,
+    r: RequestAnalysis,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let mut searcher = match analysis_searcher(&b, tablebase, network, params, &r) {
+        Some(searcher) => searcher,
+        None => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let state = searcher.state().clone();
+    let result = tokio::task::spawn_blocking(move || searcher.search(&r.limits()))
+        .await
+        .unwrap();
+    Ok(Box::new(warp::reply::json(&ResponseAnalysis::new(
+        &state, &result,
+    ))))
+}
+
+// streams every completed iteration as an `analysis` server-sent event, closing the
+// connection stops the search
+async fn get_analysis_stream_route(
+    b: Arc<Mutex<SharedBoard>>,
+    tablebase: Option<Arc<Tablebase>>,
+    network: Option<Arc<Network>>,
+    params: Arc<EvalParams>,
+    r: RequestAnalysis,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let mut searcher = match analysis_searcher(&b, tablebase, network, params, &r) {
+        Some(searcher) => searcher,
+        None => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let state = searcher.state().clone();
+    let stop = searcher.signals().stop;
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let events = tx.clone();
+    searcher.set_reporter(Box::new(move |result| {
+        let analysis = ResponseAnalysis::new(&state, result);
+        if events.send(("analysis", analysis)).is_err() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }));
+    tokio::task::spawn_blocking(move || {
+        let result = searcher.search(&r.limits());
+        let state = searcher.state().clone();
+        tx.send(("done", ResponseAnalysis::new(&state, &result)))
+            .ok();
+    });
+
+    let stream = UnboundedReceiverStream::new(rx)
+        .map(|(event, analysis)| warp::sse::Event::default().event(event).json_data(analysis));
+    Ok(Box::new(warp::sse::reply(
+        warp::sse::keep_alive().stream(stream),
+    )))
+}
+
+// win/draw/loss and distance to zeroing of the current board from the tablebase
+async fn get_tablebase_route(
+    b: Arc<Mutex<SharedBoard>>,
+    tablebase: Option<Arc<Tablebase>>,
+    r: RequestTablebase,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let tablebase = match tablebase {
+        Some(tablebase) => tablebase,
+        None => return Ok(Box::new(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+    let color = match r.color() {
+        Ok(color) => color,
+        Err(_) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let board = *b.lock().unwrap().board;
+    let mut state = State::from_board(Board::from_data(board), color, Castling::none());
+
+    let response = tokio::task::spawn_blocking(move || {
+        let wdl = tablebase.probe_wdl(&mut state)?;
+        let dtz = tablebase.probe_dtz(&mut state);
+        Some(ResponseTablebase::new(&state, wdl, dtz))
+    })
+    .await
+    .unwrap();
+    match response {
+        Some(response) => Ok(Box::new(warp::reply::json(&response))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// the token sent as `Authorization: Bearer <token>`
+fn bearer(authorization: Option<String>) -> Option<String> {
+    authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+// the user logged in with the session token sent as `Authorization: Bearer <token>`
+fn session(registry: &Registry, authorization: Option<String>) -> Option<String> {
+    bearer(authorization).and_then(|token| registry.users().session(&token))
+}
+
+// starts a game, an engine opponent with the first move plays it right away. a logged in
+// creator is seated as their user
+async fn post_game_route(
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+    r: RequestNewGame,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let (state, side, (white, black)) = match (r.state(), r.color(), r.players()) {
+        (Ok(state), Ok(side), Some(players)) if r.time_control().is_some() == r.timed() => {
+            (state, side, players)
+        }
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let user = session(&registry, authorization);
+    if r.rated() {
+        if user.is_none() {
+            return Ok(Box::new(StatusCode::UNAUTHORIZED));
+        }
+        if white != Player::Human || black != Player::Human {
+            return Ok(Box::new(StatusCode::BAD_REQUEST));
+        }
+    }
+    match registry.create(state, white, black, r.limits(), r.time_control(), r.rated()) {
+        Ok(game) => {
+            let mut game = game.lock().unwrap();
+            let (side, token) = game
+                .join(Some(side), user.as_deref())
+                .expect("The creator's seat is open");
+            let response = ResponseNewGame::new(&game, ResponseSeat::new(side, token));
+            Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            )))
+        }
+        Err(e) => {
+            warn!("new game: {}", e);
+            Ok(Box::new(StatusCode::SERVICE_UNAVAILABLE))
+        }
+    }
+}
+
+async fn get_games_route(registry: Arc<Registry>) -> Result<impl warp::Reply, Infallible> {
+    let games: Vec<ResponseGame> = registry
+        .list()
+        .iter()
+        .map(|game| ResponseGame::from(&*game.lock().unwrap()))
+        .collect();
+    Ok(warp::reply::json(&games))
+}
+
+async fn get_game_route(
+    id: u64,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match registry.get(id) {
+        Some(game) => Ok(Box::new(warp::reply::json(&ResponseGame::from(
+            &*game.lock().unwrap(),
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// the board keeps the shape of `/board`, the clock is sent in the `X-Clock-White` and
+// `X-Clock-Black` headers in milliseconds
+async fn get_game_board_route(
+    id: u64,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let (board, clock) = {
+        let game = game.lock().unwrap();
+        (*game.state().board, game.clock_reading())
+    };
+    let reply = warp::reply::json(&api::Board::from(board));
+    match clock {
+        Some(clock) => Ok(Box::new(warp::reply::with_header(
+            warp::reply::with_header(reply, "X-Clock-White", clock.white.as_millis().to_string()),
+            "X-Clock-Black",
+            clock.black.as_millis().to_string(),
+        ))),
+        None => Ok(Box::new(reply)),
+    }
+}
+
+// every move of the game with the position and clock after it
+async fn get_game_history_route(
+    id: u64,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match registry.get(id) {
+        Some(game) => Ok(Box::new(warp::reply::json(&ResponseHistory::from(
+            &*game.lock().unwrap(),
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// the board after a number of moves, in the shape of `/board`
+async fn get_game_position_route(
+    id: u64,
+    r: RequestPosition,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let state = {
+        let game = game.lock().unwrap();
+        game.position(r.ply().unwrap_or(game.moves().len()))
+    };
+    match state {
+        Some(state) => Ok(Box::new(warp::reply::json(&api::Board::from(*state.board)))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// legal destinations of the piece on `pos`
+async fn get_game_moves_route(
+    id: u64,
+    pos: String,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let destinations = match parse_square(&pos) {
+        Some(from) => game.lock().unwrap().destinations(from),
+        None => Vec::new(),
+    };
+    let moves: Vec<String> = destinations.into_iter().map(square_name).collect();
+    Ok(Box::new(warp::reply::json(&moves)))
+}
+
+// takes a seat, as the logged in user if a session token is sent. the token in the reply is
+// what moves it
+async fn post_game_join_route(
+    id: u64,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+    r: RequestJoin,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let side = match r.color() {
+        Ok(side) => side,
+        Err(_) => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let user = session(&registry, authorization);
+    let mut game = game.lock().unwrap();
+    if game.rated() && user.is_none() {
+        return Ok(Box::new(StatusCode::UNAUTHORIZED));
+    }
+    let joined = game.join(side, user.as_deref());
+    match joined {
+        Ok((side, token)) => Ok(Box::new(warp::reply::json(&ResponseSeat::new(side, token)))),
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// plays the move of the player whose token is sent as `Authorization: Bearer <token>`, an
+// engine opponent answers in the background
+async fn post_game_move_route(
+    id: u64,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+    r: RequestMove,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let token = match bearer(authorization) {
+        Some(token) => token,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    {
+        let game = game.lock().unwrap();
+        if !game.human_to_move() {
+            return Ok(Box::new(StatusCode::CONFLICT));
+        }
+        if game.seat(&token) != Some(game.state().side) {
+            return Ok(Box::new(StatusCode::FORBIDDEN));
+        }
+    }
+    match registry.play(&game, &token, &r.uci()) {
+        Ok(mv) => Ok(Box::new(warp::reply::json(&ResponseMove::from(mv)))),
+        Err(_) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+    }
+}
+
+// resigns, aborts or handles a draw for the player whose seat's token is sent as
+// `Authorization: Bearer <token>`, the reply is the game after it
+async fn post_game_action_route(
+    id: u64,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+    action: Action,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let token = match bearer(authorization) {
+        Some(token) => token,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    if game.lock().unwrap().seat(&token).is_none() {
+        return Ok(Box::new(StatusCode::FORBIDDEN));
+    }
+    match registry.act(&game, &token, action) {
+        Ok(()) => Ok(Box::new(warp::reply::json(&ResponseGame::from(
+            &*game.lock().unwrap(),
+        )))),
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// offers, accepts, declines or claims a draw
+async fn post_game_draw_route(
+    id: u64,
+    action: String,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match api::draw_action(&action) {
+        Some(action) => post_game_action_route(id, authorization, registry, action).await,
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// the messages of one of the game's chat rooms, oldest first
+async fn get_game_chat_route(
+    id: u64,
+    r: RequestChatRoom,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let room = match r.room() {
+        Some(room) => room,
+        None => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let game = game.lock().unwrap();
+    // the spectators' room is for the logged-in users without a seat who write in it
+    if room == Room::Spectators {
+        let token = match bearer(authorization) {
+            Some(token) => token,
+            None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+        };
+        if !spectator(&registry, &game, Some(&token)) {
+            return Ok(Box::new(StatusCode::FORBIDDEN));
+        }
+    }
+    let messages: Vec<ResponseChatMessage> = game
+        .chat(room)
+        .into_iter()
+        .map(ResponseChatMessage::from)
+        .collect();
+    Ok(Box::new(warp::reply::json(&messages)))
+}
+
+// posts a message to the players' room with a seat's token, or to the spectators' with a
+// session token
+async fn post_game_chat_route(
+    id: u64,
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+    r: RequestChat,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let token = match bearer(authorization) {
+        Some(token) if registry.speaker(&game.lock().unwrap(), &token).is_some() => token,
+        _ => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    match registry.chat(&game, &token, r.text()) {
+        Ok(message) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&ResponseChatMessage::from(&message)),
+            StatusCode::CREATED,
+        ))),
+        Err(_) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+    }
+}
+
+// upgrades to the game's socket
+async fn get_game_socket_route(
+    id: u64,
+    ws: warp::ws::Ws,
+    r: RequestToken,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let token = r.token().map(|token| token.to_string());
+    match registry.get(id) {
+        Some(game) => {
+            Ok(Box::new(ws.on_upgrade(move |socket| {
+                game_socket(socket, game, registry, token)
+            })))
+        }
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// pushes the game's events to one client and plays the moves it sends with its seat's token.
+// spectators only follow, and read and chat in their own room when logged in
+async fn game_socket(
+    socket: WebSocket,
+    game: Arc<Mutex<Game>>,
+    registry: Arc<Registry>,
+    token: Option<String>,
+) {
+    let (mut sink, mut messages) = futures_util::StreamExt::split(socket);
+    let (mut events, greeting, spectator) = {
+        let game = game.lock().unwrap();
+        let spectator = spectator(&registry, &game, token.as_deref());
+        let mut greeting = snapshot(&game);
+        greeting.extend(
+            game.events_since(0)
+                .iter()
+                .filter(|(_, event)| matches!(event, Event::Chat(_)) && !hidden(event, spectator))
+                .map(|(_, event)| ResponseEvent::from(event)),
+        );
+        (game.subscribe(), greeting, spectator)
+    };
+    if !send_events(&mut sink, &greeting).await {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let responses = match event {
+                    Ok((_, event)) if hidden(&event, spectator) => continue,
+                    Ok((_, event)) => socket_events(&event),
+                    // too far behind to catch up event by event
+                    Err(RecvError::Lagged(_)) => snapshot(&game.lock().unwrap()),
+                    Err(RecvError::Closed) => break,
+                };
+                if !send_events(&mut sink, &responses).await {
+                    break;
+                }
+            }
+            message = messages.next() => {
+                let message = match message {
+                    Some(Ok(message)) if message.is_close() => break,
+                    Some(Ok(message)) => message,
+                    _ => break,
+                };
+                let text = match message.to_str() {
+                    Ok(text) => text,
+                    // pings and binary messages
+                    Err(_) => continue,
+                };
+                let played = match (serde_json::from_str::<RequestSocket>(text), &token) {
+                    (Err(e), _) => Err(e.into()),
+                    (Ok(RequestSocket::Chat { text }), Some(token)) => {
+                        registry.chat(&game, token, &text).map(|_| ())
+                    }
+                    (Ok(RequestSocket::Chat { .. }), None) => Err(anyhow!("Log in to chat")),
+                    (Ok(_), None) => Err(anyhow!("Spectators cannot play")),
+                    (Ok(RequestSocket::Move(r)), Some(token)) => {
+                        registry.play(&game, token, &r.uci()).map(|_| ())
+                    }
+                    (Ok(RequestSocket::Resign), Some(token)) => {
+                        registry.act(&game, token, Action::Resign)
+                    }
+                    (Ok(RequestSocket::Abort), Some(token)) => {
+                        registry.act(&game, token, Action::Abort)
+                    }
+                    (Ok(RequestSocket::Draw { action }), Some(token)) => {
+                        match api::draw_action(&action) {
+                            Some(action) => registry.act(&game, token, action),
+                            None => Err(anyhow!("Unknown draw action {}", action)),
+                        }
+                    }
+                };
+                // the move or action itself comes back as an event like everyone else's
+                if let Err(e) = played {
+                    let error = ResponseEvent::Error { message: e.to_string() };
+                    if !send_events(&mut sink, &[error]).await {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the spectators' chat is shown to the spectators who can write in it only
+fn hidden(event: &Event, spectator: bool) -> bool {
+    matches!(event, Event::Chat(message) if !spectator && message.room == Room::Spectators)
+}
+
+// whether `token` belongs to a logged-in user following `game` without a seat
+fn spectator(registry: &Registry, game: &Game, token: Option<&str>) -> bool {
+    token
+        .and_then(|token| registry.speaker(game, token))
+        .is_some_and(|(room, ..)| room == Room::Spectators)
+}
+
+// moves are followed by the board after them
+fn socket_events(event: &Event) -> Vec<ResponseEvent> {
+    let mut events = vec![ResponseEvent::from(event)];
+    if let Event::Move { fen, clock, .. } = event {
+        if let Ok(state) = State::from_fen(fen) {
+            events.push(ResponseEvent::board(&state, *clock));
+        }
+    }
+    events
+}
+
+// the game and its board, sent to clients that connect or fell behind
+fn snapshot(game: &Game) -> Vec<ResponseEvent> {
+    vec![
+        ResponseEvent::Game(ResponseGame::from(game)),
+        ResponseEvent::board(game.state(), game.clock_reading()),
+    ]
+}
+
+async fn send_events<T: Serialize>(sink: &mut SplitSink<WebSocket, Message>, events: &[T]) -> bool {
+    for event in events {
+        let text = serde_json::to_string(event).expect("Events serialize");
+        if sink.send(Message::text(text)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+// streams the game's events to spectators. every event has an id, a viewer reconnecting
+// with `Last-Event-ID` is sent the events it missed, a new one the whole game so far
+async fn get_game_events_route(
+    id: u64,
+    last_event_id: Option<String>,
+    r: RequestToken,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let game = match registry.get(id) {
+        Some(game) => game,
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+    };
+    let spectator = spectator(&registry, &game.lock().unwrap(), r.token());
+    let mut last = last_event_id.and_then(|id| id.parse().ok()).unwrap_or(0);
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            // catch up from the log, then follow until falling too far behind
+            let (missed, mut events) = {
+                let game = game.lock().unwrap();
+                (game.events_since(last), game.subscribe())
+            };
+            for (id, event) in missed {
+                if tx.send((id, event)).is_err() {
+                    return;
+                }
+                last = id;
+            }
+            loop {
+                let received = tokio::select! {
+                    received = events.recv() => received,
+                    // the viewer left, no need to wait for the next move
+                    _ = tx.closed() => return,
+                };
+                match received {
+                    Ok((id, _)) if id <= last => (),
+                    Ok((id, event)) => {
+                        if tx.send((id, event)).is_err() {
+                            return;
+                        }
+                        last = id;
+                    }
+                    Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        }
+    });
+
+    let stream = UnboundedReceiverStream::new(rx)
+        .filter(move |(_, event)| !hidden(event, spectator))
+        .map(|(id, event)| {
+            let event = ResponseEvent::from(&event);
+            warp::sse::Event::default()
+                .id(id.to_string())
+                .event(event.kind())
+                .json_data(event)
+        });
+    Ok(Box::new(warp::sse::reply(
+        warp::sse::keep_alive().stream(stream),
+    )))
+}
+
+// registers a user, passwords are hashed off the async runtime since that takes a while
+async fn post_user_route(
+    registry: Arc<Registry>,
+    r: RequestUser,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if users::check_credentials(r.name(), r.password()).is_err() {
+        return Ok(Box::new(StatusCode::BAD_REQUEST));
+    }
+    let registered = tokio::task::spawn_blocking(move || {
+        registry.users().register(r.name(), r.password())?;
+        Ok::<_, anyhow::Error>(registry.users().user(r.name()))
+    })
+    .await
+    .unwrap();
+    match registered {
+        Ok(Some(user)) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&ResponseUser::from(&user)),
+            StatusCode::CREATED,
+        ))),
+        _ => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// logs a user in, the reply has the session token
+async fn post_session_route(
+    registry: Arc<Registry>,
+    r: RequestUser,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let token = tokio::task::spawn_blocking({
+        let r = r.clone();
+        move || registry.users().login(r.name(), r.password())
+    })
+    .await
+    .unwrap();
+    match token {
+        Some(token) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&ResponseSession::new(r.name(), token)),
+            StatusCode::CREATED,
+        ))),
+        None => Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    }
+}
+
+// logs out the session whose token is sent as `Authorization: Bearer <token>`
+async fn delete_session_route(
+    authorization: Option<String>,
+    registry: Arc<Registry>,
+) -> Result<impl warp::Reply, Infallible> {
+    match bearer(authorization) {
+        Some(token) if registry.users().logout(&token) => Ok(StatusCode::NO_CONTENT),
+        _ => Ok(StatusCode::UNAUTHORIZED),
+    }
+}
+
+// a user's rating with its history and their recent games
+async fn get_user_route(
+    name: String,
+    registry: Arc<Registry>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match registry.users().user(&name) {
+        Some(user) => Ok(Box::new(warp::reply::json(&ResponseUser::from(&user)))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// the open seeks and the challenges from and to the logged in user
+async fn get_seeks_route(
+    authorization: Option<String>,
+    lobby: Arc<Lobby>,
+) -> Result<impl warp::Reply, Infallible> {
+    let user = session(lobby.registry(), authorization);
+    let seeks: Vec<ResponseSeek> = lobby
+        .list(user.as_deref())
+        .iter()
+        .map(|seek| ResponseSeek::new(seek, user.as_deref()))
+        .collect();
+    Ok(warp::reply::json(&seeks))
+}
+
+// posts a seek or challenge, a seek matching an open one starts the game right away and
+// comes back with the seat in it
+async fn post_seek_route(
+    authorization: Option<String>,
+    lobby: Arc<Lobby>,
+    r: RequestSeek,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(lobby.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    let terms = match r.terms() {
+        Some(terms) => terms,
+        None => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    match lobby.seek(&user, terms) {
+        Ok(seek) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&ResponseSeek::new(&seek, Some(&user))),
+            StatusCode::CREATED,
+        ))),
+        Err(_) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+    }
+}
+
+// a seek, with the seat in its game for its owner once it started one
+async fn get_seek_route(
+    id: u64,
+    authorization: Option<String>,
+    lobby: Arc<Lobby>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = session(lobby.registry(), authorization);
+    match lobby.get(id) {
+        Some(seek) => Ok(Box::new(warp::reply::json(&ResponseSeek::new(
+            &seek,
+            user.as_deref(),
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// takes up a seek or challenge and starts its game
+async fn post_seek_accept_route(
+    id: u64,
+    authorization: Option<String>,
+    lobby: Arc<Lobby>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(lobby.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    match lobby.get(id) {
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+        Some(seek) if seek.started.is_some() || seek.user == user => {
+            return Ok(Box::new(StatusCode::CONFLICT))
+        }
+        Some(seek) if !seek.accepts(&user, lobby.rating(&user)) => {
+            return Ok(Box::new(StatusCode::FORBIDDEN))
+        }
+        Some(_) => (),
+    }
+    match lobby.accept(id, &user) {
+        Ok((game, side, token)) => {
+            let response =
+                ResponseNewGame::new(&game.lock().unwrap(), ResponseSeat::new(side, token));
+            Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::CREATED,
+            )))
+        }
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// withdraws one's seek or declines a challenge
+async fn delete_seek_route(
+    id: u64,
+    authorization: Option<String>,
+    lobby: Arc<Lobby>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(lobby.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    if lobby.get(id).is_none() {
+        return Ok(Box::new(StatusCode::NOT_FOUND));
+    }
+    match lobby.cancel(id, &user) {
+        Ok(()) => Ok(Box::new(StatusCode::NO_CONTENT)),
+        Err(_) => Ok(Box::new(StatusCode::FORBIDDEN)),
+    }
+}
+
+// upgrades to the lobby's socket, as the user logged in with the session token if one is sent
+async fn get_lobby_socket_route(
+    ws: warp::ws::Ws,
+    r: RequestToken,
+    lobby: Arc<Lobby>,
+) -> Result<impl warp::Reply, Infallible> {
+    let user = r
+        .token()
+        .and_then(|token| lobby.registry().users().session(token));
+    Ok(ws.on_upgrade(move |socket| lobby_socket(socket, lobby, user)))
+}
+
+// pushes the lobby's events to one client and takes its seeks. a client that is not logged
+// in only follows the open seeks
+async fn lobby_socket(socket: WebSocket, lobby: Arc<Lobby>, user: Option<String>) {
+    let (mut sink, mut messages) = futures_util::StreamExt::split(socket);
+    let mut events = lobby.subscribe();
+    if !send_events(&mut sink, &[lobby_snapshot(&lobby, user.as_deref())]).await {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let response = match event {
+                    Ok(event) => ResponseLobbyEvent::new(&event, user.as_deref()),
+                    Err(RecvError::Lagged(_)) => Some(lobby_snapshot(&lobby, user.as_deref())),
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some(response) = response {
+                    if !send_events(&mut sink, &[response]).await {
+                        break;
+                    }
+                }
+            }
+            message = messages.next() => {
+                let message = match message {
+                    Some(Ok(message)) if message.is_close() => break,
+                    Some(Ok(message)) => message,
+                    _ => break,
+                };
+                let text = match message.to_str() {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                };
+                // what comes of a request comes back as events like everyone else's
+                let done = match (serde_json::from_str::<RequestLobbySocket>(text), &user) {
+                    (Err(e), _) => Err(e.into()),
+                    (Ok(_), None) => Err(anyhow!("Log in to seek games")),
+                    (Ok(RequestLobbySocket::Seek(r)), Some(user)) => match r.terms() {
+                        Some(terms) => lobby.seek(user, terms).map(|_| ()),
+                        None => Err(anyhow!("Invalid seek")),
+                    },
+                    (Ok(RequestLobbySocket::Accept { id }), Some(user)) => {
+                        lobby.accept(id, user).map(|_| ())
+                    }
+                    (Ok(RequestLobbySocket::Cancel { id }), Some(user)) => lobby.cancel(id, user),
+                };
+                if let Err(e) = done {
+                    let error = ResponseLobbyEvent::Error { message: e.to_string() };
+                    if !send_events(&mut sink, &[error]).await {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn lobby_snapshot(lobby: &Lobby, user: Option<&str>) -> ResponseLobbyEvent {
+    ResponseLobbyEvent::Seeks {
+        seeks: lobby
+            .list(user)
+            .iter()
+            .map(|seek| ResponseSeek::new(seek, user))
+            .collect(),
+    }
+}
+
+// all tournaments, with their seats for the logged in user
+async fn get_tournaments_route(
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = session(tournaments.registry(), authorization);
+    let list: Vec<ResponseTournament> = tournaments
+        .list()
+        .iter()
+        .map(|tournament| ResponseTournament::new(tournament, user.as_deref()))
+        .collect();
+    Ok(Box::new(warp::reply::json(&list)))
+}
+
+// creates a tournament run by the logged in user
+async fn post_tournament_route(
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+    r: RequestTournament,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(tournaments.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    let format = match r.format() {
+        Some(format) if r.time_control().is_some() == r.timed() => format,
+        _ => return Ok(Box::new(StatusCode::BAD_REQUEST)),
+    };
+    match tournaments.create(
+        &user,
+        r.name(),
+        format,
+        r.time_control(),
+        r.limits(),
+        r.rated(),
+    ) {
+        Ok(tournament) => Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&ResponseTournament::new(&tournament, Some(&user))),
+            StatusCode::CREATED,
+        ))),
+        Err(_) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+    }
+}
+
+async fn get_tournament_route(
+    id: u64,
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = session(tournaments.registry(), authorization);
+    match tournaments.get(id) {
+        Some(tournament) => Ok(Box::new(warp::reply::json(&ResponseTournament::new(
+            &tournament,
+            user.as_deref(),
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+// enters the logged in user
+async fn post_tournament_join_route(
+    id: u64,
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(tournaments.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    if tournaments.get(id).is_none() {
+        return Ok(Box::new(StatusCode::NOT_FOUND));
+    }
+    match tournaments.enter(id, &user) {
+        Ok(()) => tournament_reply(&tournaments, id, &user),
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+// enters the server's engine, which only the tournament's owner may do
+async fn post_tournament_engine_route(
+    id: u64,
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+    r: RequestEngineEntrant,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(tournaments.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    match tournaments.get(id) {
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+        Some(tournament) if tournament.owner != user => return Ok(Box::new(StatusCode::FORBIDDEN)),
+        Some(tournament) if tournament.started() || tournament.entrant(r.name()).is_some() => {
+            return Ok(Box::new(StatusCode::CONFLICT))
+        }
+        Some(_) => (),
+    }
+    match tournaments.enter_engine(id, r.name(), r.skill()) {
+        Ok(()) => tournament_reply(&tournaments, id, &user),
+        Err(_) => Ok(Box::new(StatusCode::BAD_REQUEST)),
+    }
+}
+
+// seeds the entrants and pairs the first round, which only the owner may do
+async fn post_tournament_start_route(
+    id: u64,
+    authorization: Option<String>,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let user = match session(tournaments.registry(), authorization) {
+        Some(user) => user,
+        None => return Ok(Box::new(StatusCode::UNAUTHORIZED)),
+    };
+    match tournaments.get(id) {
+        None => return Ok(Box::new(StatusCode::NOT_FOUND)),
+        Some(tournament) if tournament.owner != user => return Ok(Box::new(StatusCode::FORBIDDEN)),
+        Some(_) => (),
+    }
+    match tournaments.start(id) {
+        Ok(_) => tournament_reply(&tournaments, id, &user),
+        Err(_) => Ok(Box::new(StatusCode::CONFLICT)),
+    }
+}
+
+fn tournament_reply(
+    tournaments: &Tournaments,
+    id: u64,
+    user: &str,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match tournaments.get(id) {
+        Some(tournament) => Ok(Box::new(warp::reply::json(&ResponseTournament::new(
+            &tournament,
+            Some(user),
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+async fn get_standings_route(
+    id: u64,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match tournaments.get(id) {
+        Some(tournament) => Ok(Box::new(warp::reply::json(&api::standings(&tournament)))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+async fn get_crosstable_route(
+    id: u64,
+    tournaments: Arc<Tournaments>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match tournaments.get(id) {
+        Some(tournament) => Ok(Box::new(warp::reply::json(&ResponseCrosstable::from(
+            &tournament,
+        )))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
 
-    let board = Arc::new(Mutex::new(Board::default()));
+    // `chess uci` and `chess xboard` talk to chess GUIs over stdin/stdout instead of serving HTTP
+    match std::env::args().nth(1).as_deref() {
+        Some("uci") => return tokio::task::spawn_blocking(uci::run).await.unwrap(),
+        Some("xboard") => return tokio::task::spawn_blocking(xboard::run).await.unwrap(),
+        // `chess tune` and `chess match` are offline tools for improving the engine
+        Some(command @ ("tune" | "match")) => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            let run = if command == "tune" {
+                tune::run
+            } else {
+                arena::run
+            };
+            if let Err(e) = tokio::task::spawn_blocking(move || run(&args))
+                .await
+                .unwrap()
+            {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let board = Arc::new(Mutex::new(SharedBoard::default()));
+
+    // endgame tablebases are optional
+    let tablebase = std::env::var("SYZYGY_PATH")
+        .ok()
+        .and_then(|dir| match Tablebase::open(&dir) {
+            Ok(tablebase) => {
+                info!("syzygy tablebases up to {} pieces", tablebase.max_pieces());
+                Some(Arc::new(tablebase))
+            }
+            Err(e) => {
+                warn!("syzygy: {}", e);
+                None
+            }
+        });
+
+    // network evaluation is used when a network file is given, classical otherwise
+    let network = std::env::var("EVAL_FILE")
+        .ok()
+        .and_then(|path| match Network::load(&path) {
+            Ok(network) => {
+                info!("nnue network {}", path);
+                Some(Arc::new(network))
+            }
+            Err(e) => {
+                warn!("nnue: {}", e);
+                None
+            }
+        });
+
+    // classical evaluation parameters, as written by `chess tune`
+    let params = match std::env::var("EVAL_PARAMS") {
+        Ok(path) => match EvalParams::load(&path) {
+            Ok(params) => {
+                info!("evaluation parameters {}", path);
+                params
+            }
+            Err(e) => {
+                warn!("eval params: {}", e);
+                EvalParams::default()
+            }
+        },
+        Err(_) => EvalParams::default(),
+    };
+    let params = Arc::new(params);
+
+    // an external UCI engine users can play against, e.g. "cmd=/usr/bin/stockfish,Threads=2"
+    let opponent =
+        std::env::var("EXTERNAL_ENGINE")
+            .ok()
+            .and_then(|spec| match EngineSpec::parse(&spec) {
+                Ok(spec) if spec.command.is_some() => {
+                    Some(Arc::new(Mutex::new(Opponent::new(spec))))
+                }
+                Ok(_) => {
+                    warn!("external engine: the spec needs a cmd");
+                    None
+                }
+                Err(e) => {
+                    warn!("external engine: {}", e);
+                    None
+                }
+            });
+
+    let ponderer = Arc::new(Mutex::new(Ponderer::default()));
+
+    let engines = Engines {
+        tablebase: tablebase.clone(),
+        network: network.clone(),
+        params: params.clone(),
+        external: opponent.clone(),
+    };
+    // games are kept in memory only unless they are stored in a directory
+    let mut registry = match std::env::var("GAMES_DIR") {
+        Ok(dir) => match Storage::open(Path::new(&dir)).and_then(|storage| {
+            let users = Users::open(Path::new(&dir))?;
+            Registry::with_storage(engines.clone(), storage, users)
+        }) {
+            Ok(registry) => {
+                info!(
+                    "games stored in {}, {} unfinished",
+                    dir,
+                    registry
+                        .list()
+                        .iter()
+                        .filter(|game| game.lock().unwrap().ending().is_none())
+                        .count()
+                );
+                registry
+            }
+            Err(e) => {
+                warn!("games dir: {}", e);
+                Registry::new(engines.clone())
+            }
+        },
+        Err(_) => Registry::new(engines.clone()),
+    };
+    // words masked in chat messages and words that keep a message from being shown at all,
+    // each a comma separated list
+    let words = WordFilter::from_lists(
+        &std::env::var("CHAT_MASK").unwrap_or_default(),
+        &std::env::var("CHAT_DROP").unwrap_or_default(),
+    );
+    if !words.is_empty() {
+        registry.add_chat_filter(words);
+    }
+    let registry = Arc::new(registry);
+
+    // flags fall whether or not anyone moves
+    let clocks = registry.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLAG_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            clocks.check_clocks();
+        }
+    });
+
+    // tournaments are stored with the games
+    let tournaments = match std::env::var("GAMES_DIR") {
+        Ok(dir) => Tournaments::open(registry.clone(), Path::new(&dir)).unwrap_or_else(|e| {
+            warn!("tournaments: {}", e);
+            Tournaments::new(registry.clone())
+        }),
+        Err(_) => Tournaments::new(registry.clone()),
+    };
+    let tournaments = Arc::new(tournaments);
+    let collector = tournaments.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TOURNAMENT_INTERVAL);
+        loop {
+            interval.tick().await;
+            collector.collect();
+        }
+    });
 
     let board_clone_get_board = board.clone();
     let board_clone_get_moves = board.clone();
@@ -61,42 +1593,349 @@ async fn main() {
 
     let get_board_route = warp::path("board").map(move || {
         let board = board_clone_get_board.lock().unwrap();
-        let api_board: ApiBoard = ApiBoard::from(board.clone());
+        let api_board: ApiBoard = ApiBoard::from(*board.board);
         warp::reply::json(&api_board)
     });
 
-    let get_moves_route = warp::path!("moves" / String).map(move |pos: String| {
-        let board = board_clone_get_moves.lock().unwrap().clone();
-        let convpos = |s: &str| {
-            let mut chars = s.chars();
-            let col = chars.next().unwrap() as usize - 'a' as usize;
-            let row = 7 - (chars.next().unwrap() as usize - '1' as usize);
-            (row, col)
-        };
+    let get_moves_route =
+        warp::path!("moves" / String).map(move |pos: String| -> Box<dyn warp::Reply> {
+            let from = match parse_square(&pos) {
+                Some(from) => from,
+                None => return Box::new(StatusCode::BAD_REQUEST),
+            };
+            let board = *board_clone_get_moves.lock().unwrap().board;
+            let moves = Board::from_data(board).all_moves(from);
+            // convert moves to chess notation
+            let moves = moves.into_iter().map(square_name).collect::<Vec<String>>();
 
-        let moves = Board::from_data(board).all_moves(convpos(&pos.clone()));
-        // convert moves to chess notation
-        let moves = moves
-            .iter()
-            .map(|m| format!("{}{}", (m.1 as u8 + 'a' as u8) as char, 8 - m.0))
-            .collect::<Vec<String>>();
-
-        warp::reply::json(&moves)
-    });
+            Box::new(warp::reply::json(&moves))
+        });
 
     let pdir = std::env::current_dir().unwrap();
     let dir = pdir.to_string_lossy();
 
     let get_static_route = warp::path("static").and(warp::fs::dir(dir.to_string()));
 
-    let routes = warp::post()
-        .and(warp::path("move"))
-        .and(with_board(board.clone()))
+    let game_routes = warp::post()
+        .and(warp::path!("games"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_registry(registry.clone()))
+        .and(warp::body::json())
+        .and_then(post_game_route)
+        .or(warp::get()
+            .and(warp::path!("games"))
+            .and(with_registry(registry.clone()))
+            .and_then(get_games_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64))
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "board"))
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_board_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "history"))
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_history_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "position"))
+            .and(warp::query::<RequestPosition>())
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_position_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "moves" / String))
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_moves_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "join"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and(warp::body::json())
+            .and_then(post_game_join_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "move"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and(warp::body::json())
+            .and_then(post_game_move_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "resign"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and(warp::any().map(|| Action::Resign))
+            .and_then(post_game_action_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "abort"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and(warp::any().map(|| Action::Abort))
+            .and_then(post_game_action_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "draw" / String))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and_then(post_game_draw_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "chat"))
+            .and(warp::query::<RequestChatRoom>())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_chat_route))
+        .or(warp::post()
+            .and(warp::path!("games" / u64 / "chat"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and(warp::body::json())
+            .and_then(post_game_chat_route))
+        .or(warp::get()
+            .and(warp::path!("games" / u64 / "events"))
+            .and(warp::header::optional::<String>("last-event-id"))
+            .and(warp::query::<RequestToken>())
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_events_route))
+        .or(warp::path!("games" / u64 / "ws")
+            .and(warp::ws())
+            .and(warp::query::<RequestToken>())
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_socket_route));
+
+    let user_routes = warp::post()
+        .and(warp::path!("users"))
+        .and(with_registry(registry.clone()))
         .and(warp::body::json())
-        .and_then(post_move_route)
+        .and_then(post_user_route)
+        .or(warp::post()
+            .and(warp::path!("sessions"))
+            .and(with_registry(registry.clone()))
+            .and(warp::body::json())
+            .and_then(post_session_route))
+        .or(warp::delete()
+            .and(warp::path!("sessions"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_registry(registry.clone()))
+            .and_then(delete_session_route))
+        .or(warp::get()
+            .and(warp::path!("users" / String))
+            .and(with_registry(registry.clone()))
+            .and_then(get_user_route));
+
+    let lobby = Arc::new(Lobby::new(registry.clone()));
+    let lobby_routes = warp::get()
+        .and(warp::path!("lobby" / "seeks"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_lobby(lobby.clone()))
+        .and_then(get_seeks_route)
+        .or(warp::post()
+            .and(warp::path!("lobby" / "seeks"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_lobby(lobby.clone()))
+            .and(warp::body::json())
+            .and_then(post_seek_route))
+        .or(warp::get()
+            .and(warp::path!("lobby" / "seeks" / u64))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_lobby(lobby.clone()))
+            .and_then(get_seek_route))
+        .or(warp::post()
+            .and(warp::path!("lobby" / "seeks" / u64 / "accept"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_lobby(lobby.clone()))
+            .and_then(post_seek_accept_route))
+        .or(warp::delete()
+            .and(warp::path!("lobby" / "seeks" / u64))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_lobby(lobby.clone()))
+            .and_then(delete_seek_route))
+        .or(warp::path!("lobby" / "ws")
+            .and(warp::ws())
+            .and(warp::query::<RequestToken>())
+            .and(with_lobby(lobby.clone()))
+            .and_then(get_lobby_socket_route));
+
+    let tournament_routes = warp::get()
+        .and(warp::path!("tournaments"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_tournaments(tournaments.clone()))
+        .and_then(get_tournaments_route)
+        .or(warp::post()
+            .and(warp::path!("tournaments"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_tournaments(tournaments.clone()))
+            .and(warp::body::json())
+            .and_then(post_tournament_route))
+        .or(warp::get()
+            .and(warp::path!("tournaments" / u64))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(get_tournament_route))
+        .or(warp::post()
+            .and(warp::path!("tournaments" / u64 / "join"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(post_tournament_join_route))
+        .or(warp::post()
+            .and(warp::path!("tournaments" / u64 / "engines"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_tournaments(tournaments.clone()))
+            .and(warp::body::json())
+            .and_then(post_tournament_engine_route))
+        .or(warp::post()
+            .and(warp::path!("tournaments" / u64 / "start"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(post_tournament_start_route))
+        .or(warp::get()
+            .and(warp::path!("tournaments" / u64 / "standings"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(get_standings_route))
+        .or(warp::get()
+            .and(warp::path!("tournaments" / u64 / "crosstable"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(get_crosstable_route));
+
+    let routes = game_routes
+        .or(user_routes)
+        .or(lobby_routes)
+        .or(tournament_routes)
+        .or(warp::post()
+            .and(warp::path("join"))
+            .and(with_board(board.clone()))
+            .and(warp::body::json())
+            .and_then(post_join_route))
+        .or(warp::post()
+            .and(warp::path("move"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_board(board.clone()))
+            .and(with_ponderer(ponderer.clone()))
+            .and(warp::body::json())
+            .and_then(post_move_route))
+        .or(warp::post()
+            .and(warp::path("engine"))
+            .and(warp::header::optional::<String>("authorization"))
+            .and(with_board(board.clone()))
+            .and(with_engines(engines.clone()))
+            .and(with_ponderer(ponderer.clone()))
+            .and(warp::body::json())
+            .and_then(post_engine_route))
+        .or(warp::get()
+            .and(warp::path!("analysis"))
+            .and(with_board(board.clone()))
+            .and(with_tablebase(tablebase.clone()))
+            .and(with_network(network.clone()))
+            .and(with_params(params.clone()))
+            .and(warp::query::<RequestAnalysis>())
+            .and_then(get_analysis_route))
+        .or(warp::get()
+            .and(warp::path!("analysis" / "stream"))
+            .and(with_board(board.clone()))
+            .and(with_tablebase(tablebase.clone()))
+            .and(with_network(network.clone()))
+            .and(with_params(params.clone()))
+            .and(warp::query::<RequestAnalysis>())
+            .and_then(get_analysis_stream_route))
+        .or(warp::get()
+            .and(warp::path!("tablebase"))
+            .and(with_board(board.clone()))
+            .and(with_tablebase(tablebase.clone()))
+            .and(warp::query::<RequestTablebase>())
+            .and_then(get_tablebase_route))
         .or(warp::get().and(get_board_route))
         .or(warp::get().and(get_moves_route))
         .or(warp::get().and(get_static_route));
 
     warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeman::Limits;
+
+    #[test]
+    fn test_uci_position() {
+        let mut board = SharedBoard::default();
+        assert_eq!(board.uci_position(Kind::White), "startpos");
+        board.play((6, 4), (4, 4)).unwrap();
+        assert_eq!(board.uci_position(Kind::Black), "startpos moves e2e4");
+        // white moving twice does not replay, the board is sent as it is
+        assert!(board.uci_position(Kind::White).starts_with("fen "));
+        assert!(board.play((4, 4), (1, 4)).is_err());
+        assert_eq!(board.moves.len(), 1);
+    }
+
+    #[test]
+    fn test_shared_seats() {
+        let mut board = SharedBoard::default();
+        let (side, black) = board.join(Some(Kind::Black)).unwrap();
+        assert_eq!(side, Kind::Black);
+        assert!(board.join(Some(Kind::Black)).is_err());
+        let (side, white) = board.join(None).unwrap();
+        assert_eq!(side, Kind::White);
+        assert_ne!(white, black);
+        assert!(board.join(None).is_err());
+        assert_eq!(board.seat(&black), Some(Kind::Black));
+        assert_eq!(board.seat("guess"), None);
+    }
+
+    #[tokio::test]
+    async fn test_game_socket() {
+        let registry = Arc::new(Registry::new(Engines::default()));
+        let game = registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+                false,
+            )
+            .unwrap();
+        let id = game.lock().unwrap().id();
+        let (_, white) = game.lock().unwrap().join(Some(Kind::White), None).unwrap();
+        let route = warp::path!("games" / u64 / "ws")
+            .and(warp::ws())
+            .and(warp::query::<RequestToken>())
+            .and(with_registry(registry.clone()))
+            .and_then(get_game_socket_route);
+        let mut client = warp::test::ws()
+            .path(&format!("/games/{}/ws?token={}", id, white))
+            .handshake(route)
+            .await
+            .unwrap();
+        async fn next(client: &mut warp::test::WsClient) -> serde_json::Value {
+            let message = client.recv().await.unwrap();
+            serde_json::from_str(message.to_str().unwrap()).unwrap()
+        }
+        assert_eq!(next(&mut client).await["type"], "game");
+        assert_eq!(next(&mut client).await["type"], "board");
+
+        client
+            .send_text(r#"{"type": "move", "from": "e2", "to": "e4"}"#)
+            .await;
+        let event = next(&mut client).await;
+        assert_eq!(event["type"], "move");
+        assert_eq!(event["san"], "e4");
+        assert_eq!(next(&mut client).await["type"], "board");
+
+        // black's move with white's token, and a message that isn't one
+        client
+            .send_text(r#"{"type": "move", "from": "e7", "to": "e5"}"#)
+            .await;
+        assert_eq!(next(&mut client).await["type"], "error");
+        client.send_text("e7e5").await;
+        assert_eq!(next(&mut client).await["type"], "error");
+        assert_eq!(game.lock().unwrap().moves().len(), 1);
+
+        // the spectators' chat does not reach the players
+        registry.users().register("carol", "password").unwrap();
+        let carol = registry.users().login("carol", "password").unwrap();
+        registry.chat(&game, &carol, "secret").unwrap();
+        registry.chat(&game, &white, "hello").unwrap();
+        let event = next(&mut client).await;
+        assert_eq!(
+            (&event["type"], &event["text"]),
+            (&"chat".into(), &"hello".into())
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+
+use crate::chess::{Kind, Move, Pair, Piece, Position, State};
+
+// efficiently updatable neural network evaluation.
+//
+// the network has 768 one-hot inputs per perspective (2 colors x 6 pieces x 64 squares), a
+// hidden layer of H neurons whose weights are shared by both perspectives, and one output.
+// the hidden layer (the accumulator) only changes by a few weight rows per move, so it is
+// updated incrementally instead of being recomputed.
+//
+// file format, all numbers little endian:
+//
+//   bytes        field
+//   4            magic "NNUE"
+//   4   u32      version, currently 1
+//   4   u32      hidden size H
+//   768*H i16    feature weights, row per feature: weight for neuron j at feature * H + j
+//   H     i16    hidden biases
+//   2*H   i16    output weights, side to move's half first
+//   4   i32      output bias
+//
+// a feature is (own piece ? 0 : 384) + piece * 64 + square, pieces ordered pawn, knight,
+// bishop, rook, queen, king, squares a1 = 0 .. h8 = 63 seen from the perspective's side
+// (black's are flipped vertically). the hidden layer is clipped to 0..QA, weights are
+// quantized by QA for the hidden layer and QB for the output, and the output is scaled
+// to centipawns by SCALE
+
+const MAGIC: &[u8; 4] = b"NNUE";
+const VERSION: u32 = 1;
+const FEATURES: usize = 768;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+impl Network {
+    pub fn load(path: impl AsRef<Path>) -> Result<Network, anyhow::Error> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, anyhow::Error> {
+        if bytes.len() < 12 || &bytes[..4] != MAGIC {
+            return Err(anyhow!("Not a network file"));
+        }
+        let u32_at = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let version = u32_at(4);
+        if version != VERSION {
+            return Err(anyhow!("Unsupported network version {}", version));
+        }
+        let hidden = u32_at(8) as usize;
+        if hidden == 0 || bytes.len() != 12 + (FEATURES * hidden + 3 * hidden) * 2 + 4 {
+            return Err(anyhow!("Network file has the wrong size"));
+        }
+
+        let mut at = 12;
+        let mut read = |count: usize| -> Vec<i16> {
+            let values = bytes[at..at + count * 2]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            at += count * 2;
+            values
+        };
+        let feature_weights = read(FEATURES * hidden);
+        let feature_bias = read(hidden);
+        let output_weights = read(2 * hidden);
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_bias,
+            output_weights,
+            output_bias: u32_at(bytes.len() - 4) as i32,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + (FEATURES + 3) * self.hidden * 2 + 4);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for values in [
+            &self.feature_weights,
+            &self.feature_bias,
+            &self.output_weights,
+        ] {
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    fn weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+}
+
+fn feature(perspective: Kind, pair: Pair, pos: Position) -> usize {
+    let square = match perspective {
+        Kind::White => (7 - pos.0) * 8 + pos.1,
+        Kind::Black => pos.0 * 8 + pos.1,
+    };
+    let side = if pair.kind == perspective { 0 } else { 384 };
+    side + pair.piece.index() * 64 + square
+}
+
+// the hidden layer for every position along the current search line. each entry holds
+// white's perspective followed by black's
+pub struct Nnue {
+    network: Arc<Network>,
+    stack: Vec<i16>,
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>, state: &State) -> Nnue {
+        let mut nnue = Nnue {
+            network,
+            stack: Vec::new(),
+        };
+        nnue.refresh(state);
+        nnue
+    }
+
+    pub fn network(&self) -> Arc<Network> {
+        self.network.clone()
+    }
+
+    // recomputes the accumulator of the position from scratch
+    pub fn refresh(&mut self, state: &State) {
+        let hidden = self.network.hidden;
+        self.stack.clear();
+        self.stack.extend_from_slice(&self.network.feature_bias);
+        self.stack.extend_from_slice(&self.network.feature_bias);
+        for (r, row) in state.board.iter().enumerate() {
+            for (c, square) in row.iter().enumerate() {
+                if let Some(pair) = square {
+                    for (i, perspective) in [Kind::White, Kind::Black].into_iter().enumerate() {
+                        let weights = self.network.weights(feature(perspective, *pair, (r, c)));
+                        add(&mut self.stack[i * hidden..(i + 1) * hidden], weights);
+                    }
+                }
+            }
+        }
+    }
+
+    // pushes the accumulator after `mv`, call before making the move on `state`
+    pub fn push(&mut self, state: &State, mv: &Move) {
+        let size = 2 * self.network.hidden;
+        let top = self.stack.len() - size;
+        self.stack.extend_from_within(top..);
+
+        let piece = state.board[mv.from.0][mv.from.1].expect("Piece must be present");
+        let placed = Pair {
+            kind: piece.kind,
+            piece: mv.promotion.unwrap_or(piece.piece),
+        };
+        let mut removed = vec![(piece, mv.from)];
+        let mut added = vec![(placed, mv.to)];
+        if let Some(captured) = state.captured_piece(mv) {
+            let pos = match state.board[mv.to.0][mv.to.1] {
+                Some(_) => mv.to,
+                // en passant
+                None => (mv.from.0, mv.to.1),
+            };
+            let pair = Pair {
+                kind: piece.kind.opposite(),
+                piece: captured,
+            };
+            removed.push((pair, pos));
+        }
+        if piece.piece == Piece::King && mv.from.1.abs_diff(mv.to.1) == 2 {
+            let (from, to) = if mv.to.1 == 6 { (7, 5) } else { (0, 3) };
+            let rook = Pair {
+                kind: piece.kind,
+                piece: Piece::Rook,
+            };
+            removed.push((rook, (mv.from.0, from)));
+            added.push((rook, (mv.from.0, to)));
+        }
+
+        let hidden = self.network.hidden;
+        let accumulator = &mut self.stack[top + size..];
+        for (i, perspective) in [Kind::White, Kind::Black].into_iter().enumerate() {
+            let half = &mut accumulator[i * hidden..(i + 1) * hidden];
+            for (pair, pos) in &removed {
+                sub(
+                    half,
+                    self.network.weights(feature(perspective, *pair, *pos)),
+                );
+            }
+            for (pair, pos) in &added {
+                add(
+                    half,
+                    self.network.weights(feature(perspective, *pair, *pos)),
+                );
+            }
+        }
+    }
+
+    pub fn pop(&mut self) {
+        let size = 2 * self.network.hidden;
+        self.stack.truncate(self.stack.len() - size);
+    }
+
+    // evaluation in centipawns for the side to move
+    pub fn evaluate(&self, side: Kind) -> i32 {
+        let hidden = self.network.hidden;
+        let top = self.stack.len() - 2 * hidden;
+        let (white, black) = self.stack[top..].split_at(hidden);
+        let (us, them) = match side {
+            Kind::White => (white, black),
+            Kind::Black => (black, white),
+        };
+
+        let (us_weights, them_weights) = self.network.output_weights.split_at(hidden);
+        let mut output: i64 = 0;
+        for (values, weights) in [(us, us_weights), (them, them_weights)] {
+            for (value, weight) in values.iter().zip(weights) {
+                output += (*value as i32).clamp(0, QA) as i64 * *weight as i64;
+            }
+        }
+        output += self.network.output_bias as i64;
+        let score = output * SCALE as i64 / (QA * QB) as i64;
+        score.clamp(-20000, 20000) as i32
+    }
+}
+
+fn add(values: &mut [i16], weights: &[i16]) {
+    for (value, weight) in values.iter_mut().zip(weights) {
+        *value = value.wrapping_add(*weight);
+    }
+}
+
+fn sub(values: &mut [i16], weights: &[i16]) {
+    for (value, weight) in values.iter_mut().zip(weights) {
+        *value = value.wrapping_sub(*weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_network(hidden: usize) -> Network {
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = |range: i64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            ((seed % (2 * range as u64 + 1)) as i64 - range) as i16
+        };
+        Network {
+            hidden,
+            feature_weights: (0..FEATURES * hidden).map(|_| next(60)).collect(),
+            feature_bias: (0..hidden).map(|_| next(60)).collect(),
+            output_weights: (0..2 * hidden).map(|_| next(60)).collect(),
+            output_bias: 123,
+        }
+    }
+
+    #[test]
+    fn test_network_file_round_trip() {
+        let network = random_network(8);
+        let bytes = network.to_bytes();
+        assert_eq!(Network::from_bytes(&bytes).unwrap(), network);
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_incremental_updates_match_refresh() {
+        let network = Arc::new(random_network(16));
+        // castling, en passant and a capturing promotion
+        let fen = "r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+        let mut state = State::from_fen(fen).unwrap();
+        let mut nnue = Nnue::new(network.clone(), &state);
+        let start = nnue.evaluate(state.side);
+
+        let mut undos = Vec::new();
+        for s in ["e5d6", "e8g8", "b7a8q", "f8a8", "e1c1"] {
+            let mv = state.parse_move(s).unwrap();
+            nnue.push(&state, &mv);
+            undos.push(state.make_move(mv));
+            let fresh = Nnue::new(network.clone(), &state);
+            assert_eq!(
+                nnue.evaluate(state.side),
+                fresh.evaluate(state.side),
+                "after {}",
+                s
+            );
+        }
+        while let Some(undo) = undos.pop() {
+            state.unmake_move(undo);
+            nnue.pop();
+        }
+        assert_eq!(nnue.evaluate(state.side), start);
+    }
+}
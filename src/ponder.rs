@@ -0,0 +1,189 @@
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::chess::{Board, State};
+use crate::search::{SearchConfig, SearchResult, Searcher};
+use crate::skill::Skill;
+use crate::timeman::{Limits, Signals};
+
+// a ponder search nobody picks up is stopped after this long
+const MAX_PONDER: Duration = Duration::from_secs(30);
+
+// the server's engine, kept between moves so its transposition table carries over. after
+// playing it can ponder on the position it expects after the opponent's reply, and when
+// that reply comes it carries on with the running search instead of starting over
+pub struct Ponderer {
+    // the searcher moves into the worker thread while it ponders
+    searcher: Option<Searcher>,
+    worker: Option<JoinHandle<(Searcher, SearchResult)>>,
+    signals: Signals,
+    // the predicted position and the limits the ponder search will play on
+    pondering: Option<(State, Limits)>,
+    // dropped when pondering ends, which calls off the time limit
+    deadline: Option<Sender<()>>,
+}
+
+impl Default for Ponderer {
+    fn default() -> Self {
+        let searcher = Searcher::new(State::default(), SearchConfig::default());
+        Ponderer {
+            signals: searcher.signals(),
+            searcher: Some(searcher),
+            worker: None,
+            pondering: None,
+            deadline: None,
+        }
+    }
+}
+
+impl Ponderer {
+    // the best move in `state` at `skill`. `configure` prepares the searcher for this
+    // request and is skipped on a ponder hit, which keeps the configuration the ponder
+    // search started with. weakened levels never ponder
+    pub fn search(
+        &mut self,
+        state: &State,
+        limits: &Limits,
+        skill: Skill,
+        configure: impl FnOnce(&mut Searcher),
+    ) -> SearchResult {
+        if skill.is_full() {
+            if let Some(result) = self.ponderhit(state, limits) {
+                return result;
+            }
+        }
+        let searcher = self.searcher();
+        configure(searcher);
+        searcher.set_state(state.clone());
+        skill.search(searcher, limits)
+    }
+
+    // starts searching `state`, the position after the expected reply, in the background
+    pub fn ponder(&mut self, state: State, limits: Limits) {
+        let mut searcher = self.searcher.take().expect("Searcher is idle");
+        self.signals.stop.store(false, Ordering::Relaxed);
+        self.signals.ponder.store(true, Ordering::Relaxed);
+        searcher.set_state(state.clone());
+        let ponder_limits = Limits {
+            ponder: true,
+            ..limits.clone()
+        };
+        self.worker = Some(thread::spawn(move || {
+            let result = searcher.search(&ponder_limits);
+            (searcher, result)
+        }));
+        self.pondering = Some((state, limits));
+
+        let (deadline, expired) = mpsc::channel::<()>();
+        let signals = self.signals.clone();
+        thread::spawn(move || {
+            let timeout = expired.recv_timeout(MAX_PONDER) == Err(RecvTimeoutError::Timeout);
+            if timeout && signals.ponder.load(Ordering::Relaxed) {
+                signals.stop.store(true, Ordering::Relaxed);
+            }
+        });
+        self.deadline = Some(deadline);
+    }
+
+    // the opponent moved: pondering only goes on if `board` is the position it expected
+    pub fn moved(&mut self, board: &Board) {
+        let expected = self
+            .pondering
+            .as_ref()
+            .is_some_and(|(predicted, _)| *predicted.board == **board);
+        if !expected {
+            self.stop();
+        }
+    }
+
+    // on a ponder hit the running search starts its clock and its result is used, as long
+    // as its move is legal in `state` too (the predicted position may differ in details
+    // like en passant rights)
+    fn ponderhit(&mut self, state: &State, limits: &Limits) -> Option<SearchResult> {
+        let (predicted, ponder_limits) = self.pondering.take()?;
+        self.deadline = None;
+        let hit = *predicted.board == *state.board
+            && predicted.side == state.side
+            && ponder_limits == *limits;
+        if !hit {
+            self.stop();
+            return None;
+        }
+
+        self.signals.ponder.store(false, Ordering::Relaxed);
+        let worker = self.worker.take().expect("A ponder search is running");
+        let (searcher, result) = worker.join().expect("Ponder thread panicked");
+        self.searcher = Some(searcher);
+        let legal = result
+            .best_move
+            .is_some_and(|mv| state.parse_move(&mv.to_string()) == Some(mv));
+        legal.then_some(result)
+    }
+
+    // waits for a running ponder search to stop and hands back the searcher
+    fn searcher(&mut self) -> &mut Searcher {
+        self.stop();
+        self.searcher.as_mut().expect("Searcher is idle")
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.signals.stop.store(true, Ordering::Relaxed);
+            self.signals.ponder.store(false, Ordering::Relaxed);
+            self.searcher = Some(worker.join().expect("Ponder thread panicked").0);
+        }
+        self.pondering = None;
+        self.deadline = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ponderhit_uses_running_search() {
+        let mut ponderer = Ponderer::default();
+        let mut state = State::default();
+        let limits = Limits {
+            movetime: Some(Duration::from_millis(100)),
+            ..Limits::default()
+        };
+        let result = ponderer.search(&state, &limits, Skill::default(), |_| ());
+        let mv = result.best_move.unwrap();
+        state.make_move(mv);
+        let reply = state.legal_moves()[0];
+        state.make_move(reply);
+
+        ponderer.ponder(state.clone(), limits.clone());
+        // pondering ignores the clock until the hit
+        thread::sleep(Duration::from_millis(300));
+        assert!(!ponderer.worker.as_ref().unwrap().is_finished());
+
+        let result = ponderer.search(&state, &limits, Skill::default(), |_| {
+            panic!("Must not reconfigure")
+        });
+        assert!(state.legal_moves().contains(&result.best_move.unwrap()));
+
+        // a miss searches from scratch
+        ponderer.ponder(state.clone(), limits.clone());
+        let mut configured = false;
+        let result = ponderer.search(&State::default(), &limits, Skill::default(), |_| {
+            configured = true
+        });
+        assert!(configured);
+        assert!(State::default()
+            .legal_moves()
+            .contains(&result.best_move.unwrap()));
+
+        // the opponent's move keeps the search going only if it was the expected one
+        ponderer.ponder(state.clone(), limits.clone());
+        ponderer.moved(&state.board);
+        assert!(ponderer.worker.is_some());
+        ponderer.moved(&State::default().board);
+        assert!(ponderer.worker.is_none());
+    }
+}
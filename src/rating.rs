@@ -0,0 +1,145 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+// Glicko-2 ratings as described in Mark Glickman's "Example of the Glicko-2 system". the
+// server rates every game as a rating period of its own
+
+// how much the volatility may change, smaller values keep it steadier
+const TAU: f64 = 0.5;
+// the factor between the Glicko and the Glicko-2 scale
+const SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000001;
+const MAX_DEVIATION: f64 = 350.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: 1500.0,
+            deviation: MAX_DEVIATION,
+            volatility: 0.06,
+        }
+    }
+}
+
+impl Rating {
+    // the rating after playing `games`: the opponents' ratings with the score against each,
+    // 1 for a win, 0.5 for a draw and 0 for a loss
+    pub fn update(&self, games: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - 1500.0) / SCALE;
+        let phi = self.deviation / SCALE;
+        if games.is_empty() {
+            let deviation = (phi * phi + self.volatility * self.volatility).sqrt() * SCALE;
+            return Rating {
+                deviation: deviation.min(MAX_DEVIATION),
+                ..*self
+            };
+        }
+
+        let mut variance = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in games {
+            let g = g(opponent.deviation / SCALE);
+            let expected = expected(mu, (opponent.rating - 1500.0) / SCALE, g);
+            variance += g * g * expected * (1.0 - expected);
+            improvement += g * (score - expected);
+        }
+        let v = 1.0 / variance;
+        let delta = v * improvement;
+
+        let volatility = self.volatility(phi, v, delta);
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+        Rating {
+            rating: mu * SCALE + 1500.0,
+            deviation: (phi * SCALE).min(MAX_DEVIATION),
+            volatility,
+        }
+    }
+
+    // the new volatility, found with the Illinois algorithm
+    fn volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+
+        let mut lower = a;
+        let mut upper = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_lower, mut f_upper) = (f(lower), f(upper));
+        while (upper - lower).abs() > CONVERGENCE {
+            let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+            let f_c = f(c);
+            if f_c * f_upper <= 0.0 {
+                lower = upper;
+                f_lower = f_upper;
+            } else {
+                f_lower /= 2.0;
+            }
+            upper = c;
+            f_upper = f_c;
+        }
+        (lower / 2.0).exp()
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, opponent: f64, g: f64) -> f64 {
+    1.0 / (1.0 + (-g * (mu - opponent)).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            ..Rating::default()
+        }
+    }
+
+    #[test]
+    fn test_glickman_example() {
+        let player = rating(1500.0, 200.0);
+        let updated = player.update(&[
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ]);
+        assert!((updated.rating - 1464.06).abs() < 0.01, "{:?}", updated);
+        assert!((updated.deviation - 151.52).abs() < 0.01, "{:?}", updated);
+        assert!(
+            (updated.volatility - 0.05999).abs() < 0.00001,
+            "{:?}",
+            updated
+        );
+
+        // not playing only makes the rating less certain
+        let idle = player.update(&[]);
+        assert_eq!(idle.rating, 1500.0);
+        assert!(idle.deviation > 200.0);
+    }
+}
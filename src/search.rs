@@ -0,0 +1,1062 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chess::Undo;
+use crate::chess::{Move, State};
+use crate::eval::{evaluate, piece_value, EvalParams};
+use crate::nnue::{Network, Nnue};
+use crate::tablebase::{Tablebase, Wdl};
+use crate::timeman::{Limits, Signals, TimeManager};
+
+pub const INFINITY: i32 = 32000;
+pub const MATE: i32 = 31000;
+pub const MAX_PLY: usize = 128;
+// scores above this are mates found within the search horizon
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+// tablebase wins rank below every mate
+pub const TB_WIN: i32 = MATE_BOUND - MAX_PLY as i32;
+
+const FUTILITY_MARGIN: i32 = 120;
+const RAZOR_MARGIN: i32 = 300;
+const ASPIRATION_WINDOW: i32 = 40;
+// null move cutoffs at this depth and above are verified with a reduced normal search
+const NULL_VERIFICATION_DEPTH: i32 = 8;
+// how often the search looks at the clock and the stop flag
+const CHECK_INTERVAL: u64 = 1024;
+
+// each selective search technique can be switched off to measure what it is worth
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchConfig {
+    pub null_move: bool,
+    pub lmr: bool,
+    pub futility: bool,
+    pub razoring: bool,
+    pub check_extensions: bool,
+    pub aspiration: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            null_move: true,
+            lmr: true,
+            futility: true,
+            razoring: true,
+            check_extensions: true,
+            aspiration: true,
+        }
+    }
+}
+
+impl SearchConfig {
+    // plain alpha-beta, every selective technique switched off
+    #[cfg(test)]
+    pub fn full_width() -> SearchConfig {
+        SearchConfig {
+            null_move: false,
+            lmr: false,
+            futility: false,
+            razoring: false,
+            check_extensions: false,
+            aspiration: false,
+        }
+    }
+}
+
+// one principal variation of a multi-pv search
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    // centipawns from the point of view of the side to move, see `MATE`
+    pub score: i32,
+    pub depth: u32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    pub time: Duration,
+    // best lines first, only one unless multi-pv is enabled
+    pub lines: Vec<Line>,
+}
+
+// converts a score into mate in moves, negative when getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+pub type Reporter = Box<dyn FnMut(&SearchResult) + Send>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    hash: u64,
+    depth: i32,
+    score: i32,
+    bound: Bound,
+    mv: Option<Move>,
+}
+
+// shared between search threads without locking. every slot holds the hash xor'ed with
+// the packed entry next to the packed entry itself, so a slot torn by two threads writing
+// at once no longer matches its hash and reads as empty
+pub struct TranspositionTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl Entry {
+    // move 16 bits, score 16 bits, depth 8 bits, bound 2 bits
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        self.mv.map_or(0, |mv| mv.pack()) as u64
+            | (self.score as i16 as u16 as u64) << 16
+            | (self.depth.clamp(-128, 127) as i8 as u8 as u64) << 32
+            | bound << 40
+    }
+
+    fn unpack(hash: u64, data: u64) -> Entry {
+        Entry {
+            hash,
+            mv: Move::unpack(data as u16),
+            score: (data >> 16) as u16 as i16 as i32,
+            depth: (data >> 32) as u8 as i8 as i32,
+            bound: match data >> 40 & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+        }
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let megabytes = megabytes.max(1);
+        let count = (megabytes * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        TranspositionTable {
+            entries: (0..count)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in self.entries.iter() {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn probe(&self, hash: u64) -> Option<Entry> {
+        let slot = &self.entries[hash as usize % self.entries.len()];
+        let key = slot[0].load(Ordering::Relaxed);
+        let data = slot[1].load(Ordering::Relaxed);
+        if key ^ data == hash && data != 0 {
+            Some(Entry::unpack(hash, data))
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, entry: Entry) {
+        // keep deeper results of the same position
+        if let Some(old) = self.probe(entry.hash) {
+            if old.depth > entry.depth && entry.bound != Bound::Exact {
+                return;
+            }
+        }
+        let slot = &self.entries[entry.hash as usize % self.entries.len()];
+        let data = entry.pack();
+        slot[0].store(entry.hash ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+// mate scores are stored relative to the node so they stay valid at other plies
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+fn square_index(mv: &Move) -> usize {
+    (mv.from.0 * 8 + mv.from.1) * 64 + mv.to.0 * 8 + mv.to.1
+}
+
+fn lmr_reduction(depth: i32, move_number: usize) -> i32 {
+    let r = 0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25;
+    (r as i32).clamp(1, (depth - 2).max(1))
+}
+
+pub struct Searcher {
+    state: State,
+    params: EvalParams,
+    config: SearchConfig,
+    tt: Arc<TranspositionTable>,
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<i32>,
+    pv: Vec<Vec<Move>>,
+    nodes: u64,
+    signals: Signals,
+    timer: TimeManager,
+    // set once the search ran out of time, the running iteration is thrown away
+    stopped: bool,
+    // the first iteration always finishes so there is a move to play
+    can_stop: bool,
+    multipv: usize,
+    // root moves left out of this search, see `Limits::search_moves` and multi-pv
+    root_allowed: Vec<Move>,
+    root_excluded: Vec<Move>,
+    reporter: Option<Reporter>,
+    tablebase: Option<Arc<Tablebase>>,
+    // network evaluation, the classical one is used without
+    nnue: Option<Nnue>,
+    // amplitude in centipawns and seed of the noise added to the evaluation, see `skill`
+    eval_noise: (i32, u64),
+    // lazy smp: helper threads search the same position and share findings through the table
+    threads: usize,
+    helper: bool,
+    helper_nodes: Arc<AtomicU64>,
+}
+
+impl Searcher {
+    pub fn new(state: State, config: SearchConfig) -> Searcher {
+        Searcher::with_table(state, config, Arc::new(TranspositionTable::new(16)))
+    }
+
+    fn with_table(state: State, config: SearchConfig, tt: Arc<TranspositionTable>) -> Searcher {
+        let signals = Signals::default();
+        Searcher {
+            state,
+            params: EvalParams::default(),
+            config,
+            tt,
+            killers: vec![[None; 2]; MAX_PLY],
+            history: vec![0; 64 * 64],
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            nodes: 0,
+            timer: TimeManager::new(&Limits::default(), signals.clone()),
+            signals,
+            stopped: false,
+            can_stop: false,
+            multipv: 1,
+            root_allowed: Vec::new(),
+            root_excluded: Vec::new(),
+            reporter: None,
+            tablebase: None,
+            nnue: None,
+            eval_noise: (0, 0),
+            threads: 1,
+            helper: false,
+            helper_nodes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // stop and ponderhit flags of this searcher, usable while it runs on another thread
+    pub fn signals(&self) -> Signals {
+        self.signals.clone()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    pub fn config_mut(&mut self) -> &mut SearchConfig {
+        &mut self.config
+    }
+
+    pub fn set_hash(&mut self, megabytes: usize) {
+        self.tt = Arc::new(TranspositionTable::new(megabytes));
+    }
+
+    // one thread keeps the search deterministic
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    // switches to network evaluation, or back to the classical one with none
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.nnue = network.map(|network| Nnue::new(network, &self.state));
+    }
+
+    pub fn set_params(&mut self, params: EvalParams) {
+        self.params = params;
+    }
+
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    pub fn multipv(&self) -> usize {
+        self.multipv
+    }
+
+    pub fn set_multipv(&mut self, lines: usize) {
+        self.multipv = lines.max(1);
+    }
+
+    // adds up to `amplitude` centipawns either way to every evaluation. the noise depends
+    // on the position and `seed` only, so a position always evaluates the same
+    pub fn set_eval_noise(&mut self, amplitude: i32, seed: u64) {
+        self.eval_noise = (amplitude.max(0), seed);
+    }
+
+    // called after every completed iteration
+    pub fn set_reporter(&mut self, reporter: Reporter) {
+        self.reporter = Some(reporter);
+    }
+
+    // forgets everything learned from previous games
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.history.iter_mut().for_each(|h| *h = 0);
+    }
+
+    // searches until one of the limits is reached, on all configured threads
+    pub fn search(&mut self, limits: &Limits) -> SearchResult {
+        self.helper_nodes.store(0, Ordering::Relaxed);
+        let mut limits = limits.clone();
+        if let Some(moves) = self.tablebase_root_moves(&limits.search_moves) {
+            limits.search_moves = moves;
+        }
+        let limits = &limits;
+        if self.threads <= 1 {
+            return self.iterate(limits, 1);
+        }
+
+        // helpers run until this thread is done
+        let helper_signals = Signals::default();
+        let helper_limits = Limits {
+            depth: limits.depth,
+            mate: limits.mate,
+            search_moves: limits.search_moves.clone(),
+            infinite: true,
+            ..Limits::default()
+        };
+        let mut helpers: Vec<Searcher> = (1..self.threads)
+            .map(|_| {
+                let mut helper =
+                    Searcher::with_table(self.state.clone(), self.config.clone(), self.tt.clone());
+                helper.params = self.params.clone();
+                helper.eval_noise = self.eval_noise;
+                helper.tablebase = self.tablebase.clone();
+                helper.nnue = self
+                    .nnue
+                    .as_ref()
+                    .map(|nnue| Nnue::new(nnue.network(), &self.state));
+                helper.signals = helper_signals.clone();
+                helper.helper = true;
+                helper.helper_nodes = self.helper_nodes.clone();
+                helper
+            })
+            .collect();
+
+        thread::scope(|scope| {
+            for (i, helper) in helpers.iter_mut().enumerate() {
+                let limits = &helper_limits;
+                // every other helper skips the first iteration so threads spread over depths
+                scope.spawn(move || helper.iterate(limits, 1 + (i as u32 + 1) % 2));
+            }
+            let result = self.iterate(limits, 1);
+            helper_signals.stop.store(true, Ordering::Relaxed);
+            result
+        })
+    }
+
+    // in a tablebase position only the moves keeping the best result are searched
+    fn tablebase_root_moves(&mut self, allowed: &[Move]) -> Option<Vec<Move>> {
+        let tablebase = self.tablebase.clone()?;
+        let ranked: Vec<(Move, i32)> = tablebase
+            .rank_root_moves(&mut self.state)?
+            .into_iter()
+            .filter(|(mv, _)| allowed.is_empty() || allowed.contains(mv))
+            .collect();
+        let best = ranked.iter().map(|(_, rank)| *rank).max()?;
+        Some(
+            ranked
+                .into_iter()
+                .filter(|(_, rank)| *rank == best)
+                .map(|(mv, _)| mv)
+                .collect(),
+        )
+    }
+
+    fn evaluate(&self) -> i32 {
+        let score = match &self.nnue {
+            Some(nnue) => nnue.evaluate(self.state.side),
+            None => evaluate(&self.state, &self.params),
+        };
+        match self.eval_noise {
+            (0, _) => score,
+            (amplitude, seed) => {
+                // splitmix64 finalizer
+                let mut x = self.state.hash() ^ seed;
+                x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                x ^= x >> 31;
+                score + (x % (2 * amplitude as u64 + 1)) as i32 - amplitude
+            }
+        }
+    }
+
+    // keeps the network's accumulator in step with the board
+    fn make_move(&mut self, mv: Move) -> Undo {
+        if let Some(nnue) = self.nnue.as_mut() {
+            nnue.push(&self.state, &mv);
+        }
+        self.state.make_move(mv)
+    }
+
+    fn unmake_move(&mut self, undo: Undo) {
+        if let Some(nnue) = self.nnue.as_mut() {
+            nnue.pop();
+        }
+        self.state.unmake_move(undo);
+    }
+
+    // exact result right after a capture or pawn move once few enough pieces are left
+    fn probe_tablebase(&mut self, ply: usize) -> Option<i32> {
+        let tablebase = self.tablebase.as_ref()?;
+        if self.state.halfmove != 0 {
+            return None;
+        }
+        let score = match tablebase.probe_wdl(&mut self.state)? {
+            Wdl::Win => TB_WIN - ply as i32,
+            Wdl::Loss => -TB_WIN + ply as i32,
+            _ => 0,
+        };
+        Some(score)
+    }
+
+    // iterative deepening from `first_depth`
+    fn iterate(&mut self, limits: &Limits, first_depth: u32) -> SearchResult {
+        self.nodes = 0;
+        self.stopped = false;
+        self.can_stop = false;
+        self.timer = TimeManager::new(limits, self.signals.clone());
+        self.root_allowed = limits.search_moves.clone();
+        if let Some(nnue) = self.nnue.as_mut() {
+            nnue.refresh(&self.state);
+        }
+        let start = Instant::now();
+        self.killers.iter_mut().for_each(|k| *k = [None; 2]);
+        self.history.iter_mut().for_each(|h| *h /= 8);
+
+        let mut result = SearchResult {
+            best_move: None,
+            score: 0,
+            depth: 0,
+            pv: Vec::new(),
+            nodes: 0,
+            time: Duration::ZERO,
+            lines: Vec::new(),
+        };
+
+        // a mate in n moves is found within 2n - 1 plies
+        let max_depth = limits
+            .mate
+            .map(|moves| moves * 2)
+            .or(limits.depth)
+            .unwrap_or(MAX_PLY as u32 - 1)
+            .clamp(1, MAX_PLY as u32 - 1);
+        'deepening: for depth in first_depth.min(max_depth)..=max_depth {
+            let mut lines: Vec<Line> = Vec::new();
+            self.root_excluded.clear();
+
+            for index in 0..self.multipv {
+                let previous = result.lines.get(index).map_or(0, |line| line.score);
+                let score = if self.config.aspiration && depth >= 4 {
+                    self.aspiration(depth as i32, previous)
+                } else {
+                    self.negamax(depth as i32, 0, -INFINITY, INFINITY, false)
+                };
+
+                // an interrupted iteration is incomplete
+                if self.stopped {
+                    break 'deepening;
+                }
+                // every root move already has its line
+                if self.pv[0].is_empty() && index > 0 {
+                    break;
+                }
+
+                lines.push(Line {
+                    score,
+                    pv: self.pv[0].clone(),
+                });
+                match self.pv[0].first() {
+                    Some(mv) => self.root_excluded.push(*mv),
+                    // checkmate or stalemate at the root
+                    None => break,
+                }
+            }
+            lines.sort_by_key(|line| -line.score);
+
+            let best = lines[0].clone();
+            result = SearchResult {
+                best_move: best.pv.first().copied(),
+                score: best.score,
+                depth,
+                pv: best.pv,
+                nodes: self.nodes + self.helper_nodes.load(Ordering::Relaxed),
+                time: start.elapsed(),
+                lines,
+            };
+            if let Some(reporter) = self.reporter.as_mut() {
+                reporter(&result);
+            }
+
+            match result.best_move {
+                Some(mv) => self.timer.update(&mv.to_string(), result.score),
+                None => break,
+            }
+            if matches!(limits.mate, Some(moves) if mate_in(result.score).is_some_and(|m| m > 0 && m <= moves as i32))
+            {
+                break;
+            }
+            self.can_stop = true;
+            if !self.timer.can_deepen() || self.timer.should_stop(self.nodes) {
+                break;
+            }
+        }
+
+        self.root_excluded.clear();
+        result.nodes = self.nodes + self.helper_nodes.load(Ordering::Relaxed);
+        result.time = start.elapsed();
+        result
+    }
+
+    fn check_time(&mut self) {
+        if !self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            return;
+        }
+        if self.helper {
+            self.helper_nodes
+                .fetch_add(CHECK_INTERVAL, Ordering::Relaxed);
+        }
+        if self.can_stop && self.timer.should_stop(self.nodes) {
+            self.stopped = true;
+        }
+    }
+
+    // searches with a narrow window around the previous score and widens it on failure
+    fn aspiration(&mut self, depth: i32, previous: i32) -> i32 {
+        let mut delta = ASPIRATION_WINDOW;
+        let mut alpha = (previous - delta).max(-INFINITY);
+        let mut beta = (previous + delta).min(INFINITY);
+
+        loop {
+            let score = self.negamax(depth, 0, alpha, beta, false);
+            if self.stopped {
+                return score;
+            }
+            if score <= alpha && alpha > -INFINITY {
+                alpha = (alpha - delta).max(-INFINITY);
+            } else if score >= beta && beta < INFINITY {
+                beta = (beta + delta).min(INFINITY);
+            } else {
+                return score;
+            }
+            delta *= 2;
+        }
+    }
+
+    fn negamax(
+        &mut self,
+        mut depth: i32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        allow_null: bool,
+    ) -> i32 {
+        self.pv[ply].clear();
+
+        if self.stopped {
+            return 0;
+        }
+
+        if ply > 0 && (self.state.is_repetition() || self.state.halfmove >= 100) {
+            return 0;
+        }
+
+        let in_check = self.state.in_check();
+        if in_check && self.config.check_extensions && ply < MAX_PLY / 2 {
+            depth += 1;
+        }
+
+        if depth <= 0 {
+            return self.quiescence(ply, alpha, beta);
+        }
+
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate();
+        }
+
+        self.nodes += 1;
+        self.check_time();
+        let pv_node = beta - alpha > 1;
+        let hash = self.state.hash();
+
+        if ply > 0 {
+            if let Some(score) = self.probe_tablebase(ply) {
+                return score;
+            }
+        }
+
+        let mut tt_move = None;
+        if let Some(entry) = self.tt.probe(hash) {
+            tt_move = entry.mv;
+            if !pv_node && entry.depth >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        let static_eval = self.evaluate();
+
+        if !pv_node && !in_check {
+            // reverse futility: far above beta even after giving away a margin
+            if self.config.futility
+                && depth <= 3
+                && beta.abs() < MATE_BOUND
+                && static_eval - FUTILITY_MARGIN * depth >= beta
+            {
+                return static_eval;
+            }
+
+            // razoring: hopelessly below alpha, see if any capture saves us
+            if self.config.razoring && depth <= 2 && static_eval + RAZOR_MARGIN * depth < alpha {
+                let score = self.quiescence(ply, alpha - 1, alpha);
+                if score < alpha {
+                    return score;
+                }
+            }
+
+            // null move: if passing still beats beta the position is good enough.
+            // skipped without pieces besides pawns where zugzwang is common
+            if self.config.null_move
+                && allow_null
+                && depth >= 3
+                && static_eval >= beta
+                && self.state.has_non_pawn_material(self.state.side)
+            {
+                let r = 2 + depth / 6;
+                let undo = self.state.make_null();
+                let score = -self.negamax(depth - 1 - r, ply + 1, -beta, -beta + 1, false);
+                self.state.unmake_null(undo);
+
+                if score >= beta {
+                    let score = if score >= MATE_BOUND { beta } else { score };
+                    if depth < NULL_VERIFICATION_DEPTH {
+                        return score;
+                    }
+                    // deep cutoffs are verified without null moves to catch zugzwang
+                    let verified = self.negamax(depth - 1 - r, ply, beta - 1, beta, false);
+                    if verified >= beta {
+                        return score;
+                    }
+                }
+            }
+        }
+
+        let mut moves = self.state.legal_moves();
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        let restricted_root =
+            ply == 0 && (!self.root_allowed.is_empty() || !self.root_excluded.is_empty());
+        if restricted_root {
+            moves.retain(|mv| {
+                (self.root_allowed.is_empty() || self.root_allowed.contains(mv))
+                    && !self.root_excluded.contains(mv)
+            });
+            if moves.is_empty() {
+                return -INFINITY;
+            }
+        }
+        self.order_moves(&mut moves, tt_move, ply);
+
+        // futility: quiet moves can't raise a hopeless static eval above alpha
+        let futile = self.config.futility
+            && !pv_node
+            && !in_check
+            && depth <= 3
+            && alpha.abs() < MATE_BOUND
+            && static_eval + FUTILITY_MARGIN * depth <= alpha;
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+
+        for (i, mv) in moves.iter().enumerate() {
+            let quiet = !self.state.is_capture(mv) && mv.promotion.is_none();
+            let undo = self.make_move(*mv);
+            let gives_check = self.state.in_check();
+
+            if futile && quiet && i > 0 && !gives_check {
+                self.unmake_move(undo);
+                continue;
+            }
+
+            let mut score;
+            if i == 0 {
+                score = -self.negamax(depth - 1, ply + 1, -beta, -alpha, true);
+            } else {
+                // late quiet moves are unlikely to be best, search them shallower first
+                let reduction = if self.config.lmr
+                    && depth >= 3
+                    && i >= 3
+                    && quiet
+                    && !in_check
+                    && !gives_check
+                {
+                    lmr_reduction(depth, i)
+                } else {
+                    0
+                };
+
+                score = -self.negamax(depth - 1 - reduction, ply + 1, -alpha - 1, -alpha, true);
+                if reduction > 0 && score > alpha {
+                    score = -self.negamax(depth - 1, ply + 1, -alpha - 1, -alpha, true);
+                }
+                if score > alpha && score < beta {
+                    score = -self.negamax(depth - 1, ply + 1, -beta, -alpha, true);
+                }
+            }
+
+            self.unmake_move(undo);
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(*mv);
+
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, *mv);
+
+                    if alpha >= beta {
+                        if quiet {
+                            self.store_killer(ply, *mv);
+                            self.history[square_index(mv)] += depth * depth;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        // a root missing some of its moves is not the real position
+        if restricted_root {
+            return best_score;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(Entry {
+            hash,
+            depth,
+            score: score_to_tt(best_score, ply),
+            bound,
+            mv: best_move,
+        });
+
+        best_score
+    }
+
+    // resolves captures so the static evaluation isn't taken in the middle of an exchange
+    fn quiescence(&mut self, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv[ply].clear();
+        self.nodes += 1;
+        self.check_time();
+
+        if self.stopped {
+            return 0;
+        }
+
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate();
+        }
+
+        let in_check = self.state.in_check();
+        let mut best_score = -INFINITY;
+
+        if !in_check {
+            let stand_pat = self.evaluate();
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            best_score = stand_pat;
+        }
+
+        let mut moves = self.state.legal_moves();
+        if in_check && moves.is_empty() {
+            return -MATE + ply as i32;
+        }
+        if !in_check {
+            moves.retain(|mv| self.state.is_capture(mv) || mv.promotion.is_some());
+        }
+        self.order_moves(&mut moves, None, ply);
+
+        for mv in moves {
+            let undo = self.make_move(mv);
+            let score = -self.quiescence(ply + 1, -beta, -alpha);
+            self.unmake_move(undo);
+
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, mv);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        best_score
+    }
+
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        let child = std::mem::take(&mut self.pv[ply + 1]);
+        self.pv[ply].clear();
+        self.pv[ply].push(mv);
+        self.pv[ply].extend_from_slice(&child);
+        self.pv[ply + 1] = child;
+    }
+
+    fn store_killer(&mut self, ply: usize, mv: Move) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+    }
+
+    // transposition table move, captures by most valuable victim, killers, then history
+    fn order_moves(&self, moves: &mut [Move], tt_move: Option<Move>, ply: usize) {
+        let killers = self.killers[ply.min(MAX_PLY - 1)];
+        moves.sort_by_cached_key(|mv| {
+            let score = if Some(*mv) == tt_move {
+                1_000_000
+            } else if let Some(victim) = self.state.captured_piece(mv) {
+                let attacker =
+                    self.state.board[mv.from.0][mv.from.1].map_or(0, |p| piece_value(p.piece));
+                100_000 + piece_value(victim) * 10 - attacker / 10
+            } else if let Some(promotion) = mv.promotion {
+                90_000 + piece_value(promotion)
+            } else if killers[0] == Some(*mv) {
+                80_000
+            } else if killers[1] == Some(*mv) {
+                79_000
+            } else {
+                self.history[square_index(mv)].min(70_000)
+            };
+            -score
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best_move(fen: &str, depth: u32, config: SearchConfig) -> SearchResult {
+        let state = State::from_fen(fen).unwrap();
+        Searcher::new(state, config).search(&Limits::depth(depth))
+    }
+
+    #[test]
+    fn test_finds_back_rank_mate() {
+        let result = best_move(
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            3,
+            SearchConfig::default(),
+        );
+        assert_eq!(result.best_move.unwrap().to_string(), "a1a8");
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn test_captures_hanging_queen() {
+        let result = best_move(
+            "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1",
+            4,
+            SearchConfig::default(),
+        );
+        assert_eq!(result.best_move.unwrap().to_string(), "d1d5");
+    }
+
+    #[test]
+    fn test_every_toggle_finds_mate_in_two() {
+        // 1. Rxe8+ Rxe8 2. Rxe8#
+        let fen = "3rr1k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1";
+        let configs = [
+            SearchConfig::default(),
+            SearchConfig::full_width(),
+            SearchConfig {
+                null_move: false,
+                ..SearchConfig::default()
+            },
+            SearchConfig {
+                lmr: false,
+                ..SearchConfig::default()
+            },
+            SearchConfig {
+                futility: false,
+                razoring: false,
+                ..SearchConfig::default()
+            },
+            SearchConfig {
+                aspiration: false,
+                check_extensions: false,
+                ..SearchConfig::default()
+            },
+        ];
+
+        for config in configs {
+            let result = best_move(fen, 4, config.clone());
+            assert_eq!(result.score, MATE - 3, "{:?}", config);
+            assert_eq!(
+                result.best_move.unwrap().to_string(),
+                "e2e8",
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn test_node_and_time_limits() {
+        let nodes = Limits {
+            nodes: Some(20_000),
+            ..Limits::default()
+        };
+        let mut searcher = Searcher::new(State::default(), SearchConfig::default());
+        let result = searcher.search(&nodes);
+        assert!(result.best_move.is_some());
+        assert!(result.nodes < 20_000 + CHECK_INTERVAL);
+
+        // same limits give the same result
+        let mut again = Searcher::new(State::default(), SearchConfig::default());
+        let repeated = again.search(&nodes);
+        assert_eq!(result.pv, repeated.pv);
+
+        let start = std::time::Instant::now();
+        let result = searcher.search(&Limits {
+            movetime: Some(std::time::Duration::from_millis(200)),
+            ..Limits::default()
+        });
+        assert!(result.best_move.is_some());
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_stop_flag_returns_completed_iteration() {
+        let mut searcher = Searcher::new(State::default(), SearchConfig::default());
+        searcher
+            .signals()
+            .stop
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let result = searcher.search(&Limits {
+            infinite: true,
+            ..Limits::default()
+        });
+        assert_eq!(result.depth, 1);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn test_helper_threads_agree_on_mate() {
+        let fen = "3rr1k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1";
+        let mut searcher = Searcher::new(State::from_fen(fen).unwrap(), SearchConfig::default());
+        searcher.set_threads(4);
+        let result = searcher.search(&Limits::depth(6));
+        assert_eq!(result.best_move.unwrap().to_string(), "e2e8");
+        assert_eq!(result.score, MATE - 3);
+    }
+
+    #[test]
+    fn test_table_entries_survive_packing() {
+        let tt = TranspositionTable::new(1);
+        let mv = Move {
+            from: (1, 4),
+            to: (0, 4),
+            promotion: Some(crate::chess::Piece::Knight),
+        };
+        tt.store(Entry {
+            hash: 0xDEAD_BEEF,
+            depth: 7,
+            score: -MATE + 5,
+            bound: Bound::Upper,
+            mv: Some(mv),
+        });
+        let entry = tt.probe(0xDEAD_BEEF).unwrap();
+        assert_eq!(entry.mv, Some(mv));
+        assert_eq!(entry.score, -MATE + 5);
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.bound, Bound::Upper);
+        assert!(tt.probe(0xDEAD_BEEF + 1).is_none());
+    }
+
+    #[test]
+    fn test_stalemate_is_a_draw() {
+        let result = best_move("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 2, SearchConfig::default());
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, 0);
+    }
+}
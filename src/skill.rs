@@ -0,0 +1,168 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::search::{SearchResult, Searcher};
+use crate::timeman::Limits;
+
+// strength levels for casual play. below the top level the engine searches shallower and
+// fewer nodes, sees its evaluation through some noise and now and then prefers a weaker
+// move among its best few, the way Stockfish's skill level does. the levels are spread
+// evenly over an Elo range; that is an estimate rather than a measured rating, `chess
+// match` with the `Skill Level` option is the way to check it
+pub const MAX_LEVEL: u32 = 20;
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+// lines the weaker move is picked from
+const CANDIDATES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill { level: MAX_LEVEL }
+    }
+}
+
+impl Skill {
+    pub fn new(level: u32) -> Skill {
+        Skill {
+            level: level.min(MAX_LEVEL),
+        }
+    }
+
+    pub fn from_elo(elo: u32) -> Skill {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let step = (MAX_ELO - MIN_ELO) as f64 / MAX_LEVEL as f64;
+        Skill::new(((elo - MIN_ELO) as f64 / step).round() as u32)
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn elo(&self) -> u32 {
+        MIN_ELO + (MAX_ELO - MIN_ELO) * self.level / MAX_LEVEL
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.level >= MAX_LEVEL
+    }
+
+    // `limits` tightened to what this level may search
+    pub fn limits(&self, limits: &Limits) -> Limits {
+        if self.is_full() {
+            return limits.clone();
+        }
+        let depth = 1 + self.level / 2;
+        let nodes = 200 << (self.level / 2);
+        Limits {
+            depth: Some(limits.depth.map_or(depth, |d| d.min(depth))),
+            nodes: Some(limits.nodes.map_or(nodes, |n| n.min(nodes))),
+            ..limits.clone()
+        }
+    }
+
+    // evaluation noise in centipawns
+    fn noise(&self) -> i32 {
+        (MAX_LEVEL - self.level) as i32 * 10
+    }
+
+    // searches with this level's handicaps, the result holds the move to play
+    pub fn search(&self, searcher: &mut Searcher, limits: &Limits) -> SearchResult {
+        if self.is_full() {
+            return searcher.search(limits);
+        }
+        let multipv = searcher.multipv();
+        let mut rng = Rng::from_time();
+        searcher.set_multipv(multipv.max(CANDIDATES));
+        searcher.set_eval_noise(self.noise(), rng.next());
+        let mut result = searcher.search(&self.limits(limits));
+        searcher.set_multipv(multipv);
+        searcher.set_eval_noise(0, 0);
+
+        if let Some(index) = self.pick(&result, &mut rng) {
+            let line = result.lines[index].clone();
+            result.best_move = line.pv.first().copied();
+            result.score = line.score;
+            result.pv = line.pv;
+        }
+        result
+    }
+
+    // index of the line to play: every line gets a push that grows with its distance to
+    // the best score and a random share of the spread between the lines, both weighted by
+    // how weak the level is
+    fn pick(&self, result: &SearchResult, rng: &mut Rng) -> Option<usize> {
+        let lines = &result.lines;
+        let top = lines.first()?.score;
+        let delta = (top - lines.last()?.score).min(100) as i64;
+        let weakness = 120 - 2 * self.level as i64;
+
+        let mut best = (i64::MIN, 0);
+        for (i, line) in lines.iter().enumerate() {
+            if line.pv.is_empty() {
+                continue;
+            }
+            let push = (weakness * (top - line.score) as i64
+                + delta * (rng.next() % weakness as u64) as i64)
+                / 128;
+            if line.score as i64 + push >= best.0 {
+                best = (line.score as i64 + push, i);
+            }
+        }
+        Some(best.1)
+    }
+}
+
+// xorshift, seeded from the clock so games differ
+struct Rng(u64);
+
+impl Rng {
+    fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::State;
+    use crate::search::SearchConfig;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(Skill::from_elo(MIN_ELO).level(), 0);
+        assert_eq!(Skill::from_elo(5000), Skill::default());
+        assert_eq!(Skill::from_elo(1600).elo(), 1600);
+        assert_eq!(Skill::new(3).limits(&Limits::depth(10)).depth, Some(2));
+        assert_eq!(
+            Skill::default().limits(&Limits::default()),
+            Limits::default()
+        );
+    }
+
+    #[test]
+    fn test_weak_level_still_plays_legal_moves() {
+        let state = State::default();
+        let mut searcher = Searcher::new(state.clone(), SearchConfig::default());
+        let result = Skill::new(0).search(&mut searcher, &Limits::default());
+        assert!(result.depth <= 1);
+        assert!(state.legal_moves().contains(&result.best_move.unwrap()));
+        // the searcher is left as it was
+        assert_eq!(searcher.multipv(), 1);
+    }
+}
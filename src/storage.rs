@@ -0,0 +1,635 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{Message, Room};
+use crate::chess::{Kind, State};
+use crate::clock::TimeControl;
+use crate::games::{Ending, Event, Game, Player};
+use crate::skill::Skill;
+use crate::timeman::Limits;
+
+// the server's games are kept in an append-only log, `games.jsonl` in the storage
+// directory. every line is one record: a game was created, a move was played or a game
+// ended. nothing is ever rewritten, a server restarting replays the log to get its games
+// back
+const GAMES_FILE: &str = "games.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    Create {
+        id: u64,
+        fen: String,
+        white: StoredPlayer,
+        black: StoredPlayer,
+        depth: Option<u32>,
+        nodes: Option<u64>,
+        // milliseconds, as are all times below
+        movetime: Option<u64>,
+        clock: Option<StoredTimeControl>,
+        #[serde(default)]
+        rated: bool,
+        // unix time in milliseconds
+        at: u64,
+    },
+    Move {
+        id: u64,
+        uci: String,
+        // time left for white and black after the move
+        clock: Option<[u64; 2]>,
+        at: u64,
+    },
+    End {
+        id: u64,
+        result: String,
+        reason: String,
+        at: u64,
+    },
+    // a player took a seat, as the registered `user` if logged in. the token is kept so
+    // seated players can go on after a restart, which makes the log as secret as the tokens
+    Join {
+        id: u64,
+        color: String,
+        token: String,
+        #[serde(default)]
+        user: Option<String>,
+        at: u64,
+    },
+    // a draw offer of `color`, and its declining of one
+    Offer {
+        id: u64,
+        color: String,
+        at: u64,
+    },
+    Decline {
+        id: u64,
+        color: String,
+        at: u64,
+    },
+    // a chat message as the filter let it through, with the color of a player's
+    Chat {
+        id: u64,
+        room: String,
+        author: String,
+        color: Option<String>,
+        text: String,
+        at: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct StoredPlayer {
+    // "human", "engine" or "external"
+    kind: String,
+    level: Option<u32>,
+}
+
+// a time control in milliseconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredTimeControl {
+    base: u64,
+    increment: u64,
+    delay: u64,
+    moves: Option<u32>,
+}
+
+// a game as read back from the log
+pub struct StoredGame {
+    pub id: u64,
+    pub start: State,
+    pub white: Player,
+    pub black: Player,
+    pub limits: Limits,
+    pub time_control: Option<TimeControl>,
+    pub moves: Vec<StoredMove>,
+    // the tokens of white's and black's seats and the users seated there
+    pub seats: [Option<String>; 2],
+    pub users: [Option<String>; 2],
+    pub rated: bool,
+    // the joins, draw offers and declines, chat messages and the end with the number of moves played before each, which the
+    // game sends again in the same order so their ids stay the same
+    pub events: Vec<(usize, Event)>,
+}
+
+// a move in coordinate notation with the clock after it and when it was played
+pub struct StoredMove {
+    pub uci: String,
+    pub clock: Option<[Duration; 2]>,
+    pub at: u64,
+}
+
+// an append-only file of records, one JSON object per line
+pub struct Log {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Log {
+    // opens the log at `path`, creating it when needed
+    pub fn open(path: PathBuf) -> Result<Log, anyhow::Error> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // a line cut short by a crash is ended so the next record starts on a line of its own
+        let text = fs::read(&path)?;
+        if text.last().is_some_and(|&c| c != b'\n') {
+            writeln!(file)?;
+        }
+        Ok(Log {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    // all records, oldest first. lines that cannot be read, like one cut short by a crash,
+    // are skipped
+    pub fn read<T: DeserializeOwned>(&self) -> Result<Vec<T>, anyhow::Error> {
+        let mut records = Vec::new();
+        for (i, line) in fs::read_to_string(&self.path)?.lines().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("{}:{}: {}", self.path.display(), i + 1, e),
+            }
+        }
+        Ok(records)
+    }
+
+    // a failed write is logged, the server goes on
+    pub fn append<T: Serialize>(&self, record: &T) {
+        let line = serde_json::to_string(record).expect("Records serialize");
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            warn!("{}: {}", self.path.display(), e);
+        }
+    }
+}
+
+pub struct Storage {
+    log: Log,
+}
+
+impl Storage {
+    // opens the log in `dir`, creating both when needed
+    pub fn open(dir: &Path) -> Result<Storage, anyhow::Error> {
+        fs::create_dir_all(dir)?;
+        Ok(Storage {
+            log: Log::open(dir.join(GAMES_FILE))?,
+        })
+    }
+
+    // all games in the log, oldest first
+    pub fn load(&self) -> Result<Vec<StoredGame>, anyhow::Error> {
+        let mut games = BTreeMap::new();
+        for record in self.log.read::<Record>()? {
+            match record {
+                Record::Create {
+                    id,
+                    fen,
+                    white,
+                    black,
+                    depth,
+                    nodes,
+                    movetime,
+                    clock,
+                    rated,
+                    ..
+                } => {
+                    let start = match State::from_fen(&fen) {
+                        Ok(start) => start,
+                        Err(e) => {
+                            warn!("game {}: {}", id, e);
+                            continue;
+                        }
+                    };
+                    let game = StoredGame {
+                        id,
+                        start,
+                        white: white.player(),
+                        black: black.player(),
+                        limits: Limits {
+                            depth,
+                            nodes,
+                            movetime: movetime.map(Duration::from_millis),
+                            ..Limits::default()
+                        },
+                        time_control: clock.map(|c| c.time_control()),
+                        moves: Vec::new(),
+                        seats: [None, None],
+                        users: [None, None],
+                        rated,
+                        events: Vec::new(),
+                    };
+                    games.insert(id, game);
+                }
+                Record::Move { id, uci, clock, at } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let clock = clock.map(|c| c.map(Duration::from_millis));
+                        game.moves.push(StoredMove { uci, clock, at });
+                    }
+                }
+                Record::End {
+                    id, result, reason, ..
+                } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let ending = Ending {
+                            winner: match result.as_str() {
+                                "1-0" => Some(Kind::White),
+                                "0-1" => Some(Kind::Black),
+                                _ => None,
+                            },
+                            reason,
+                            aborted: result == "*",
+                        };
+                        let event = Event::End {
+                            ending,
+                            clock: None,
+                        };
+                        game.events.push((game.moves.len(), event));
+                    }
+                }
+                Record::Join {
+                    id,
+                    color,
+                    token,
+                    user,
+                    ..
+                } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let side = side(&color);
+                        game.seats[side.index()] = Some(token);
+                        game.users[side.index()] = user;
+                        game.events.push((game.moves.len(), Event::Join { side }));
+                    }
+                }
+                Record::Offer { id, color, .. } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let event = Event::Offer { side: side(&color) };
+                        game.events.push((game.moves.len(), event));
+                    }
+                }
+                Record::Decline { id, color, .. } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let event = Event::Decline { side: side(&color) };
+                        game.events.push((game.moves.len(), event));
+                    }
+                }
+                Record::Chat {
+                    id,
+                    room,
+                    author,
+                    color,
+                    text,
+                    at,
+                } => {
+                    if let Some(game) = games.get_mut(&id) {
+                        let message = Message {
+                            room: Room::parse(&room).unwrap_or(Room::Spectators),
+                            author,
+                            side: color.as_deref().map(side),
+                            text,
+                            at,
+                        };
+                        let event = Event::Chat(message);
+                        game.events.push((game.moves.len(), event));
+                    }
+                }
+            }
+        }
+        Ok(games.into_values().collect())
+    }
+
+    pub fn record_game(&self, game: &Game) {
+        let limits = game.limits();
+        self.log.append(&Record::Create {
+            id: game.id(),
+            fen: game.start().to_fen(),
+            white: StoredPlayer::new(game.player(Kind::White)),
+            black: StoredPlayer::new(game.player(Kind::Black)),
+            depth: limits.depth,
+            nodes: limits.nodes,
+            movetime: limits.movetime.map(|t| t.as_millis() as u64),
+            clock: game.time_control().map(StoredTimeControl::from),
+            rated: game.rated(),
+            at: now(),
+        });
+    }
+
+    // seats are recorded with their token by `record_join`
+    pub fn record_event(&self, id: u64, event: &Event) {
+        let record = match event {
+            Event::Move { mv, clock, at, .. } => Record::Move {
+                id,
+                uci: mv.to_string(),
+                clock: clock.map(|c| [c.white.as_millis() as u64, c.black.as_millis() as u64]),
+                at: *at,
+            },
+            Event::End { ending, .. } => Record::End {
+                id,
+                result: ending.result().to_string(),
+                reason: ending.reason.clone(),
+                at: now(),
+            },
+            Event::Chat(message) => Record::Chat {
+                id,
+                room: message.room.name().to_string(),
+                author: message.author.clone(),
+                color: message.side.map(|side| color(side).to_string()),
+                text: message.text.clone(),
+                at: message.at,
+            },
+            Event::Offer { side } => Record::Offer {
+                id,
+                color: color(*side).to_string(),
+                at: now(),
+            },
+            Event::Decline { side } => Record::Decline {
+                id,
+                color: color(*side).to_string(),
+                at: now(),
+            },
+            Event::Join { .. } => return,
+        };
+        self.log.append(&record);
+    }
+
+    pub fn record_join(&self, id: u64, side: Kind, token: &str, user: Option<&str>) {
+        self.log.append(&Record::Join {
+            id,
+            color: color(side).to_string(),
+            token: token.to_string(),
+            user: user.map(|user| user.to_string()),
+            at: now(),
+        });
+    }
+}
+
+impl From<&TimeControl> for StoredTimeControl {
+    fn from(control: &TimeControl) -> Self {
+        StoredTimeControl {
+            base: control.base.as_millis() as u64,
+            increment: control.increment.as_millis() as u64,
+            delay: control.delay.as_millis() as u64,
+            moves: control.moves,
+        }
+    }
+}
+
+impl StoredTimeControl {
+    pub fn time_control(&self) -> TimeControl {
+        TimeControl {
+            base: Duration::from_millis(self.base),
+            increment: Duration::from_millis(self.increment),
+            delay: Duration::from_millis(self.delay),
+            moves: self.moves,
+        }
+    }
+}
+
+impl StoredPlayer {
+    fn new(player: Player) -> StoredPlayer {
+        StoredPlayer {
+            kind: player.name().to_string(),
+            level: match player {
+                Player::Engine(skill) => Some(skill.level()),
+                _ => None,
+            },
+        }
+    }
+
+    fn player(&self) -> Player {
+        match self.kind.as_str() {
+            "engine" => Player::Engine(self.level.map_or(Skill::default(), Skill::new)),
+            "external" => Player::External,
+            _ => Player::Human,
+        }
+    }
+}
+
+fn color(side: Kind) -> &'static str {
+    match side {
+        Kind::White => "white",
+        Kind::Black => "black",
+    }
+}
+
+fn side(color: &str) -> Kind {
+    if color == "black" {
+        Kind::Black
+    } else {
+        Kind::White
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::{Action, Engines, Registry};
+    use crate::users::Users;
+
+    #[test]
+    fn test_games_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("chess-storage-{}", now()));
+        let control = TimeControl {
+            base: Duration::from_secs(60),
+            increment: Duration::from_secs(1),
+            delay: Duration::ZERO,
+            moves: None,
+        };
+        // the kind of every event with its id
+        let ids = |game: &Game| {
+            game.events_since(0)
+                .iter()
+                .map(|(id, event)| (*id, std::mem::discriminant(event)))
+                .collect::<Vec<_>>()
+        };
+        let (black, events) = {
+            let registry = Registry::with_storage(
+                Engines::default(),
+                Storage::open(&dir).unwrap(),
+                Users::default(),
+            )
+            .unwrap();
+            let new_game = |fen: &str| {
+                let start = State::from_fen(fen).unwrap();
+                let limits = Limits::depth(3);
+                registry
+                    .create(
+                        start,
+                        Player::Human,
+                        Player::Human,
+                        limits,
+                        Some(control),
+                        false,
+                    )
+                    .unwrap()
+            };
+            let unfinished = new_game("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+            let (_, white) = unfinished
+                .lock()
+                .unwrap()
+                .join(Some(Kind::White), None)
+                .unwrap();
+            let (_, black) = unfinished.lock().unwrap().join(None, None).unwrap();
+            registry.play(&unfinished, &white, "a1a7").unwrap();
+            registry.chat(&unfinished, &black, "well played").unwrap();
+            registry
+                .act(&unfinished, &white, Action::OfferDraw)
+                .unwrap();
+            registry
+                .act(&unfinished, &black, Action::DeclineDraw)
+                .unwrap();
+            for (token, mv) in [(&black, "e8d8"), (&white, "e1e2")] {
+                registry.play(&unfinished, token, mv).unwrap();
+            }
+            registry
+                .act(&unfinished, &black, Action::OfferDraw)
+                .unwrap();
+            let mated = new_game("4k3/8/4K3/8/8/8/8/R7 w - - 0 1");
+            let (_, token) = mated.lock().unwrap().join(None, None).unwrap();
+            registry.play(&mated, &token, "a1a8").unwrap();
+            assert!(mated.lock().unwrap().ending().is_some());
+            let events = ids(&unfinished.lock().unwrap());
+            (black, events)
+        };
+        // a line cut short by a crash
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(GAMES_FILE))
+            .unwrap();
+        write!(file, "{{\"type\":\"move\",\"id\":1,").unwrap();
+        drop(file);
+
+        let registry = Registry::with_storage(
+            Engines::default(),
+            Storage::open(&dir).unwrap(),
+            Users::default(),
+        )
+        .unwrap();
+        let games = registry.list();
+        assert_eq!(games.len(), 2);
+        // finished games come back with their results
+        let mated = games[1].lock().unwrap().ending().cloned().unwrap();
+        assert_eq!((mated.winner, mated.aborted), (Some(Kind::White), false));
+        let game = games[0].lock().unwrap();
+        // seated players keep their seats
+        assert_eq!(game.seat(&black), Some(Kind::Black));
+        assert_eq!(game.moves().len(), 3);
+        assert_eq!(game.state().side, Kind::Black);
+        assert_eq!(game.limits().depth, Some(3));
+        assert_eq!(game.time_control(), Some(&control));
+        assert_eq!(game.draw_offer(), Some(Kind::Black));
+        // chat comes back between the moves it was sent between
+        let chat = game.chat(Room::Players);
+        assert_eq!(chat.len(), 1);
+        assert_eq!(chat[0].text, "well played");
+        assert_eq!(chat[0].side, Some(Kind::Black));
+        let moves_before = game
+            .events_since(0)
+            .into_iter()
+            .take_while(|(_, event)| !matches!(event, Event::Chat(_)))
+            .filter(|(_, event)| matches!(event, Event::Move { .. }))
+            .count();
+        assert_eq!(moves_before, 1);
+        // events keep their ids, so followers reconnecting with the last one miss nothing
+        assert_eq!(ids(&game), events);
+        drop(game);
+        // new games do not reuse ids of finished ones
+        let game = registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+                false,
+            )
+            .unwrap();
+        assert_eq!(game.lock().unwrap().id(), 3);
+        let (_, token) = game.lock().unwrap().join(None, None).unwrap();
+        registry.play(&game, &token, "e2e4").unwrap();
+        drop(registry);
+
+        // the records after the broken line still count
+        let storage = Storage::open(&dir).unwrap();
+        let games = storage.load().unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[2].moves.len(), 1);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_finished_games_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("chess-finished-{}", now()));
+        let open = || {
+            Registry::with_storage(
+                Engines::default(),
+                Storage::open(&dir).unwrap(),
+                Users::default(),
+            )
+            .unwrap()
+        };
+        let (white, events) = {
+            let registry = open();
+            let game = registry
+                .create(
+                    State::default(),
+                    Player::Human,
+                    Player::Human,
+                    Limits::default(),
+                    None,
+                    false,
+                )
+                .unwrap();
+            let (_, white) = game.lock().unwrap().join(Some(Kind::White), None).unwrap();
+            let (_, black) = game.lock().unwrap().join(None, None).unwrap();
+            registry.play(&game, &white, "e2e4").unwrap();
+            registry.play(&game, &black, "e7e5").unwrap();
+            registry.act(&game, &white, Action::Resign).unwrap();
+            let events = game
+                .lock()
+                .unwrap()
+                .events_since(0)
+                .iter()
+                .map(|(id, event)| (*id, std::mem::discriminant(event)))
+                .collect::<Vec<_>>();
+            (white, events)
+        };
+
+        // a finished game is read back with its moves, it cannot be played on
+        let registry = open();
+        let restored = registry.get(1).unwrap();
+        let game = restored.lock().unwrap();
+        let ending = game.ending().unwrap();
+        assert_eq!(ending.winner, Some(Kind::Black));
+        assert_eq!(ending.reason, "White resigns");
+        assert_eq!(game.history().len(), 2);
+        let mut after_e4 = State::default();
+        after_e4.make_move(game.moves()[0]);
+        assert_eq!(
+            game.position(1).map(|state| state.to_fen()),
+            Some(after_e4.to_fen())
+        );
+        assert!(game.position(3).is_none());
+        let ids = game
+            .events_since(0)
+            .iter()
+            .map(|(id, event)| (*id, std::mem::discriminant(event)))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, events);
+        drop(game);
+        assert!(registry.play(&restored, &white, "g1f3").is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,1182 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::anyhow;
+
+use crate::chess::{Castling, Kind, Move, Piece, State};
+
+// reads Syzygy endgame tablebases. WDL files (.rtbw) tell whether a position is won, drawn
+// or lost, DTZ files (.rtbz) how many plies it takes to the next capture or pawn move.
+// squares follow the tablebase generator: a1 = 0, b1 = 1, ..., h8 = 63
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+const MAX_PIECES: usize = 7;
+
+// table flags
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// outcome for the side to move. cursed wins and blessed losses are draws under the fifty-move rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1 => Wdl::CursedWin,
+            2 => Wdl::Win,
+            _ => Wdl::Draw,
+        }
+    }
+
+    fn negate(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+
+    fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed-loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed-win",
+            Wdl::Win => "win",
+        }
+    }
+}
+
+// all tables found in one directory, loaded into memory on first use
+pub struct Tablebase {
+    dir: PathBuf,
+    max_pieces: usize,
+    // keyed by file name, none if the file is missing or broken
+    tables: Mutex<HashMap<String, Option<Arc<Table>>>>,
+}
+
+impl Tablebase {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Tablebase, anyhow::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut max_pieces = 0;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rtbw") {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                if name.starts_with('K') && name.contains('v') {
+                    max_pieces = max_pieces.max(name.len() - 1);
+                }
+            }
+        }
+        if max_pieces == 0 {
+            return Err(anyhow!("No tablebase files in {}", dir.display()));
+        }
+        Ok(Tablebase {
+            dir,
+            max_pieces: max_pieces.min(MAX_PIECES),
+            tables: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // positions with more pieces can't be probed
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn can_probe(&self, state: &State) -> bool {
+        state.castling == Castling::none() && piece_count(state) <= self.max_pieces
+    }
+
+    pub fn probe_wdl(&self, state: &mut State) -> Option<Wdl> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        self.search(state, false).map(|(wdl, _)| wdl)
+    }
+
+    // plies to the next zeroing move, negative when losing, 0 for draws. one off in positions
+    // where the table stores moves instead of plies
+    pub fn probe_dtz(&self, state: &mut State) -> Option<i32> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        self.dtz(state)
+    }
+
+    // every legal move with a rank, higher is better: quick wins, then draws, then slow losses
+    pub fn rank_root_moves(&self, state: &mut State) -> Option<Vec<(Move, i32)>> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        let halfmove = state.halfmove as i32;
+        let mut ranked = Vec::new();
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            let dtz = if state.halfmove == 0 {
+                self.search(state, false)
+                    .map(|(wdl, _)| dtz_before_zeroing(wdl.negate()))
+            } else if state.is_repetition() {
+                Some(0)
+            } else {
+                self.dtz(state).map(|dtz| -dtz + (-dtz).signum())
+            };
+            let mate = state.in_check() && state.legal_moves().is_empty();
+            state.unmake_move(undo);
+
+            let dtz = if mate { 1 } else { dtz? };
+            let rank = match dtz {
+                dtz if dtz > 0 && dtz + halfmove <= 100 => 1000 - dtz,
+                dtz if dtz < 0 && -dtz + halfmove <= 100 => -1000 - dtz,
+                _ => 0,
+            };
+            ranked.push((mv, rank));
+        }
+        Some(ranked)
+    }
+
+    // wdl with a search through captures, since tables don't know about en passant and may
+    // store anything where a capture is best. the flag tells if the best move is zeroing
+    fn search(&self, state: &mut State, check_zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = state.legal_moves();
+        let total = moves.len();
+        let mut searched = 0;
+        let mut best = Wdl::Loss;
+
+        for mv in moves {
+            let capture = state.is_capture(&mv);
+            if !capture && (!check_zeroing || !is_pawn_move(state, &mv)) {
+                continue;
+            }
+            searched += 1;
+            let undo = state.make_move(mv);
+            let value = self.search(state, false).map(|(wdl, _)| wdl.negate());
+            state.unmake_move(undo);
+
+            let value = value?;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == total;
+        let value = if no_more_moves {
+            best
+        } else {
+            match self.probe_table(state, false, best)? {
+                Probe::Value(value) => Wdl::from_value(value),
+                Probe::ChangeStm => return None,
+            }
+        };
+
+        // the table may store anything when a capture wins
+        if best >= value {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, state: &mut State) -> Option<i32> {
+        let (wdl, zeroing) = self.search(state, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        if let Probe::Value(dtz) = self.probe_table(state, true, wdl)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+
+        // the table only has the other side to move, look one ply ahead
+        let mut min_dtz = 0xffff;
+        for mv in state.legal_moves() {
+            let zeroing = state.is_capture(&mv) || is_pawn_move(state, &mv);
+            let undo = state.make_move(mv);
+            let dtz = if zeroing {
+                self.search(state, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.dtz(state).map(|dtz| -dtz)
+            };
+            if dtz == Some(1) && state.in_check() && state.legal_moves().is_empty() {
+                min_dtz = 1;
+            }
+            state.unmake_move(undo);
+
+            let mut dtz = dtz?;
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xffff { -1 } else { min_dtz })
+    }
+
+    fn probe_table(&self, state: &State, dtz: bool, wdl: Wdl) -> Option<Probe> {
+        let pieces = pieces(state);
+        if pieces.len() == 2 {
+            return Some(Probe::Value(Wdl::Draw as i32));
+        }
+        if pieces.len() > self.max_pieces {
+            return None;
+        }
+        let white = material(&pieces, Kind::White);
+        let black = material(&pieces, Kind::Black);
+        // files are named with the stronger side first
+        let table = self
+            .table(&format!("{}v{}", white, black), dtz)
+            .or_else(|| self.table(&format!("{}v{}", black, white), dtz))?;
+        Some(table.probe(state, &pieces, white, wdl))
+    }
+
+    fn table(&self, name: &str, dtz: bool) -> Option<Arc<Table>> {
+        let file = format!("{}.{}", name, if dtz { "rtbz" } else { "rtbw" });
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .entry(file.clone())
+            .or_insert_with(|| match Table::load(&self.dir.join(&file), name, dtz) {
+                Ok(table) => Some(Arc::new(table)),
+                Err(e) => {
+                    if self.dir.join(&file).exists() {
+                        warn!("tablebase {}: {}", file, e);
+                    }
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+enum Probe {
+    Value(i32),
+    // dtz tables store one side to move only
+    ChangeStm,
+}
+
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+fn is_pawn_move(state: &State, mv: &Move) -> bool {
+    matches!(state.board[mv.from.0][mv.from.1], Some(p) if p.piece == Piece::Pawn)
+}
+
+fn piece_count(state: &State) -> usize {
+    state.board.iter().flatten().flatten().count()
+}
+
+// piece code used in the files: pawn 1 .. king 6, black pieces have 8 added
+fn piece_code(kind: Kind, piece: Piece) -> u8 {
+    let code = match piece {
+        Piece::Pawn => 1,
+        Piece::Knight => 2,
+        Piece::Bishop => 3,
+        Piece::Rook => 4,
+        Piece::Queen => 5,
+        Piece::King => 6,
+    };
+    match kind {
+        Kind::White => code,
+        Kind::Black => code + 8,
+    }
+}
+
+// (square, piece code) in square order
+fn pieces(state: &State) -> Vec<(usize, u8)> {
+    (0..64)
+        .filter_map(|sq| state.board[7 - sq / 8][sq % 8].map(|p| (sq, piece_code(p.kind, p.piece))))
+        .collect()
+}
+
+// one side's pieces as in the file names, e.g. "KRP"
+fn material(pieces: &[(usize, u8)], kind: Kind) -> String {
+    let base = if kind == Kind::White { 0 } else { 8 };
+    "KQRBNP"
+        .chars()
+        .zip([6, 5, 4, 3, 2, 1])
+        .flat_map(|(letter, code)| {
+            let count = pieces.iter().filter(|(_, c)| *c == code + base).count();
+            std::iter::repeat_n(letter, count)
+        })
+        .collect()
+}
+
+// lookup tables of the index encoding
+struct Encoding {
+    binomial: [[u64; 64]; 6],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [u64; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+fn off_a1h8(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut e = Encoding {
+            binomial: [[0; 64]; 6],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        // squares below the a1-h8 diagonal
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                e.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle, diagonal squares last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..28 {
+            if off_a1h8(sq) < 0 && sq % 8 <= 3 {
+                e.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // the 462 legal placements of two kings with the first one in the triangle
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if e.map_a1d1d4[s1] != idx as u64 || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let touching = (s1 / 8).abs_diff(s2 / 8) <= 1 && (s1 % 8).abs_diff(s2 % 8) <= 1;
+                    if touching || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // pawn squares a2-h7 counting down from the edges, the leading pawn has the highest value
+        let mut available: i32 = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        e.map_pawns[sq] = available as usize;
+                        e.map_pawns[sq ^ 7] = (available - 1) as usize;
+                        available -= 2;
+                    }
+                    e.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += e.binomial[lead_pawns - 1][e.map_pawns[sq]];
+                }
+                e.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        e
+    })
+}
+
+// decoding state of one sub-table, per side to move and leading pawn file.
+// offsets point into the table's bytes
+#[derive(Clone, Debug, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    block_size: usize,
+    span: usize,
+    num_blocks: usize,
+    lowest_sym: usize,
+    btree: usize,
+    symlen: Vec<u8>,
+    base64: Vec<u64>,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [usize; 4],
+}
+
+struct Table {
+    bytes: Vec<u8>,
+    dtz: bool,
+    // material of the stronger side, the one listed first in the file name
+    white: String,
+    symmetric: bool,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // pawns of the leading color and of the other one
+    pawn_count: [usize; 2],
+    items: [[PairsData; 4]; 2],
+    // dtz value map
+    map: usize,
+}
+
+impl Table {
+    fn load(path: &Path, name: &str, dtz: bool) -> Result<Table, anyhow::Error> {
+        let bytes = fs::read(path)?;
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.len() < 5 || bytes[..4] != magic {
+            return Err(anyhow!("Not a tablebase file"));
+        }
+
+        let (white, black) = name
+            .split_once('v')
+            .ok_or_else(|| anyhow!("Bad table name"))?;
+        let count = |side: &str, letter: char| side.chars().filter(|c| *c == letter).count();
+        let has_unique_pieces = [white, black]
+            .iter()
+            .any(|side| "QRBNP".chars().any(|letter| count(side, letter) == 1));
+        // the side with fewer pawns leads because it compresses better
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+
+        let mut table = Table {
+            bytes,
+            dtz,
+            white: white.to_string(),
+            symmetric: white == black,
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            items: Default::default(),
+            map: 0,
+        };
+        table.parse()?;
+        Ok(table)
+    }
+
+    fn byte(&self, at: usize) -> u8 {
+        self.bytes.get(at).copied().unwrap_or(0)
+    }
+
+    fn u16_le(&self, at: usize) -> usize {
+        u16::from_le_bytes([self.byte(at), self.byte(at + 1)]) as usize
+    }
+
+    fn u32_le(&self, at: usize) -> usize {
+        u32::from_le_bytes([
+            self.byte(at),
+            self.byte(at + 1),
+            self.byte(at + 2),
+            self.byte(at + 3),
+        ]) as usize
+    }
+
+    fn u32_be(&self, at: usize) -> u64 {
+        u32::from_be_bytes([
+            self.byte(at),
+            self.byte(at + 1),
+            self.byte(at + 2),
+            self.byte(at + 3),
+        ]) as u64
+    }
+
+    fn u64_be(&self, at: usize) -> u64 {
+        self.u32_be(at) << 32 | self.u32_be(at + 4)
+    }
+
+    // left and right child of a symbol, 12 bits each
+    fn left(&self, d: &PairsData, sym: usize) -> usize {
+        let at = d.btree + sym * 3;
+        ((self.byte(at + 1) as usize & 0xf) << 8) | self.byte(at) as usize
+    }
+
+    fn right(&self, d: &PairsData, sym: usize) -> usize {
+        let at = d.btree + sym * 3;
+        ((self.byte(at + 2) as usize) << 4) | (self.byte(at + 1) as usize >> 4)
+    }
+
+    fn sides(&self) -> usize {
+        if self.dtz || self.symmetric {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        &self.items[stm % if self.dtz { 1 } else { 2 }][if self.has_pawns { file } else { 0 }]
+    }
+
+    // walks the header and records where every part of every sub-table starts
+    fn parse(&mut self) -> Result<(), anyhow::Error> {
+        let mut p = 4;
+        if (self.byte(p) & 2 != 0) != self.has_pawns {
+            return Err(anyhow!("Pawn flag doesn't match the name"));
+        }
+        p += 1;
+
+        let sides = self.sides();
+        let max_file = if self.has_pawns { 3 } else { 0 };
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+
+        for file in 0..=max_file {
+            let next = if both_pawns { self.byte(p + 1) } else { 0xff };
+            let order = [
+                [(self.byte(p) & 0xf) as usize, (next & 0xf) as usize],
+                [(self.byte(p) >> 4) as usize, (next >> 4) as usize],
+            ];
+            p += 1 + both_pawns as usize;
+            for k in 0..self.piece_count {
+                let byte = self.byte(p);
+                for side in 0..sides {
+                    self.items[side][file].pieces[k] =
+                        if side == 1 { byte >> 4 } else { byte & 0xf };
+                }
+                p += 1;
+            }
+            for (side, order) in order.iter().enumerate().take(sides) {
+                self.set_groups(side, file, *order);
+            }
+        }
+        p += p & 1;
+
+        for file in 0..=max_file {
+            for side in 0..sides {
+                p = self.set_sizes(side, file, p);
+            }
+        }
+        if self.dtz {
+            p = self.set_dtz_map(p, max_file);
+        }
+        for file in 0..=max_file {
+            for side in 0..sides {
+                let d = &mut self.items[side][file];
+                d.sparse_index = p;
+                p += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..=max_file {
+            for side in 0..sides {
+                let d = &mut self.items[side][file];
+                d.block_length = p;
+                p += d.block_length_size * 2;
+            }
+        }
+        for file in 0..=max_file {
+            for side in 0..sides {
+                p = (p + 0x3f) & !0x3f;
+                let d = &mut self.items[side][file];
+                d.data = p;
+                p += d.num_blocks * d.block_size;
+            }
+        }
+
+        if p > self.bytes.len() {
+            return Err(anyhow!("Truncated tablebase file"));
+        }
+        Ok(())
+    }
+
+    // splits the pieces into groups of identical pieces and sizes each group's index range
+    fn set_groups(&mut self, side: usize, file: usize, order: [usize; 2]) {
+        let e = encoding();
+        let has_pawns = self.has_pawns;
+        let both_pawns = has_pawns && self.pawn_count[1] > 0;
+        let mut first_len: i32 = if has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        let d = &mut self.items[side][file];
+
+        let mut n = 0;
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {
+                    e.lead_pawns_size[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= e.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= e.binomial[d.group_len[next]][free];
+                free -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    // reads the huffman code description of a sub-table
+    fn set_sizes(&mut self, side: usize, file: usize, mut p: usize) -> usize {
+        let flags = self.byte(p);
+        p += 1;
+        if flags & SINGLE_VALUE != 0 {
+            let value = self.byte(p);
+            let d = &mut self.items[side][file];
+            d.flags = flags;
+            d.min_sym_len = value;
+            return p + 1;
+        }
+
+        let block_size = 1 << self.byte(p);
+        let span = 1 << self.byte(p + 1);
+        let padding = self.byte(p + 2) as usize;
+        let num_blocks = self.u32_le(p + 3);
+        let max_sym_len = self.byte(p + 7) as usize;
+        let min_sym_len = self.byte(p + 8) as usize;
+        let lowest_sym = p + 9;
+        p = lowest_sym;
+
+        // canonical huffman: base64[i] is the lowest code of length min_sym_len + i, left aligned
+        let lengths = (max_sym_len + 1).saturating_sub(min_sym_len).max(1);
+        let mut base64 = vec![0u64; lengths];
+        for i in (0..lengths - 1).rev() {
+            base64[i] = base64[i + 1]
+                .wrapping_add(self.u16_le(lowest_sym + 2 * i) as u64)
+                .wrapping_sub(self.u16_le(lowest_sym + 2 * (i + 1)) as u64)
+                / 2;
+        }
+        for (i, base) in base64.iter_mut().enumerate() {
+            *base = base.checked_shl((64 - i - min_sym_len) as u32).unwrap_or(0);
+        }
+        p += lengths * 2;
+
+        let symbols = self.u16_le(p);
+        p += 2;
+        let btree = p;
+
+        let d = &mut self.items[side][file];
+        let tb_size = d.group_idx[d.group_len.iter().position(|&len| len == 0).unwrap_or(0)];
+        d.flags = flags;
+        d.block_size = block_size;
+        d.span = span;
+        d.sparse_index_size = (tb_size as usize).div_ceil(span);
+        d.num_blocks = num_blocks;
+        d.block_length_size = num_blocks + padding;
+        d.min_sym_len = min_sym_len as u8;
+        d.lowest_sym = lowest_sym;
+        d.base64 = base64;
+        d.btree = btree;
+
+        // how many values each symbol expands to, minus one
+        let d = &self.items[side][file];
+        let mut symlen = vec![0u8; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                symlen[sym] = self.set_symlen(d, sym, &mut symlen, &mut visited);
+            }
+        }
+        self.items[side][file].symlen = symlen;
+
+        btree + symbols * 3 + (symbols & 1)
+    }
+
+    fn set_symlen(&self, d: &PairsData, sym: usize, symlen: &mut [u8], visited: &mut [bool]) -> u8 {
+        visited[sym] = true;
+        let right = self.right(d, sym);
+        if right == 0xfff {
+            return 0;
+        }
+        let left = self.left(d, sym);
+        if left >= symlen.len() || right >= symlen.len() {
+            return 0;
+        }
+        if !visited[left] {
+            symlen[left] = self.set_symlen(d, left, symlen, visited);
+        }
+        if !visited[right] {
+            symlen[right] = self.set_symlen(d, right, symlen, visited);
+        }
+        symlen[left].wrapping_add(symlen[right]).wrapping_add(1)
+    }
+
+    // dtz values may be stored through a per-outcome map
+    fn set_dtz_map(&mut self, mut p: usize, max_file: usize) -> usize {
+        self.map = p;
+        for file in 0..=max_file {
+            let flags = self.items[0][file].flags;
+            if flags & MAPPED == 0 {
+                continue;
+            }
+            let mut map_idx = [0; 4];
+            if flags & WIDE != 0 {
+                p += p & 1;
+                for idx in map_idx.iter_mut() {
+                    *idx = (p - self.map) / 2 + 1;
+                    p += 2 * self.u16_le(p) + 2;
+                }
+            } else {
+                for idx in map_idx.iter_mut() {
+                    *idx = p - self.map + 1;
+                    p += self.byte(p) as usize + 1;
+                }
+            }
+            self.items[0][file].map_idx = map_idx;
+        }
+        p + (p & 1)
+    }
+
+    // the value stored at an index
+    fn decompress(&self, d: &PairsData, idx: u64) -> i32 {
+        if d.flags & SINGLE_VALUE != 0 {
+            return d.min_sym_len as i32;
+        }
+
+        // the sparse index points close to the block holding idx
+        let span = d.span as u64;
+        let k = (idx / span) as usize;
+        let mut block = self.u32_le(d.sparse_index + 6 * k);
+        let mut offset = self.u16_le(d.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % span) as i64 - (span / 2) as i64;
+
+        let block_length = |block: usize| self.u16_le(d.block_length + 2 * block) as i64;
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        // walk the huffman coded symbols of the block until the one covering offset
+        let mut ptr = d.data + block * d.block_size;
+        let mut buf = self.u64_be(ptr);
+        ptr += 8;
+        let mut buf_size: usize = 64;
+        let min = d.min_sym_len as usize;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < d.base64.len() && buf < d.base64[len] {
+                len += 1;
+            }
+            sym = (buf - d.base64[len])
+                .checked_shr((64 - len - min) as u32)
+                .unwrap_or(0) as usize;
+            sym += self.u16_le(d.lowest_sym + 2 * len);
+            let expands = *d.symlen.get(sym).unwrap_or(&0) as i64 + 1;
+            if offset < expands {
+                break;
+            }
+            offset -= expands;
+            buf = buf.checked_shl((len + min) as u32).unwrap_or(0);
+            buf_size = buf_size.saturating_sub(len + min);
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= self.u32_be(ptr) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // symbols expand into pairs of symbols, descend to the leaf holding offset
+        while *d.symlen.get(sym).unwrap_or(&0) != 0 {
+            let left = self.left(d, sym);
+            let expands = *d.symlen.get(left).unwrap_or(&0) as i64 + 1;
+            if offset < expands {
+                sym = left;
+            } else {
+                offset -= expands;
+                sym = self.right(d, sym);
+            }
+        }
+        self.left(d, sym) as i32
+    }
+
+    // computes the index of the position and looks up its value
+    fn probe(&self, state: &State, all: &[(usize, u8)], white: String, wdl: Wdl) -> Probe {
+        let e = encoding();
+        // tables are stored with the stronger side as white, and symmetric ones for white to move
+        let flip = (self.symmetric && state.side == Kind::Black) || white != self.white;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ state.side.index();
+
+        let mut squares = Vec::with_capacity(all.len());
+        let mut pieces = Vec::with_capacity(all.len());
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        let lead = self.items[0][0].pieces[0] ^ flip_color;
+        if self.has_pawns {
+            for &(sq, piece) in all.iter().filter(|(_, piece)| *piece == lead) {
+                squares.push(sq ^ flip_squares);
+                pieces.push(piece ^ flip_color);
+            }
+            lead_pawns = squares.len();
+            // the leading pawn is the one closest to the edge and then to rank 2
+            let first = (0..lead_pawns)
+                .max_by_key(|&i| e.map_pawns[squares[i]])
+                .unwrap_or(0);
+            squares.swap(0, first);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        if self.dtz {
+            let flags = self.get(stm, file).flags;
+            // symmetric pawnless tables serve both sides
+            let both_sides = self.symmetric && !self.has_pawns;
+            if (flags & STM) as usize != stm && !both_sides {
+                return Probe::ChangeStm;
+            }
+        }
+
+        for &(sq, piece) in all {
+            if !(self.has_pawns && piece == lead) {
+                squares.push(sq ^ flip_squares);
+                pieces.push(piece ^ flip_color);
+            }
+        }
+        let size = squares.len();
+        let d = self.get(stm, file);
+
+        // same piece order as the table
+        for i in lead_pawns..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // mirror the leading piece onto files a-d
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|sq| *sq ^= 7);
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = e.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&sq| e.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += e.binomial[i][e.map_pawns[sq]];
+            }
+        } else {
+            // and onto ranks 1-4
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|sq| *sq ^= 56);
+            }
+            // and below the a1-h8 diagonal
+            for i in 0..d.group_len[0] {
+                let off = off_a1h8(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in squares[i..].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                let rank = |sq: usize| (sq / 8) as u64;
+                idx = if off_a1h8(s0) != 0 {
+                    (e.map_a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+                } else if off_a1h8(s1) != 0 {
+                    (6 * 63 + rank(s0) * 28 + e.map_b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+                } else if off_a1h8(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s0) * 7 * 28
+                        + (rank(s1) - adjust1) * 28
+                        + e.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s0) * 7 * 6
+                        + (rank(s1) - adjust1) * 6
+                        + (rank(s2) - adjust2)
+                };
+            } else {
+                idx = e.map_kk[e.map_a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // the remaining groups, each as a combination of the squares left free
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                let free = sq - adjust - if remaining_pawns { 8 } else { 0 };
+                n += e.binomial[i + 1][free];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = self.decompress(d, idx);
+        if !self.dtz {
+            return Probe::Value(value - 2);
+        }
+        Probe::Value(self.map_dtz(file, value, wdl))
+    }
+
+    // turns a stored dtz value into plies
+    fn map_dtz(&self, file: usize, mut value: i32, wdl: Wdl) -> i32 {
+        let d = self.get(0, file);
+        if d.flags & MAPPED != 0 {
+            let map = [1, 3, 0, 2, 0][(wdl as i32 + 2) as usize];
+            let at = d.map_idx[map] + value as usize;
+            value = if d.flags & WIDE != 0 {
+                self.u16_le(self.map + 2 * at) as i32
+            } else {
+                self.byte(self.map + at) as i32
+            };
+        }
+        if (wdl == Wdl::Win && d.flags & WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && d.flags & LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_tables() {
+        let e = encoding();
+        // every legal king pair gets its own index
+        let mut codes: Vec<u64> = (0..10)
+            .flat_map(|idx| (0..64).map(move |sq| (idx, sq)))
+            .filter(|&(idx, sq)| e.map_kk[idx][sq] != 0)
+            .map(|(idx, sq)| e.map_kk[idx][sq])
+            .collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), 461);
+        assert_eq!(codes.last(), Some(&461));
+
+        assert_eq!(e.binomial[2][6], 15);
+        assert_eq!(e.map_pawns[8], 47);
+        assert_eq!(e.map_pawns[15], 46);
+        assert_eq!(e.lead_pawns_size[1], [6, 6, 6, 6]);
+    }
+
+    #[test]
+    fn test_material_names() {
+        let state = State::from_fen("8/8/4k3/8/2P5/8/3KR3/8 w - - 0 1").unwrap();
+        let pieces = pieces(&state);
+        assert_eq!(material(&pieces, Kind::White), "KRP");
+        assert_eq!(material(&pieces, Kind::Black), "K");
+        // c4 is square 26
+        assert!(pieces.contains(&(26, 1)));
+    }
+
+    #[test]
+    fn test_probe() {
+        // KRvK tables written by testdata/syzygy/generate.py from its own solution. the
+        // generator follows this reader's idea of the format, so this only shows the two agree
+        // and the probing around them works. `test_probe_published_tables` checks the reader
+        // against the real tables
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/syzygy");
+        let tablebase = Tablebase::open(dir).unwrap();
+        assert_eq!(tablebase.max_pieces(), 3);
+        let probe = |fen: &str| {
+            let mut state = State::from_fen(fen).unwrap();
+            let wdl = tablebase.probe_wdl(&mut state);
+            (wdl, tablebase.probe_dtz(&mut state))
+        };
+
+        // mate in one, and mated
+        assert_eq!(
+            probe("7k/8/6K1/8/8/8/8/R7 w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        assert_eq!(
+            probe("R6k/8/6K1/8/8/8/8/8 b - - 0 1"),
+            (Some(Wdl::Loss), Some(-1))
+        );
+        // the dtz table only has white to move, black's side is looked up one ply ahead
+        assert_eq!(
+            probe("8/8/8/4k3/8/8/8/K6R w - - 0 1"),
+            (Some(Wdl::Win), Some(29))
+        );
+        assert_eq!(
+            probe("8/8/8/4k3/8/8/8/K6R b - - 0 1"),
+            (Some(Wdl::Loss), Some(-30))
+        );
+        // the king takes the rook
+        assert_eq!(
+            probe("8/8/8/8/8/8/6kR/K7 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+        // black with the rook reads the same table with the colors swapped
+        assert_eq!(
+            probe("r6K/8/6k1/8/8/8/8/8 w - - 0 1"),
+            (Some(Wdl::Loss), Some(-1))
+        );
+        assert_eq!(
+            probe("k6r/8/8/8/4K3/8/8/8 b - - 0 1"),
+            (Some(Wdl::Win), Some(29))
+        );
+
+        let mut state = State::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let ranked = tablebase.rank_root_moves(&mut state).unwrap();
+        let best = ranked.iter().max_by_key(|(_, rank)| *rank).unwrap();
+        assert_eq!(best.0.to_string(), "a1a8");
+    }
+
+    // the published tables are not kept in the repository. with the KRvK and KQvK .rtbw and
+    // .rtbz files in the directory `SYZYGY_TEST_PATH` names, run `cargo test -- --ignored`
+    #[test]
+    #[ignore = "needs the published KRvK and KQvK tables in SYZYGY_TEST_PATH"]
+    fn test_probe_published_tables() {
+        let dir = std::env::var("SYZYGY_TEST_PATH").expect("SYZYGY_TEST_PATH is set");
+        let tablebase = Tablebase::open(dir).unwrap();
+        let probe = |fen: &str| {
+            let mut state = State::from_fen(fen).unwrap();
+            let wdl = tablebase.probe_wdl(&mut state);
+            (wdl, tablebase.probe_dtz(&mut state))
+        };
+        // a table keeping moves rather than plies may round the distance up by one
+        let near = |dtz: Option<i32>, plies: i32| dtz.is_some_and(|dtz| (dtz - plies).abs() <= 1);
+
+        // mate in one, the mate in 15 of the generated table and the king taking the rook
+        assert_eq!(
+            probe("7k/8/6K1/8/8/8/8/R7 w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        let (wdl, dtz) = probe("8/8/8/4k3/8/8/8/K6R w - - 0 1");
+        assert_eq!(wdl, Some(Wdl::Win));
+        assert!(near(dtz, 29), "{:?}", dtz);
+        let (wdl, dtz) = probe("8/8/8/4k3/8/8/8/K6R b - - 0 1");
+        assert_eq!(wdl, Some(Wdl::Loss));
+        assert!(near(dtz, -30), "{:?}", dtz);
+        assert_eq!(
+            probe("8/8/8/8/8/8/6kR/K7 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+        let (wdl, dtz) = probe("k6r/8/8/8/4K3/8/8/8 b - - 0 1");
+        assert_eq!(wdl, Some(Wdl::Win));
+        assert!(near(dtz, 29), "{:?}", dtz);
+
+        // KQvK: mate in one, the king taking the queen and stalemate
+        assert_eq!(
+            probe("7k/8/6K1/8/8/8/8/Q7 w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        assert_eq!(
+            probe("8/8/8/8/8/8/4kQ2/K7 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+        assert_eq!(
+            probe("k7/8/1QK5/8/8/8/8/8 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chess::Move;
+
+// time kept in reserve for communication and scheduling lag
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+// assumed number of moves left when the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// what the search is allowed to spend. only the clock fields get a budget allocated,
+// depth, nodes and movetime are fixed limits for reproducible testing
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // remaining time on our own clock
+    pub time: Option<Duration>,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+    // search until told to stop
+    pub infinite: bool,
+    // search the predicted position until `ponderhit`, then play on the clock limits
+    pub ponder: bool,
+    // find a mate in this many moves
+    pub mate: Option<u32>,
+    // restrict the root to these moves, all legal moves when empty
+    pub search_moves: Vec<Move>,
+}
+
+impl Limits {
+    pub fn depth(depth: u32) -> Limits {
+        Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        }
+    }
+}
+
+// flags shared with whoever controls a running search
+#[derive(Clone, Debug, Default)]
+pub struct Signals {
+    // stop as soon as possible and return the last completed iteration
+    pub stop: Arc<AtomicBool>,
+    // while set the clock limits don't apply, cleared on `ponderhit`
+    pub ponder: Arc<AtomicBool>,
+}
+
+// decides when the search should stop. `soft` is the budget checked between iterations
+// and stretched when the best move is unstable, `hard` aborts an iteration midway
+pub struct TimeManager {
+    start: Instant,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    nodes: Option<u64>,
+    signals: Signals,
+    pondering: bool,
+    scale: f64,
+    last_best: Option<String>,
+    last_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &Limits, signals: Signals) -> TimeManager {
+        let (soft, hard) = if limits.infinite {
+            (None, None)
+        } else if let Some(movetime) = limits.movetime {
+            let movetime = movetime
+                .saturating_sub(MOVE_OVERHEAD)
+                .max(Duration::from_millis(1));
+            (Some(movetime), Some(movetime))
+        } else if let Some(time) = limits.time {
+            let (soft, hard) = allocate(time, limits.increment, limits.moves_to_go);
+            (Some(soft), Some(hard))
+        } else {
+            (None, None)
+        };
+
+        TimeManager {
+            start: Instant::now(),
+            soft,
+            hard,
+            nodes: limits.nodes,
+            pondering: signals.ponder.load(Ordering::Relaxed),
+            signals,
+            scale: 1.0,
+            last_best: None,
+            last_score: None,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // the clock starts running once pondering turns into a real search
+    fn check_ponderhit(&mut self) -> bool {
+        if self.pondering && !self.signals.ponder.load(Ordering::Relaxed) {
+            self.pondering = false;
+            self.start = Instant::now();
+        }
+        self.pondering
+    }
+
+    // polled from inside the search
+    pub fn should_stop(&mut self, nodes: u64) -> bool {
+        if self.signals.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.check_ponderhit() {
+            return false;
+        }
+        if matches!(self.nodes, Some(limit) if nodes >= limit) {
+            return true;
+        }
+        matches!(self.hard, Some(hard) if self.elapsed() >= hard)
+    }
+
+    // called after every completed iteration with its best move and score
+    pub fn update(&mut self, best_move: &str, score: i32) {
+        if let Some(last) = &self.last_best {
+            if last != best_move {
+                // the best move changed, spend more to settle it
+                self.scale = (self.scale * 1.4).min(2.5);
+            } else {
+                self.scale = (self.scale * 0.9).max(0.5);
+            }
+        }
+        if let Some(last) = self.last_score {
+            if last - score >= 30 {
+                // the score is dropping, look for a way out
+                self.scale = (self.scale * 1.3).min(2.5);
+            }
+        }
+        self.last_best = Some(best_move.to_string());
+        self.last_score = Some(score);
+    }
+
+    // true if there is enough of the soft budget left for another iteration
+    pub fn can_deepen(&mut self) -> bool {
+        if self.check_ponderhit() {
+            return true;
+        }
+        match self.soft {
+            // the next iteration usually takes longer than all previous ones together
+            Some(soft) => self.elapsed().as_secs_f64() < soft.as_secs_f64() * self.scale * 0.6,
+            None => true,
+        }
+    }
+}
+
+// splits the remaining time into a soft and a hard budget for this move
+fn allocate(time: Duration, increment: Duration, moves_to_go: Option<u32>) -> (Duration, Duration) {
+    let available = time.saturating_sub(MOVE_OVERHEAD);
+    let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+    let hard = (available * 3 / 4).max(Duration::from_millis(1));
+    let soft = (available / moves_to_go + increment * 3 / 4).min(hard);
+    let hard = (soft * 4).min(hard);
+    (soft, hard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocation_stays_within_clock() {
+        let (soft, hard) = allocate(Duration::from_secs(60), Duration::ZERO, None);
+        assert!(soft < hard);
+        assert!(hard < Duration::from_secs(60));
+        assert_eq!(soft, Duration::from_millis(59970) / 30);
+
+        // last move before the time control may use most of the clock but never all of it
+        let (soft, hard) = allocate(Duration::from_secs(10), Duration::ZERO, Some(1));
+        assert_eq!(soft, hard);
+        assert!(hard < Duration::from_secs(10));
+
+        // increment is mostly spent
+        let (soft, _) = allocate(Duration::from_secs(60), Duration::from_secs(2), None);
+        assert!(soft > Duration::from_millis(59970) / 30 + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_unstable_best_move_extends_budget() {
+        let limits = Limits {
+            time: Some(Duration::from_secs(60)),
+            ..Limits::default()
+        };
+        let mut timer = TimeManager::new(&limits, Signals::default());
+        timer.update("e2e4", 20);
+        timer.update("d2d4", 20);
+        assert!(timer.scale > 1.0);
+
+        timer.update("d2d4", -40);
+        assert!(timer.scale > 1.4);
+    }
+
+    #[test]
+    fn test_stop_flag_and_node_limit() {
+        let signals = Signals::default();
+        let mut timer = TimeManager::new(
+            &Limits {
+                nodes: Some(1000),
+                ..Limits::default()
+            },
+            signals.clone(),
+        );
+        assert!(!timer.should_stop(999));
+        assert!(timer.should_stop(1000));
+
+        signals.stop.store(true, Ordering::Relaxed);
+        assert!(timer.should_stop(0));
+    }
+
+    #[test]
+    fn test_ponder_ignores_clock_until_ponderhit() {
+        let signals = Signals::default();
+        signals.ponder.store(true, Ordering::Relaxed);
+        let mut timer = TimeManager::new(
+            &Limits {
+                movetime: Some(Duration::from_millis(40)),
+                ponder: true,
+                ..Limits::default()
+            },
+            signals.clone(),
+        );
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!timer.should_stop(0));
+        assert!(timer.can_deepen());
+
+        // ponderhit restarts the clock
+        signals.ponder.store(false, Ordering::Relaxed);
+        assert!(!timer.should_stop(0));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(timer.should_stop(0));
+    }
+}
@@ -0,0 +1,1021 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::chess::{Kind, State};
+use crate::clock::TimeControl;
+use crate::games::{Player, Registry};
+use crate::skill::Skill;
+use crate::storage::{now, Log, StoredTimeControl};
+use crate::timeman::Limits;
+
+// tournaments are kept in `tournaments.jsonl` next to the games, one record per line: a
+// tournament was created, a player entered, it started, a round was paired, a game was
+// started again or a result came in. the games themselves are stored like any other
+const TOURNAMENTS_FILE: &str = "tournaments.jsonl";
+
+// pairings tried for a Swiss round without rematches before rematches are allowed
+const PAIRING_BUDGET: usize = 100_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    Create {
+        id: u64,
+        name: String,
+        owner: String,
+        // "round-robin" or "swiss", with the number of rounds for the latter
+        format: String,
+        rounds: Option<usize>,
+        clock: Option<StoredTimeControl>,
+        depth: Option<u32>,
+        nodes: Option<u64>,
+        movetime: Option<u64>,
+        rated: bool,
+        at: u64,
+    },
+    Enter {
+        id: u64,
+        name: String,
+        level: Option<u32>,
+        rating: f64,
+        at: u64,
+    },
+    // the entrants were seeded by rating
+    Start {
+        id: u64,
+        at: u64,
+    },
+    Round {
+        id: u64,
+        pairings: Vec<Pairing>,
+        at: u64,
+    },
+    // the game of a board was started again
+    Game {
+        id: u64,
+        round: usize,
+        board: usize,
+        game: u64,
+        tokens: [Option<String>; 2],
+        at: u64,
+    },
+    Result {
+        id: u64,
+        round: usize,
+        board: usize,
+        result: f64,
+        at: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // everyone plays everyone once
+    RoundRobin,
+    // this many rounds paired by the Dutch system
+    Swiss(usize),
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::RoundRobin => "round-robin",
+            Format::Swiss(_) => "swiss",
+        }
+    }
+}
+
+// a player of a tournament: a registered user or the server's engine at a strength level
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entrant {
+    pub name: String,
+    // none for the user `name`
+    pub level: Option<u32>,
+    // what the players are seeded by: the user's rating when entering, the level's Elo
+    pub rating: f64,
+}
+
+impl Entrant {
+    pub fn player(&self) -> Player {
+        self.level
+            .map_or(Player::Human, |level| Player::Engine(Skill::new(level)))
+    }
+}
+
+// a board of a round, a bye when there is no black
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pairing {
+    pub white: usize,
+    pub black: Option<usize>,
+    pub game: Option<u64>,
+    // the tokens of the seats of the users playing white and black
+    pub tokens: [Option<String>; 2],
+    // white's score: 1 for a win, 0.5 for a draw and 0 for a loss. a bye is worth a point
+    pub result: Option<f64>,
+}
+
+// an entrant's game of a round
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Encounter {
+    // none for a bye
+    pub opponent: Option<usize>,
+    pub color: Option<Kind>,
+    pub score: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Standing {
+    pub entrant: usize,
+    pub points: f64,
+    // the points of all opponents added up
+    pub buchholz: f64,
+    // the points of the opponents beaten and half those of the opponents drawn
+    pub sonneborn_berger: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tournament {
+    pub id: u64,
+    pub name: String,
+    // the user who created it and runs it
+    pub owner: String,
+    pub format: Format,
+    pub time_control: Option<TimeControl>,
+    // what engines search with in untimed games
+    pub limits: Limits,
+    // rated tournaments are between users only
+    pub rated: bool,
+    // by seed once started, the seed being the index
+    pub entrants: Vec<Entrant>,
+    pub rounds: Vec<Vec<Pairing>>,
+}
+
+impl Tournament {
+    pub fn started(&self) -> bool {
+        !self.rounds.is_empty()
+    }
+
+    // the rounds to play, for a round robin as many as its entrants need
+    pub fn total_rounds(&self) -> usize {
+        match self.format {
+            Format::RoundRobin => {
+                let n = self.entrants.len();
+                (n + n % 2).saturating_sub(1)
+            }
+            Format::Swiss(rounds) => rounds,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.started() && self.rounds.len() >= self.total_rounds() && self.round_complete()
+    }
+
+    fn round_complete(&self) -> bool {
+        self.rounds
+            .last()
+            .is_some_and(|round| round.iter().all(|pairing| pairing.result.is_some()))
+    }
+
+    pub fn entrant(&self, name: &str) -> Option<usize> {
+        self.entrants
+            .iter()
+            .position(|entrant| entrant.name == name)
+    }
+
+    // the game of `entrant` in every round so far, none where they were not paired
+    pub fn encounters(&self, entrant: usize) -> Vec<Option<Encounter>> {
+        self.rounds
+            .iter()
+            .map(|round| {
+                round.iter().find_map(|pairing| {
+                    if pairing.white == entrant {
+                        Some(Encounter {
+                            opponent: pairing.black,
+                            color: pairing.black.map(|_| Kind::White),
+                            score: pairing.result,
+                        })
+                    } else if pairing.black == Some(entrant) {
+                        Some(Encounter {
+                            opponent: Some(pairing.white),
+                            color: Some(Kind::Black),
+                            score: pairing.result.map(|result| 1.0 - result),
+                        })
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // the points of every entrant
+    pub fn points(&self) -> Vec<f64> {
+        (0..self.entrants.len())
+            .map(|entrant| {
+                self.encounters(entrant)
+                    .iter()
+                    .flatten()
+                    .filter_map(|encounter| encounter.score)
+                    .sum()
+            })
+            .collect()
+    }
+
+    // the entrants by points, then by Buchholz and Sonneborn-Berger in a Swiss. in a round
+    // robin everyone meets the same opponents, Sonneborn-Berger goes first there. byes add
+    // nothing to either
+    pub fn standings(&self) -> Vec<Standing> {
+        let points = self.points();
+        let mut standings: Vec<Standing> = (0..self.entrants.len())
+            .map(|entrant| {
+                let mut standing = Standing {
+                    entrant,
+                    points: points[entrant],
+                    buchholz: 0.0,
+                    sonneborn_berger: 0.0,
+                };
+                for encounter in self.encounters(entrant).into_iter().flatten() {
+                    if let Some(opponent) = encounter.opponent {
+                        standing.buchholz += points[opponent];
+                        standing.sonneborn_berger +=
+                            encounter.score.unwrap_or(0.0) * points[opponent];
+                    }
+                }
+                standing
+            })
+            .collect();
+        let keys = |standing: &Standing| match self.format {
+            Format::RoundRobin => [
+                standing.points,
+                standing.sonneborn_berger,
+                standing.buchholz,
+            ],
+            Format::Swiss(_) => [
+                standing.points,
+                standing.buchholz,
+                standing.sonneborn_berger,
+            ],
+        };
+        standings.sort_by(|a, b| {
+            keys(b)
+                .partial_cmp(&keys(a))
+                .unwrap_or(Ordering::Equal)
+                .then(a.entrant.cmp(&b.entrant))
+        });
+        standings
+    }
+
+    // white and black of every board of the next round, none for black in a bye
+    fn pair(&self) -> Vec<(usize, Option<usize>)> {
+        match self.format {
+            Format::RoundRobin => round_robin(self.entrants.len()).swap_remove(self.rounds.len()),
+            Format::Swiss(_) => swiss(self),
+        }
+    }
+}
+
+// the rounds of a round robin between `n` players by the circle method: the last player
+// stays put while the others rotate, and with an odd number whoever meets the missing
+// one has a bye. nobody gets the same color three times in a row
+fn round_robin(n: usize) -> Vec<Vec<(usize, Option<usize>)>> {
+    let m = n + n % 2;
+    (0..m.saturating_sub(1))
+        .map(|round| {
+            (0..m / 2)
+                .map(|board| {
+                    let a = (round + board) % (m - 1);
+                    let b = if board == 0 {
+                        m - 1
+                    } else {
+                        (round + m - 1 - board) % (m - 1)
+                    };
+                    // the fixed player alternates from round to round
+                    let a_white = if board == 0 {
+                        round.is_multiple_of(2)
+                    } else {
+                        board % 2 == 1
+                    };
+                    let (white, black) = if a_white { (a, b) } else { (b, a) };
+                    match (white < n, black < n) {
+                        (true, true) => (white, Some(black)),
+                        (true, false) => (white, None),
+                        _ => (black, None),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// a simplified Dutch system. players are ordered by points, then seed, and each in turn
+// is paired within their score group, the top half against the bottom half, trying the
+// bottom half in order until that avoids rematches. one left over floats down to the next
+// group. with an odd number the lowest player without a bye sits out. rematches are only
+// allowed when there is no other way
+fn swiss(tournament: &Tournament) -> Vec<(usize, Option<usize>)> {
+    let points = tournament.points();
+    let encounters: Vec<Vec<Encounter>> = (0..tournament.entrants.len())
+        .map(|entrant| {
+            tournament
+                .encounters(entrant)
+                .into_iter()
+                .flatten()
+                .collect()
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..tournament.entrants.len()).collect();
+    order.sort_by(|&a, &b| {
+        points[b]
+            .partial_cmp(&points[a])
+            .unwrap_or(Ordering::Equal)
+            .then(a.cmp(&b))
+    });
+
+    // who sits out, those without a bye first, from the bottom up
+    let mut byes: Vec<Option<usize>> = order.iter().rev().map(|&p| Some(p)).collect();
+    byes.sort_by_key(|bye| bye.is_some_and(|p| encounters[p].iter().any(|e| e.opponent.is_none())));
+    if order.len().is_multiple_of(2) {
+        byes = vec![None];
+    }
+
+    let met = |a: usize, b: usize| encounters[a].iter().any(|e| e.opponent == Some(b));
+    let attempt = |allowed: &dyn Fn(usize, usize) -> bool| {
+        let mut budget = PAIRING_BUDGET;
+        byes.iter().find_map(|&bye| {
+            let players: Vec<usize> = order.iter().copied().filter(|&p| Some(p) != bye).collect();
+            let pairs = pair_players(&players, &points, allowed, &mut budget)?;
+            let mut boards: Vec<(usize, Option<usize>)> = pairs
+                .into_iter()
+                .enumerate()
+                .map(|(board, (a, b))| {
+                    let (white, black) = colors(&encounters, a, b, board);
+                    (white, Some(black))
+                })
+                .collect();
+            boards.extend(bye.map(|p| (p, None)));
+            Some(boards)
+        })
+    };
+    attempt(&|a, b| !met(a, b))
+        .or_else(|| attempt(&|_, _| true))
+        .unwrap_or_default()
+}
+
+// pairs `players`, ordered by points and seed, as described at `swiss`. none when
+// `allowed` rules every way out or the budget runs out
+fn pair_players(
+    players: &[usize],
+    points: &[f64],
+    allowed: &dyn Fn(usize, usize) -> bool,
+    budget: &mut usize,
+) -> Option<Vec<(usize, usize)>> {
+    let (&first, rest) = match players.split_first() {
+        Some(split) => split,
+        None => return Some(Vec::new()),
+    };
+    if *budget == 0 {
+        return None;
+    }
+    *budget -= 1;
+    let group = 1 + rest
+        .iter()
+        .take_while(|&&p| points[p] == points[first])
+        .count();
+    let half = (group / 2).max(1);
+    // the bottom half of the group, then the rest of the top half, then those further down
+    let candidates = (half..group).chain(1..half).chain(group..players.len());
+    for opponent in candidates.map(|i| players[i]) {
+        if !allowed(first, opponent) {
+            continue;
+        }
+        let others: Vec<usize> = rest.iter().copied().filter(|&p| p != opponent).collect();
+        if let Some(mut pairs) = pair_players(&others, points, allowed, budget) {
+            pairs.insert(0, (first, opponent));
+            return Some(pairs);
+        }
+    }
+    None
+}
+
+// white and black when `a`, ranked above `b`, meets them on `board`: the one who had black
+// more often gets white, then the one who had black last. without any games the top
+// boards alternate
+fn colors(encounters: &[Vec<Encounter>], a: usize, b: usize, board: usize) -> (usize, usize) {
+    let balance = |p: usize| -> i32 {
+        encounters[p]
+            .iter()
+            .map(|e| match e.color {
+                Some(Kind::White) => 1,
+                Some(Kind::Black) => -1,
+                None => 0,
+            })
+            .sum()
+    };
+    let last = |p: usize| encounters[p].iter().rev().find_map(|e| e.color);
+    let a_white = match balance(a).cmp(&balance(b)) {
+        Ordering::Less => true,
+        Ordering::Greater => false,
+        Ordering::Equal => match (last(a), last(b)) {
+            (Some(color), _) => color == Kind::Black,
+            (None, Some(color)) => color == Kind::White,
+            (None, None) => board.is_multiple_of(2),
+        },
+    };
+    if a_white {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// the server's tournaments. their games are played on the registry like any other, and
+// `collect` takes in the results and pairs the next rounds
+pub struct Tournaments {
+    registry: Arc<Registry>,
+    tournaments: Mutex<BTreeMap<u64, Tournament>>,
+    log: Option<Log>,
+}
+
+impl Tournaments {
+    pub fn new(registry: Arc<Registry>) -> Tournaments {
+        Tournaments {
+            registry,
+            tournaments: Mutex::new(BTreeMap::new()),
+            log: None,
+        }
+    }
+
+    // the tournaments recorded in `dir`, new ones are recorded there as well
+    pub fn open(registry: Arc<Registry>, dir: &Path) -> Result<Tournaments, anyhow::Error> {
+        fs::create_dir_all(dir)?;
+        let log = Log::open(dir.join(TOURNAMENTS_FILE))?;
+        let mut tournaments = BTreeMap::new();
+        for record in log.read::<Record>()? {
+            apply(&mut tournaments, record);
+        }
+        Ok(Tournaments {
+            registry,
+            tournaments: Mutex::new(tournaments),
+            log: Some(log),
+        })
+    }
+
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    pub fn get(&self, id: u64) -> Option<Tournament> {
+        self.tournaments.lock().unwrap().get(&id).cloned()
+    }
+
+    // all tournaments, oldest first
+    pub fn list(&self) -> Vec<Tournament> {
+        self.tournaments.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn create(
+        &self,
+        owner: &str,
+        name: &str,
+        format: Format,
+        time_control: Option<TimeControl>,
+        limits: Limits,
+        rated: bool,
+    ) -> Result<Tournament, anyhow::Error> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(anyhow!("Names are 1 to 64 characters"));
+        }
+        if format == Format::Swiss(0) {
+            return Err(anyhow!("A Swiss needs a round"));
+        }
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let id = tournaments.keys().next_back().map_or(1, |id| id + 1);
+        let (format, rounds) = match format {
+            Format::RoundRobin => (format.name(), None),
+            Format::Swiss(rounds) => (format.name(), Some(rounds)),
+        };
+        self.record(
+            &mut tournaments,
+            Record::Create {
+                id,
+                name: name.to_string(),
+                owner: owner.to_string(),
+                format: format.to_string(),
+                rounds,
+                clock: time_control.as_ref().map(StoredTimeControl::from),
+                depth: limits.depth,
+                nodes: limits.nodes,
+                movetime: limits.movetime.map(|t| t.as_millis() as u64),
+                rated,
+                at: now(),
+            },
+        );
+        Ok(tournaments[&id].clone())
+    }
+
+    // enters `user` into tournament `id` before it starts
+    pub fn enter(&self, id: u64, user: &str) -> Result<(), anyhow::Error> {
+        let rating = self
+            .registry
+            .users()
+            .user(user)
+            .ok_or_else(|| anyhow!("There is no user {}", user))?
+            .rating
+            .rating;
+        self.add(id, user, None, rating)
+    }
+
+    // enters the server's engine at `skill` under `name`
+    pub fn enter_engine(&self, id: u64, name: &str, skill: Skill) -> Result<(), anyhow::Error> {
+        if self.get(id).is_some_and(|tournament| tournament.rated) {
+            return Err(anyhow!("Rated tournaments are between users"));
+        }
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 32 {
+            return Err(anyhow!("Names are 1 to 32 characters"));
+        }
+        self.add(id, name, Some(skill.level()), skill.elo() as f64)
+    }
+
+    fn add(
+        &self,
+        id: u64,
+        name: &str,
+        level: Option<u32>,
+        rating: f64,
+    ) -> Result<(), anyhow::Error> {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let tournament = tournaments
+            .get(&id)
+            .ok_or_else(|| anyhow!("No tournament {}", id))?;
+        if tournament.started() {
+            return Err(anyhow!("The tournament has started"));
+        }
+        if tournament.entrant(name).is_some() {
+            return Err(anyhow!("{} has entered already", name));
+        }
+        self.record(
+            &mut tournaments,
+            Record::Enter {
+                id,
+                name: name.to_string(),
+                level,
+                rating,
+                at: now(),
+            },
+        );
+        Ok(())
+    }
+
+    // seeds the entrants and starts the first round
+    pub fn start(&self, id: u64) -> Result<Tournament, anyhow::Error> {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let tournament = tournaments
+            .get(&id)
+            .ok_or_else(|| anyhow!("No tournament {}", id))?;
+        if tournament.started() {
+            return Err(anyhow!("The tournament has started"));
+        }
+        if tournament.entrants.len() < 2 {
+            return Err(anyhow!("A tournament needs two players"));
+        }
+        self.record(&mut tournaments, Record::Start { id, at: now() });
+        self.pair_round(&mut tournaments, id)?;
+        info!("tournament {}: started", id);
+        Ok(tournaments[&id].clone())
+    }
+
+    // takes in the results of finished games and pairs the next round once one is complete.
+    // a game that was aborted, or could not be restored after a restart, is started again.
+    // the server calls this regularly
+    pub fn collect(&self) {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let playing: Vec<u64> = tournaments
+            .values()
+            .filter(|tournament| tournament.started() && !tournament.finished())
+            .map(|tournament| tournament.id)
+            .collect();
+        for id in playing {
+            let round = tournaments[&id].rounds.len() - 1;
+            let pairings = tournaments[&id].rounds[round].clone();
+            for (board, pairing) in pairings.into_iter().enumerate() {
+                let (game, black) = match (pairing.game, pairing.black, pairing.result) {
+                    (Some(game), Some(black), None) => (game, black),
+                    _ => continue,
+                };
+                let ending = self
+                    .registry
+                    .get(game)
+                    .map(|game| game.lock().unwrap().ending().cloned());
+                let record = match ending {
+                    Some(None) => continue,
+                    Some(Some(ending)) if !ending.aborted => Record::Result {
+                        id,
+                        round,
+                        board,
+                        result: match ending.winner {
+                            Some(Kind::White) => 1.0,
+                            Some(Kind::Black) => 0.0,
+                            None => 0.5,
+                        },
+                        at: now(),
+                    },
+                    _ => match self.play(&tournaments[&id], pairing.white, black) {
+                        Ok((game, tokens)) => Record::Game {
+                            id,
+                            round,
+                            board,
+                            game,
+                            tokens,
+                            at: now(),
+                        },
+                        Err(e) => {
+                            warn!("tournament {}: {}", id, e);
+                            continue;
+                        }
+                    },
+                };
+                self.record(&mut tournaments, record);
+            }
+            let tournament = &tournaments[&id];
+            if tournament.round_complete() && tournament.rounds.len() < tournament.total_rounds() {
+                if let Err(e) = self.pair_round(&mut tournaments, id) {
+                    warn!("tournament {}: {}", id, e);
+                }
+            }
+        }
+    }
+
+    // pairs the next round of tournament `id` and starts its games
+    fn pair_round(
+        &self,
+        tournaments: &mut BTreeMap<u64, Tournament>,
+        id: u64,
+    ) -> Result<(), anyhow::Error> {
+        let tournament = &tournaments[&id];
+        let mut pairings = Vec::new();
+        for (white, black) in tournament.pair() {
+            pairings.push(match black {
+                Some(black) => {
+                    let (game, tokens) = self.play(tournament, white, black)?;
+                    Pairing {
+                        white,
+                        black: Some(black),
+                        game: Some(game),
+                        tokens,
+                        result: None,
+                    }
+                }
+                None => Pairing {
+                    white,
+                    black: None,
+                    game: None,
+                    tokens: [None, None],
+                    result: Some(1.0),
+                },
+            });
+        }
+        info!(
+            "tournament {}: round {} paired",
+            id,
+            tournament.rounds.len() + 1
+        );
+        self.record(
+            tournaments,
+            Record::Round {
+                id,
+                pairings,
+                at: now(),
+            },
+        );
+        Ok(())
+    }
+
+    // starts a game between two entrants and seats the users among them
+    fn play(
+        &self,
+        tournament: &Tournament,
+        white: usize,
+        black: usize,
+    ) -> Result<(u64, [Option<String>; 2]), anyhow::Error> {
+        let entrants = [&tournament.entrants[white], &tournament.entrants[black]];
+        let game = self.registry.create(
+            State::default(),
+            entrants[0].player(),
+            entrants[1].player(),
+            tournament.limits.clone(),
+            tournament.time_control,
+            tournament.rated,
+        )?;
+        let mut game = game.lock().unwrap();
+        let mut tokens = [None, None];
+        for (i, side) in [Kind::White, Kind::Black].into_iter().enumerate() {
+            if entrants[i].level.is_none() {
+                let (_, token) = game.join(Some(side), Some(&entrants[i].name))?;
+                tokens[i] = Some(token);
+            }
+        }
+        info!(
+            "tournament {}: {} - {} in game {}",
+            tournament.id,
+            entrants[0].name,
+            entrants[1].name,
+            game.id()
+        );
+        Ok((game.id(), tokens))
+    }
+
+    fn record(&self, tournaments: &mut BTreeMap<u64, Tournament>, record: Record) {
+        if let Some(log) = &self.log {
+            log.append(&record);
+        }
+        apply(tournaments, record);
+    }
+}
+
+fn apply(tournaments: &mut BTreeMap<u64, Tournament>, record: Record) {
+    match record {
+        Record::Create {
+            id,
+            name,
+            owner,
+            format,
+            rounds,
+            clock,
+            depth,
+            nodes,
+            movetime,
+            rated,
+            ..
+        } => {
+            let format = match (format.as_str(), rounds) {
+                ("swiss", Some(rounds)) => Format::Swiss(rounds),
+                _ => Format::RoundRobin,
+            };
+            let tournament = Tournament {
+                id,
+                name,
+                owner,
+                format,
+                time_control: clock.map(|c| c.time_control()),
+                limits: Limits {
+                    depth,
+                    nodes,
+                    movetime: movetime.map(Duration::from_millis),
+                    ..Limits::default()
+                },
+                rated,
+                entrants: Vec::new(),
+                rounds: Vec::new(),
+            };
+            tournaments.insert(id, tournament);
+        }
+        Record::Enter {
+            id,
+            name,
+            level,
+            rating,
+            ..
+        } => {
+            if let Some(tournament) = tournaments.get_mut(&id) {
+                tournament.entrants.push(Entrant {
+                    name,
+                    level,
+                    rating,
+                });
+            }
+        }
+        Record::Start { id, .. } => {
+            if let Some(tournament) = tournaments.get_mut(&id) {
+                tournament.entrants.sort_by(|a, b| {
+                    b.rating
+                        .partial_cmp(&a.rating)
+                        .unwrap_or(Ordering::Equal)
+                        .then_with(|| a.name.cmp(&b.name))
+                });
+            }
+        }
+        Record::Round { id, pairings, .. } => {
+            if let Some(tournament) = tournaments.get_mut(&id) {
+                tournament.rounds.push(pairings);
+            }
+        }
+        Record::Game {
+            id,
+            round,
+            board,
+            game,
+            tokens,
+            ..
+        } => {
+            if let Some(pairing) = pairing(tournaments, id, round, board) {
+                pairing.game = Some(game);
+                pairing.tokens = tokens;
+            }
+        }
+        Record::Result {
+            id,
+            round,
+            board,
+            result,
+            ..
+        } => {
+            if let Some(pairing) = pairing(tournaments, id, round, board) {
+                pairing.result = Some(result);
+            }
+        }
+    }
+}
+
+fn pairing(
+    tournaments: &mut BTreeMap<u64, Tournament>,
+    id: u64,
+    round: usize,
+    board: usize,
+) -> Option<&mut Pairing> {
+    tournaments
+        .get_mut(&id)?
+        .rounds
+        .get_mut(round)?
+        .get_mut(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::{Action, Engines};
+    use std::collections::HashSet;
+
+    // a Swiss of `n` players where the better seed always wins
+    fn play_swiss(n: usize, rounds: usize) -> Tournament {
+        let mut tournament = Tournament {
+            id: 1,
+            name: "Swiss".to_string(),
+            owner: "alice".to_string(),
+            format: Format::Swiss(rounds),
+            time_control: None,
+            limits: Limits::default(),
+            rated: false,
+            entrants: (0..n)
+                .map(|i| Entrant {
+                    name: format!("player{}", i),
+                    level: None,
+                    rating: 2000.0 - i as f64,
+                })
+                .collect(),
+            rounds: Vec::new(),
+        };
+        for _ in 0..rounds {
+            let round = tournament
+                .pair()
+                .into_iter()
+                .map(|(white, black)| Pairing {
+                    white,
+                    black,
+                    game: None,
+                    tokens: [None, None],
+                    result: Some(match black {
+                        Some(black) if black < white => 0.0,
+                        _ => 1.0,
+                    }),
+                })
+                .collect();
+            tournament.rounds.push(round);
+        }
+        tournament
+    }
+
+    #[test]
+    fn test_round_robin() {
+        for n in 2..12 {
+            let rounds = round_robin(n);
+            assert_eq!(rounds.len(), n + n % 2 - 1);
+            let mut met = HashSet::new();
+            let mut balance = vec![0i32; n];
+            for round in &rounds {
+                let mut seen = HashSet::new();
+                for &(white, black) in round {
+                    assert!(seen.insert(white));
+                    if let Some(black) = black {
+                        assert!(seen.insert(black));
+                        assert!(met.insert((white.min(black), white.max(black))));
+                        balance[white] += 1;
+                        balance[black] -= 1;
+                    }
+                }
+                assert_eq!(seen.len(), n);
+            }
+            assert_eq!(met.len(), n * (n - 1) / 2);
+            assert!(balance.iter().all(|b| b.abs() <= 1), "{:?}", balance);
+        }
+    }
+
+    #[test]
+    fn test_swiss() {
+        let tournament = play_swiss(7, 4);
+        let mut met = HashSet::new();
+        let mut byes = HashSet::new();
+        for round in &tournament.rounds {
+            for pairing in round {
+                match pairing.black {
+                    Some(black) => {
+                        assert!(met.insert((pairing.white.min(black), pairing.white.max(black))))
+                    }
+                    None => assert!(byes.insert(pairing.white)),
+                }
+            }
+        }
+        // the lowest seed sits out first, then the top half of the winners meets the bottom
+        // half
+        assert_eq!(tournament.rounds[0][3].white, 6);
+        assert_eq!(tournament.rounds[0][0].black, Some(3));
+        let top = &tournament.rounds[1][0];
+        let mut players = [top.white, top.black.unwrap()];
+        players.sort();
+        assert_eq!(players, [0, 2]);
+
+        let standings = tournament.standings();
+        assert_eq!(standings[0].entrant, 0);
+        assert_eq!(standings[0].points, 4.0);
+        let buchholz: f64 = tournament
+            .encounters(0)
+            .iter()
+            .flatten()
+            .filter_map(|e| e.opponent)
+            .map(|o| tournament.points()[o])
+            .sum();
+        assert_eq!(standings[0].buchholz, buchholz);
+        assert_eq!(standings[0].sonneborn_berger, buchholz);
+    }
+
+    #[test]
+    fn test_tournament() {
+        let registry = Arc::new(Registry::new(Engines::default()));
+        for name in ["alice", "bob", "carol"] {
+            registry.users().register(name, "password").unwrap();
+        }
+        let tournaments = Tournaments::new(registry.clone());
+        let tournament = tournaments
+            .create(
+                "alice",
+                "Office",
+                Format::RoundRobin,
+                None,
+                Limits::default(),
+                true,
+            )
+            .unwrap();
+        assert!(tournaments.start(tournament.id).is_err());
+        for name in ["alice", "bob", "carol"] {
+            tournaments.enter(tournament.id, name).unwrap();
+        }
+        assert!(tournaments.enter(tournament.id, "bob").is_err());
+        assert!(tournaments
+            .enter_engine(tournament.id, "engine", Skill::default())
+            .is_err());
+        let tournament = tournaments.start(tournament.id).unwrap();
+        assert!(tournaments.enter(tournament.id, "dave").is_err());
+        assert_eq!(tournament.total_rounds(), 3);
+
+        // white resigns every game, an aborted one is played again
+        for round in 0..3 {
+            let tournament = tournaments.get(tournament.id).unwrap();
+            assert_eq!(tournament.rounds.len(), round + 1);
+            let board = tournament.rounds[round]
+                .iter()
+                .position(|pairing| pairing.game.is_some())
+                .unwrap();
+            let pairing = &tournament.rounds[round][board];
+            let game = registry.get(pairing.game.unwrap()).unwrap();
+            assert!(game.lock().unwrap().rated());
+            let action = if round == 0 {
+                Action::Abort
+            } else {
+                Action::Resign
+            };
+            game.lock().unwrap().act(Kind::White, action).unwrap();
+            tournaments.collect();
+            if round == 0 {
+                let restarted = tournaments.get(tournament.id).unwrap().rounds[0][board].clone();
+                assert_ne!(restarted.game, pairing.game);
+                let game = registry.get(restarted.game.unwrap()).unwrap();
+                game.lock()
+                    .unwrap()
+                    .act(Kind::White, Action::Resign)
+                    .unwrap();
+                tournaments.collect();
+            }
+        }
+        let tournament = tournaments.get(tournament.id).unwrap();
+        assert!(tournament.finished());
+        assert_eq!(tournament.points().iter().sum::<f64>(), 3.0 + 3.0);
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs;
+use std::time::Instant;
+
+use anyhow::anyhow;
+
+use crate::chess::{Kind, State};
+use crate::eval::{game_phase, EvalParams, MAX_PHASE, PARAM_COUNT};
+
+// texel tuning: fits the evaluation parameters to game results by minimizing the mean
+// squared error between the results and a sigmoid of the static evaluation.
+//
+//   chess tune <positions> <output> [--epochs N] [--rate R] [--params <start file>]
+//
+// every line of the positions file holds a FEN (only the first four fields are read) and
+// the result of the game it was taken from, as "1-0", "0-1", "1/2-1/2" or a score from
+// white's point of view in brackets like [1.0], [0.5], [0.0]. the positions should be
+// quiet since the static evaluation does not resolve captures. the tuned parameters are
+// written to <output> and can be loaded with EVAL_PARAMS or the UCI EvalParams option
+
+const DEFAULT_EPOCHS: usize = 500;
+const DEFAULT_RATE: f64 = 1.0;
+// progress is printed and the output saved this often
+const REPORT_EVERY: usize = 25;
+
+// the evaluation is linear in its parameters once the game phase is fixed, so every
+// position is reduced to a sparse list of (parameter index, weight) pairs
+struct Sample {
+    terms: Vec<(usize, f64)>,
+    result: f64,
+}
+
+pub fn run(args: &[String]) -> Result<(), anyhow::Error> {
+    let usage = || {
+        anyhow!("Usage: chess tune <positions> <output> [--epochs N] [--rate R] [--params <file>]")
+    };
+    let positions = args.first().ok_or_else(usage)?;
+    let output = args.get(1).ok_or_else(usage)?;
+    let mut epochs = DEFAULT_EPOCHS;
+    let mut rate = DEFAULT_RATE;
+    let mut params = EvalParams::default();
+    for option in args[2..].chunks(2) {
+        match option {
+            [name, value] if name == "--epochs" => epochs = value.parse()?,
+            [name, value] if name == "--rate" => rate = value.parse()?,
+            [name, value] if name == "--params" => params = EvalParams::load(value)?,
+            _ => return Err(usage()),
+        }
+    }
+
+    let mut samples = Vec::new();
+    for (i, line) in fs::read_to_string(positions)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (state, result) =
+            parse_sample(line).map_err(|e| anyhow!("{}:{}: {}", positions, i + 1, e))?;
+        samples.push(Sample {
+            terms: terms(&state),
+            result,
+        });
+    }
+    if samples.is_empty() {
+        return Err(anyhow!("No positions in {}", positions));
+    }
+    println!("{} positions, {} parameters", samples.len(), PARAM_COUNT);
+
+    let mut values: Vec<f64> = params.to_vec().into_iter().map(f64::from).collect();
+    let k = fit_scaling(&samples, &values);
+    println!(
+        "scaling constant {:.6}, loss {:.6}",
+        k,
+        loss(&samples, &values, k)
+    );
+
+    let started = Instant::now();
+    let mut adam = Adam::new(rate);
+    for epoch in 1..=epochs {
+        let gradient = gradient(&samples, &values, k);
+        adam.step(&mut values, &gradient);
+        if epoch % REPORT_EVERY == 0 || epoch == epochs {
+            println!(
+                "epoch {} loss {:.6} ({}s)",
+                epoch,
+                loss(&samples, &values, k),
+                started.elapsed().as_secs()
+            );
+            rounded(&values).save(output)?;
+        }
+    }
+    println!("parameters written to {}", output);
+    Ok(())
+}
+
+// "<fen> <result>", see the comment at the top
+fn parse_sample(line: &str) -> Result<(State, f64), anyhow::Error> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 5 {
+        return Err(anyhow!("Expected a FEN and a result"));
+    }
+    let state = State::from_fen(&tokens[..4].join(" "))?;
+
+    let rest = tokens[4..].join(" ");
+    let result = if rest.contains("1/2-1/2") {
+        0.5
+    } else if rest.contains("1-0") {
+        1.0
+    } else if rest.contains("0-1") {
+        0.0
+    } else {
+        let last = tokens[tokens.len() - 1].trim_matches(|c| "[]\";".contains(c));
+        last.parse::<f64>()
+            .ok()
+            .filter(|r| (0.0..=1.0).contains(r))
+            .ok_or_else(|| anyhow!("No game result"))?
+    };
+    Ok((state, result))
+}
+
+// mirrors `eval::evaluate`, from white's point of view, indexing `EvalParams::to_vec`
+fn terms(state: &State) -> Vec<(usize, f64)> {
+    let phase = game_phase(state) as f64 / MAX_PHASE as f64;
+    let mut terms = Vec::new();
+    for (r, row) in state.board.iter().enumerate() {
+        for (c, square) in row.iter().enumerate() {
+            let pair = match square {
+                Some(p) => p,
+                None => continue,
+            };
+            let piece = pair.piece.index();
+            let (sq, sign) = match pair.kind {
+                Kind::White => (r * 8 + c, 1.0),
+                Kind::Black => ((7 - r) * 8 + c, -1.0),
+            };
+            let (mg, eg) = (sign * phase, sign * (1.0 - phase));
+            terms.push((piece, mg));
+            terms.push((6 + piece, eg));
+            terms.push((12 + piece * 64 + sq, mg));
+            terms.push((12 + 6 * 64 + piece * 64 + sq, eg));
+        }
+    }
+    terms
+}
+
+fn linear_eval(sample: &Sample, values: &[f64]) -> f64 {
+    sample.terms.iter().map(|(i, w)| values[*i] * w).sum()
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + (-k * eval).exp())
+}
+
+fn loss(samples: &[Sample], values: &[f64], k: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|s| (s.result - sigmoid(k, linear_eval(s, values))).powi(2))
+        .sum();
+    total / samples.len() as f64
+}
+
+fn gradient(samples: &[Sample], values: &[f64], k: f64) -> Vec<f64> {
+    let mut gradient = vec![0.0; values.len()];
+    for sample in samples {
+        let p = sigmoid(k, linear_eval(sample, values));
+        let error = -2.0 * (sample.result - p) * p * (1.0 - p) * k;
+        for (i, w) in &sample.terms {
+            gradient[*i] += error * w;
+        }
+    }
+    let n = samples.len() as f64;
+    gradient.iter_mut().for_each(|g| *g /= n);
+    gradient
+}
+
+// the sigmoid's scaling constant that best maps the starting evaluation to results,
+// found by golden section search
+fn fit_scaling(samples: &[Sample], values: &[f64]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.0001, 0.05);
+    for _ in 0..60 {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if loss(samples, values, a) < loss(samples, values, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+fn rounded(values: &[f64]) -> EvalParams {
+    let values: Vec<i32> = values.iter().map(|v| v.round() as i32).collect();
+    EvalParams::from_slice(&values)
+}
+
+struct Adam {
+    rate: f64,
+    t: i32,
+    m: Vec<f64>,
+    v: Vec<f64>,
+}
+
+impl Adam {
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+
+    fn new(rate: f64) -> Adam {
+        Adam {
+            rate,
+            t: 0,
+            m: vec![0.0; PARAM_COUNT],
+            v: vec![0.0; PARAM_COUNT],
+        }
+    }
+
+    fn step(&mut self, values: &mut [f64], gradient: &[f64]) {
+        self.t += 1;
+        let m_scale = 1.0 - Adam::BETA1.powi(self.t);
+        let v_scale = 1.0 - Adam::BETA2.powi(self.t);
+        for i in 0..values.len() {
+            self.m[i] = Adam::BETA1 * self.m[i] + (1.0 - Adam::BETA1) * gradient[i];
+            self.v[i] = Adam::BETA2 * self.v[i] + (1.0 - Adam::BETA2) * gradient[i].powi(2);
+            let m = self.m[i] / m_scale;
+            let v = self.v[i] / v_scale;
+            values[i] -= self.rate * m / (v.sqrt() + 1e-8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate;
+
+    // evaluation from white's point of view, as the tuner sees it
+    fn white_eval(state: &State, params: &EvalParams) -> i32 {
+        match state.side {
+            Kind::White => evaluate(state, params),
+            Kind::Black => -evaluate(state, params),
+        }
+    }
+
+    #[test]
+    fn test_terms_match_evaluate() {
+        let params = EvalParams::default();
+        let values: Vec<f64> = params.to_vec().into_iter().map(f64::from).collect();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "r1bq1rk1/pp3ppp/2n2n2/3p4/1b1P4/2NB1N2/PP3PPP/R1BQ1RK1 w - - 0 1",
+            "8/5k2/8/3P4/8/8/2K5/7r w - - 0 1",
+        ] {
+            let state = State::from_fen(fen).unwrap();
+            let sample = Sample {
+                terms: terms(&state),
+                result: 0.5,
+            };
+            let linear = linear_eval(&sample, &values);
+            assert!(
+                (linear - white_eval(&state, &params) as f64).abs() < 1.0,
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let (state, result) = parse_sample("8/5k2/8/8/8/8/2K5/7r w - - c9 \"0-1\";").unwrap();
+        assert_eq!(state.side, Kind::White);
+        assert_eq!(result, 0.0);
+        let (_, result) = parse_sample("8/5k2/8/8/8/8/2K5/7r b - - 0 40 [0.5]").unwrap();
+        assert_eq!(result, 0.5);
+        assert!(parse_sample("8/5k2/8/8/8/8/2K5/7r b - -").is_err());
+    }
+
+    #[test]
+    fn test_tuning_lowers_loss() {
+        // white a knight up wins, black a knight up loses
+        let samples: Vec<Sample> = [
+            ("4k3/8/8/8/8/8/8/1N2K3 w - -", 1.0),
+            ("1n2k3/8/8/8/8/8/8/4K3 w - -", 0.0),
+            ("4k3/8/8/8/8/8/8/4K3 w - -", 0.5),
+        ]
+        .iter()
+        .map(|(fen, result)| Sample {
+            terms: terms(&State::from_fen(fen).unwrap()),
+            result: *result,
+        })
+        .collect();
+        let mut values: Vec<f64> = EvalParams::default()
+            .to_vec()
+            .into_iter()
+            .map(f64::from)
+            .collect();
+        let before = loss(&samples, &values, 0.01);
+        let mut adam = Adam::new(DEFAULT_RATE);
+        for _ in 0..50 {
+            let gradient = gradient(&samples, &values, 0.01);
+            adam.step(&mut values, &gradient);
+        }
+        assert!(loss(&samples, &values, 0.01) < before);
+    }
+}
@@ -0,0 +1,444 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use crate::chess::{Kind, State};
+use crate::eval::EvalParams;
+use crate::nnue::Network;
+use crate::search::{mate_in, SearchConfig, SearchResult, Searcher};
+use crate::skill::{Skill, MAX_ELO, MAX_LEVEL, MIN_ELO};
+use crate::tablebase::Tablebase;
+use crate::timeman::{Limits, Signals};
+
+const DEFAULT_HASH: usize = 16;
+// the largest hash in megabytes, search threads and lines the options take
+const MAX_HASH: usize = 1024;
+const MAX_THREADS: usize = 256;
+const MAX_MULTIPV: usize = 64;
+
+// speaks the Universal Chess Interface over stdin/stdout until `quit`
+pub fn run() {
+    let mut uci = Uci::new();
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !uci.command(line.trim()) {
+            break;
+        }
+        io::stdout().flush().ok();
+    }
+    uci.stop();
+}
+
+struct Uci {
+    state: State,
+    // the searcher moves into the worker thread while it thinks
+    searcher: Option<Searcher>,
+    worker: Option<JoinHandle<Searcher>>,
+    signals: Signals,
+    // loaded from EvalFile, used while UseNNUE is on
+    network: Option<Arc<Network>>,
+    use_network: bool,
+    // UCI_Elo applies while UCI_LimitStrength is on, Skill Level otherwise
+    limit_strength: bool,
+    elo: u32,
+    skill_level: u32,
+}
+
+impl Uci {
+    fn new() -> Uci {
+        let searcher = Searcher::new(State::default(), SearchConfig::default());
+        Uci {
+            state: State::default(),
+            signals: searcher.signals(),
+            searcher: Some(searcher),
+            worker: None,
+            network: None,
+            use_network: true,
+            limit_strength: false,
+            elo: MAX_ELO,
+            skill_level: MAX_LEVEL,
+        }
+    }
+
+    // returns false on `quit`
+    fn command(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name chess");
+                println!("id author LukasPukenis");
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH, MAX_HASH
+                );
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                );
+                println!("option name Ponder type check default false");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name UseNNUE type check default true");
+                println!("option name EvalParams type string default <empty>");
+                println!(
+                    "option name Skill Level type spin default {} min 0 max {}",
+                    MAX_LEVEL, MAX_LEVEL
+                );
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    MAX_ELO, MIN_ELO, MAX_ELO
+                );
+                for name in SEARCH_TOGGLES {
+                    println!("option name {} type check default true", name);
+                }
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => {
+                self.searcher().new_game();
+                self.state = State::default();
+            }
+            Some("position") => {
+                self.stop();
+                match parse_position(&tokens.collect::<Vec<_>>()) {
+                    Ok(state) => self.state = state,
+                    Err(e) => println!("info string {}", e),
+                }
+            }
+            Some("go") => {
+                let limits = parse_go(&tokens.collect::<Vec<_>>(), &self.state);
+                self.go(limits);
+            }
+            Some("stop") => self.stop(),
+            Some("ponderhit") => self.signals.ponder.store(false, Ordering::Relaxed),
+            Some("setoption") => {
+                if let Err(e) = self.set_option(&tokens.collect::<Vec<_>>()) {
+                    println!("info string {}", e);
+                }
+            }
+            Some("d") => println!("info string {}", self.state.to_fen()),
+            Some("quit") => return false,
+            _ => (),
+        }
+        true
+    }
+
+    // waits for a running search to finish and hands back the searcher
+    fn searcher(&mut self) -> &mut Searcher {
+        self.stop();
+        self.searcher.as_mut().expect("Searcher is idle")
+    }
+
+    fn stop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.signals.stop.store(true, Ordering::Relaxed);
+            self.signals.ponder.store(false, Ordering::Relaxed);
+            self.searcher = Some(worker.join().expect("Search thread panicked"));
+        }
+    }
+
+    fn go(&mut self, limits: Limits) {
+        self.stop();
+        let state = self.state.clone();
+        let mut searcher = self.searcher.take().expect("Searcher is idle");
+
+        self.signals.stop.store(false, Ordering::Relaxed);
+        self.signals.ponder.store(limits.ponder, Ordering::Relaxed);
+        searcher.set_state(state);
+        searcher.set_reporter(Box::new(print_info));
+
+        let skill = self.skill();
+        let signals = self.signals.clone();
+        self.worker = Some(thread::spawn(move || {
+            let result = skill.search(&mut searcher, &limits);
+
+            // bestmove may only be sent once the GUI stops an infinite or ponder search
+            while (limits.infinite && !signals.stop.load(Ordering::Relaxed))
+                || signals.ponder.load(Ordering::Relaxed)
+            {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            match (result.pv.first(), result.pv.get(1)) {
+                (Some(best), Some(ponder)) => println!("bestmove {} ponder {}", best, ponder),
+                (Some(best), None) => println!("bestmove {}", best),
+                _ => println!("bestmove 0000"),
+            }
+            io::stdout().flush().ok();
+            searcher
+        }));
+    }
+
+    fn set_option(&mut self, tokens: &[&str]) -> Result<(), anyhow::Error> {
+        let (name, value) = parse_option(tokens)?;
+        match name.as_str() {
+            "skill level" => self.skill_level = value.parse()?,
+            "uci_limitstrength" => self.limit_strength = value.parse()?,
+            "uci_elo" => self.elo = value.parse()?,
+            "evalfile" | "usennue" => {
+                if name == "evalfile" {
+                    self.network = match value.as_str() {
+                        "" | "<empty>" => None,
+                        path => Some(Arc::new(Network::load(path)?)),
+                    };
+                } else {
+                    self.use_network = value.parse()?;
+                }
+                // network evaluation when a network is loaded and switched on, classical otherwise
+                let network = self.network.clone().filter(|_| self.use_network);
+                self.searcher().set_network(network);
+            }
+            _ => return apply_option(self.searcher(), &name, &value),
+        }
+        Ok(())
+    }
+
+    fn skill(&self) -> Skill {
+        if self.limit_strength {
+            Skill::from_elo(self.elo)
+        } else {
+            Skill::new(self.skill_level)
+        }
+    }
+}
+
+// applies every option that only concerns the searcher, `name` in lowercase
+pub fn apply_option(searcher: &mut Searcher, name: &str, value: &str) -> Result<(), anyhow::Error> {
+    match name {
+        "hash" => searcher.set_hash(spin(value, MAX_HASH)?),
+        "threads" => searcher.set_threads(spin(value, MAX_THREADS)?),
+        "multipv" => searcher.set_multipv(spin(value, MAX_MULTIPV)?),
+        "ponder" => (),
+        "evalparams" => searcher.set_params(match value {
+            "" | "<empty>" => EvalParams::default(),
+            path => EvalParams::load(path)?,
+        }),
+        "syzygypath" => {
+            let tablebase = match value {
+                "" | "<empty>" => None,
+                dir => Some(Arc::new(Tablebase::open(dir)?)),
+            };
+            searcher.set_tablebase(tablebase);
+        }
+        _ => {
+            let enabled: bool = value.parse()?;
+            let config = searcher.config_mut();
+            let toggle = match name {
+                "nullmove" => &mut config.null_move,
+                "lmr" => &mut config.lmr,
+                "futility" => &mut config.futility,
+                "razoring" => &mut config.razoring,
+                "checkextensions" => &mut config.check_extensions,
+                "aspirationwindows" => &mut config.aspiration,
+                _ => return Err(anyhow!("No such option: {}", name)),
+            };
+            *toggle = enabled;
+        }
+    }
+    Ok(())
+}
+
+// the value of a spin option from 1 to `max`
+fn spin(value: &str, max: usize) -> Result<usize, anyhow::Error> {
+    match value.parse()? {
+        value @ 1.. if value <= max => Ok(value),
+        value => Err(anyhow!("{} is not between 1 and {}", value, max)),
+    }
+}
+
+// names of the `SearchConfig` switches as UCI check options
+const SEARCH_TOGGLES: [&str; 6] = [
+    "NullMove",
+    "LMR",
+    "Futility",
+    "Razoring",
+    "CheckExtensions",
+    "AspirationWindows",
+];
+
+fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+fn print_info(result: &SearchResult) {
+    let millis = result.time.as_millis() as u64;
+    let nps = result.nodes * 1000 / millis.max(1);
+    for (i, line) in result.lines.iter().enumerate() {
+        let pv: Vec<String> = line.pv.iter().map(|mv| mv.to_string()).collect();
+        println!(
+            "info depth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+            result.depth,
+            i + 1,
+            format_score(line.score),
+            result.nodes,
+            nps,
+            millis,
+            pv.join(" ")
+        );
+    }
+    io::stdout().flush().ok();
+}
+
+// setoption name <id> [value <x>], names may contain spaces. the name comes back in lowercase
+fn parse_option(tokens: &[&str]) -> Result<(String, String), anyhow::Error> {
+    let value_at = tokens
+        .iter()
+        .position(|&t| t == "value")
+        .unwrap_or(tokens.len());
+    match tokens.first() {
+        Some(&"name") if value_at > 1 => (),
+        _ => return Err(anyhow!("Expected name <id> [value <x>]")),
+    }
+    let name = tokens[1..value_at].join(" ").to_lowercase();
+    let value = tokens.get(value_at + 1..).unwrap_or_default().join(" ");
+    Ok((name, value))
+}
+
+// position [startpos | fen <fen>] [moves <move>...]
+fn parse_position(tokens: &[&str]) -> Result<State, anyhow::Error> {
+    let moves_at = tokens
+        .iter()
+        .position(|&t| t == "moves")
+        .unwrap_or(tokens.len());
+
+    let mut state = match tokens.first() {
+        Some(&"startpos") => State::default(),
+        Some(&"fen") => State::from_fen(&tokens[1..moves_at].join(" "))?,
+        _ => return Err(anyhow!("Expected startpos or fen")),
+    };
+
+    for s in tokens.iter().skip(moves_at + 1) {
+        let mv = state
+            .parse_move(s)
+            .ok_or_else(|| anyhow!("Illegal move {}", s))?;
+        state.make_move(mv);
+    }
+    Ok(state)
+}
+
+fn parse_go(tokens: &[&str], state: &State) -> Limits {
+    let mut limits = Limits::default();
+    let millis = |s: Option<&&str>| {
+        s.and_then(|s| s.parse::<i64>().ok())
+            .map(|ms| Duration::from_millis(ms.max(0) as u64))
+    };
+    let (time, increment) = match state.side {
+        Kind::White => ("wtime", "winc"),
+        Kind::Black => ("btime", "binc"),
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let value = tokens.get(i + 1);
+        match tokens[i] {
+            t if t == time => limits.time = millis(value),
+            t if t == increment => limits.increment = millis(value).unwrap_or_default(),
+            "movestogo" => limits.moves_to_go = value.and_then(|s| s.parse().ok()),
+            "depth" => limits.depth = value.and_then(|s| s.parse().ok()),
+            "nodes" => limits.nodes = value.and_then(|s| s.parse().ok()),
+            "mate" => limits.mate = value.and_then(|s| s.parse().ok()),
+            "movetime" => limits.movetime = millis(value),
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            "searchmoves" => {
+                while let Some(mv) = tokens.get(i + 1).and_then(|s| state.parse_move(s)) {
+                    limits.search_moves.push(mv);
+                    i += 1;
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_position() {
+        let state = parse_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"]).unwrap();
+        assert_eq!(
+            state.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+
+        let fen = "8/8/8/8/8/8/4k3/7K w - - 0 1";
+        let line = format!("fen {}", fen);
+        let tokens: Vec<&str> = line.split(' ').collect();
+        assert_eq!(parse_position(&tokens).unwrap().to_fen(), fen);
+
+        assert!(parse_position(&["startpos", "moves", "e2e5"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_option() {
+        let (name, value) = parse_option(&["name", "Skill", "Level", "value", "5"]).unwrap();
+        assert_eq!((name.as_str(), value.as_str()), ("skill level", "5"));
+        let (name, value) = parse_option(&["name", "Clear", "Hash"]).unwrap();
+        assert_eq!((name.as_str(), value.as_str()), ("clear hash", ""));
+        assert!(parse_option(&["value", "x"]).is_err());
+        assert!(parse_option(&["name", "value", "x"]).is_err());
+        assert!(parse_option(&[]).is_err());
+    }
+
+    #[test]
+    fn test_apply_option() {
+        let mut searcher = Searcher::new(State::default(), SearchConfig::default());
+        assert!(apply_option(&mut searcher, "hash", "100000000").is_err());
+        assert!(apply_option(&mut searcher, "threads", "100000").is_err());
+        assert!(apply_option(&mut searcher, "threads", "0").is_err());
+        assert!(apply_option(&mut searcher, "multipv", "-1").is_err());
+        apply_option(&mut searcher, "hash", "1").unwrap();
+        apply_option(&mut searcher, "threads", "2").unwrap();
+    }
+
+    #[test]
+    fn test_parse_go() {
+        let state = parse_position(&["startpos", "moves", "e2e4"]).unwrap();
+        let limits = parse_go(
+            &[
+                "wtime",
+                "1000",
+                "btime",
+                "2000",
+                "winc",
+                "10",
+                "binc",
+                "20",
+                "movestogo",
+                "5",
+            ],
+            &state,
+        );
+        assert_eq!(limits.time, Some(Duration::from_millis(2000)));
+        assert_eq!(limits.increment, Duration::from_millis(20));
+        assert_eq!(limits.moves_to_go, Some(5));
+
+        let limits = parse_go(
+            &["infinite", "searchmoves", "e7e5", "d7d5", "depth", "3"],
+            &state,
+        );
+        assert!(limits.infinite);
+        assert_eq!(limits.search_moves.len(), 2);
+        assert_eq!(limits.depth, Some(3));
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::chess::Kind;
+use crate::games::{secret_token, Ending};
+use crate::rating::Rating;
+use crate::storage::{now, Log};
+
+// registered users are kept in `users.jsonl` next to the games, as an append-only log of
+// registrations and finished games with the ratings after them. sessions are kept in memory
+// only, users log in again after a restart
+const USERS_FILE: &str = "users.jsonl";
+
+// sessions end a week after logging in, or when the user logs out
+const SESSION_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const MAX_NAME: usize = 32;
+const MIN_PASSWORD: usize = 8;
+// rounds of PBKDF2, the iterations are stored with every hash so they can be raised later.
+// tests hash with fewer, unoptimized builds take seconds for the full count
+const ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 100_000 };
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record {
+    Register {
+        name: String,
+        password: String,
+        at: u64,
+    },
+    // a finished game with at least one registered player, with the ratings of white and
+    // black after it when it was rated
+    Game {
+        id: u64,
+        white: Option<String>,
+        black: Option<String>,
+        result: String,
+        reason: String,
+        ratings: Option<[Rating; 2]>,
+        at: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    // "pbkdf2-sha256$<iterations>$<salt>$<hash in hex>"
+    password: String,
+    pub rating: Rating,
+    // the rating after each rated game, oldest first
+    pub history: Vec<RatingChange>,
+    // finished games, oldest first
+    pub games: Vec<Played>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RatingChange {
+    pub game: u64,
+    pub rating: Rating,
+    pub at: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Played {
+    pub game: u64,
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub result: String,
+    pub reason: String,
+    pub rated: bool,
+    pub at: u64,
+}
+
+// the server's users by name and who is logged in with which session token
+#[derive(Default)]
+pub struct Users {
+    users: Mutex<HashMap<String, User>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    log: Option<Log>,
+}
+
+struct Session {
+    name: String,
+    expires: Instant,
+}
+
+impl Users {
+    // the users recorded in `dir`, new ones are recorded there as well
+    pub fn open(dir: &Path) -> Result<Users, anyhow::Error> {
+        fs::create_dir_all(dir)?;
+        let log = Log::open(dir.join(USERS_FILE))?;
+        let mut users = HashMap::new();
+        for record in log.read::<Record>()? {
+            apply(&mut users, record);
+        }
+        Ok(Users {
+            users: Mutex::new(users),
+            sessions: Mutex::new(HashMap::new()),
+            log: Some(log),
+        })
+    }
+
+    pub fn register(&self, name: &str, password: &str) -> Result<(), anyhow::Error> {
+        check_credentials(name, password)?;
+        // hashing takes a while, it is done before taking the lock
+        let password = hash_password(password);
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(name) {
+            return Err(anyhow!("The name {} is taken", name));
+        }
+        self.record(
+            &mut users,
+            Record::Register {
+                name: name.to_string(),
+                password,
+                at: now(),
+            },
+        );
+        Ok(())
+    }
+
+    // a new session token for the user, none when the name or password is wrong
+    pub fn login(&self, name: &str, password: &str) -> Option<String> {
+        let hash = self.users.lock().unwrap().get(name)?.password.clone();
+        if !verify_password(password, &hash) {
+            return None;
+        }
+        let token = secret_token();
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        let session = Session {
+            name: name.to_string(),
+            expires: now + SESSION_LIFETIME,
+        };
+        sessions.insert(token.clone(), session);
+        Some(token)
+    }
+
+    // the name of the user logged in with `token`, none once the session expired
+    pub fn session(&self, token: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(token)?;
+        (session.expires > Instant::now()).then(|| session.name.clone())
+    }
+
+    // ends the session of `token`, false when there was none
+    pub fn logout(&self, token: &str) -> bool {
+        self.sessions.lock().unwrap().remove(token).is_some()
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.users.lock().unwrap().get(name).cloned()
+    }
+
+    // adds a finished game to the history of its registered players, a rated game between
+    // two of them updates both ratings. aborted games are left out
+    pub fn finish(&self, id: u64, players: &[Option<String>; 2], rated: bool, ending: &Ending) {
+        if ending.aborted || players.iter().all(Option::is_none) {
+            return;
+        }
+        let mut users = self.users.lock().unwrap();
+        let ratings = match players {
+            [Some(white), Some(black)] if rated => {
+                let (white, black) = match (users.get(white), users.get(black)) {
+                    (Some(white), Some(black)) => (white.rating, black.rating),
+                    _ => {
+                        warn!("game {}: rated for unknown users", id);
+                        return;
+                    }
+                };
+                let score = match ending.winner {
+                    Some(Kind::White) => 1.0,
+                    Some(Kind::Black) => 0.0,
+                    None => 0.5,
+                };
+                Some([
+                    white.update(&[(black, score)]),
+                    black.update(&[(white, 1.0 - score)]),
+                ])
+            }
+            _ => None,
+        };
+        self.record(
+            &mut users,
+            Record::Game {
+                id,
+                white: players[0].clone(),
+                black: players[1].clone(),
+                result: ending.result().to_string(),
+                reason: ending.reason.clone(),
+                ratings,
+                at: now(),
+            },
+        );
+    }
+
+    fn record(&self, users: &mut HashMap<String, User>, record: Record) {
+        if let Some(log) = &self.log {
+            log.append(&record);
+        }
+        apply(users, record);
+    }
+}
+
+fn apply(users: &mut HashMap<String, User>, record: Record) {
+    match record {
+        Record::Register { name, password, .. } => {
+            let user = User {
+                name: name.clone(),
+                password,
+                rating: Rating::default(),
+                history: Vec::new(),
+                games: Vec::new(),
+            };
+            users.insert(name, user);
+        }
+        Record::Game {
+            id,
+            white,
+            black,
+            result,
+            reason,
+            ratings,
+            at,
+        } => {
+            let played = Played {
+                game: id,
+                white: white.clone(),
+                black: black.clone(),
+                result,
+                reason,
+                rated: ratings.is_some(),
+                at,
+            };
+            for (i, name) in [white, black].into_iter().enumerate() {
+                let user = match name.and_then(|name| users.get_mut(&name)) {
+                    Some(user) => user,
+                    None => continue,
+                };
+                if let Some(ratings) = ratings {
+                    user.rating = ratings[i];
+                    user.history.push(RatingChange {
+                        game: id,
+                        rating: ratings[i],
+                        at,
+                    });
+                }
+                user.games.push(played.clone());
+            }
+        }
+    }
+}
+
+// names are up to 32 letters, digits, '-' and '_', passwords at least 8 characters
+pub fn check_credentials(name: &str, password: &str) -> Result<(), anyhow::Error> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > MAX_NAME || !name.chars().all(valid) {
+        return Err(anyhow!("Invalid name {:?}", name));
+    }
+    if password.chars().count() < MIN_PASSWORD {
+        return Err(anyhow!(
+            "Passwords need at least {} characters",
+            MIN_PASSWORD
+        ));
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("The OS provides random numbers");
+    let salt = hex(&salt);
+    let hash = pbkdf2(password.as_bytes(), salt.as_bytes(), ITERATIONS);
+    format!("pbkdf2-sha256${}${}${}", ITERATIONS, salt, hex(&hash))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    let (iterations, salt, expected) = match parts[..] {
+        ["pbkdf2-sha256", iterations, salt, expected] => match iterations.parse() {
+            Ok(iterations) => (iterations, salt, expected),
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    let actual = hex(&pbkdf2(password.as_bytes(), salt.as_bytes(), iterations));
+    // compared in constant time so the time taken tells nothing about the hash
+    actual.len() == expected.len()
+        && actual
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// PBKDF2 with HMAC-SHA-256 for a key as long as one hash
+fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::State;
+    use crate::games::{Engines, Player, Registry};
+    use crate::storage::Storage;
+    use crate::timeman::Limits;
+
+    #[test]
+    fn test_password_hash() {
+        // the published PBKDF2-HMAC-SHA256 test vectors
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 1)),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 2)),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        let hash = hash_password("correct horse");
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horsf", &hash));
+        assert_ne!(hash, hash_password("correct horse"));
+    }
+
+    #[test]
+    fn test_rated_game() {
+        let dir = std::env::temp_dir().join(format!("chess-users-{}", now()));
+        let users = Users::open(&dir).unwrap();
+        users.register("alice", "password1").unwrap();
+        users.register("bob", "password2").unwrap();
+        assert!(users.register("alice", "password3").is_err());
+        assert!(users.register("a b", "password3").is_err());
+        assert!(users.login("alice", "password2").is_none());
+        let alice = users.login("alice", "password1").unwrap();
+        assert_eq!(users.session(&alice).as_deref(), Some("alice"));
+        assert!(users.logout(&alice));
+        assert!(users.session(&alice).is_none());
+        assert!(!users.logout(&alice));
+        let expired = users.login("alice", "password1").unwrap();
+        users
+            .sessions
+            .lock()
+            .unwrap()
+            .get_mut(&expired)
+            .unwrap()
+            .expires = Instant::now();
+        assert!(users.session(&expired).is_none());
+
+        let registry =
+            Registry::with_storage(Engines::default(), Storage::open(&dir).unwrap(), users)
+                .unwrap();
+        let game = registry
+            .create(
+                State::default(),
+                Player::Human,
+                Player::Human,
+                Limits::default(),
+                None,
+                true,
+            )
+            .unwrap();
+        let (_, white) = game.lock().unwrap().join(None, Some("alice")).unwrap();
+        // rated games are between two different registered users
+        assert!(game.lock().unwrap().join(None, None).is_err());
+        assert!(game.lock().unwrap().join(None, Some("alice")).is_err());
+        let (_, black) = game.lock().unwrap().join(None, Some("bob")).unwrap();
+        for (token, mv) in [
+            (&white, "f2f3"),
+            (&black, "e7e5"),
+            (&white, "g2g4"),
+            (&black, "d8h4"),
+        ] {
+            registry.play(&game, token, mv).unwrap();
+        }
+        let alice = registry.users().user("alice").unwrap();
+        let bob = registry.users().user("bob").unwrap();
+        assert!(alice.rating.rating < 1500.0);
+        assert!(bob.rating.rating > 1500.0);
+        assert_eq!(bob.history.len(), 1);
+        assert_eq!(bob.games[0].result, "0-1");
+        drop(registry);
+
+        // ratings survive a restart
+        let users = Users::open(&dir).unwrap();
+        assert_eq!(users.user("bob").unwrap().rating, bob.rating);
+        assert!(users.login("bob", "password2").is_some());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::chess::{Kind, State, Undo};
+use crate::search::{mate_in, SearchConfig, SearchResult, Searcher};
+use crate::tablebase::Tablebase;
+use crate::timeman::{Limits, Signals};
+
+// speaks the Chess Engine Communication Protocol (xboard/winboard) over stdin/stdout
+pub fn run() {
+    let (events, rx) = mpsc::channel();
+    let input = events.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if input.send(Event::Line(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        input.send(Event::Line("quit".to_string())).ok();
+    });
+
+    let mut xboard = XBoard::new(events, rx);
+    while let Some(event) = xboard.next_event() {
+        let running = match event {
+            Event::Line(line) => xboard.command(line.trim()),
+            Event::Done(searcher, result) => {
+                xboard.finish(searcher, result);
+                true
+            }
+        };
+        io::stdout().flush().ok();
+        if !running {
+            break;
+        }
+    }
+    xboard.abandon();
+}
+
+enum Event {
+    Line(String),
+    Done(Box<Searcher>, SearchResult),
+}
+
+// conventional time control: moves per session (0 for the whole game), base time, increment
+#[derive(Clone, Debug, PartialEq)]
+struct Level {
+    moves: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+struct XBoard {
+    state: State,
+    undo: Vec<Undo>,
+    // the searcher moves into the worker thread while thinking
+    searcher: Option<Searcher>,
+    signals: Signals,
+    // side played by the engine, none in force mode
+    engine: Option<Kind>,
+    level: Option<Level>,
+    movetime: Option<Duration>,
+    depth: Option<u32>,
+    // our clock as reported by `time`
+    clock: Option<Duration>,
+    post: Arc<AtomicBool>,
+    pending_pings: Vec<String>,
+    game_over: bool,
+    events: Sender<Event>,
+    rx: Receiver<Event>,
+    // input that arrived while waiting for an abandoned search
+    queued: VecDeque<String>,
+}
+
+impl XBoard {
+    fn new(events: Sender<Event>, rx: Receiver<Event>) -> XBoard {
+        let searcher = Searcher::new(State::default(), SearchConfig::default());
+        XBoard {
+            state: State::default(),
+            undo: Vec::new(),
+            signals: searcher.signals(),
+            searcher: Some(searcher),
+            engine: Some(Kind::Black),
+            level: None,
+            movetime: None,
+            depth: None,
+            clock: None,
+            post: Arc::new(AtomicBool::new(false)),
+            pending_pings: Vec::new(),
+            game_over: false,
+            events,
+            rx,
+            queued: VecDeque::new(),
+        }
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        match self.queued.pop_front() {
+            Some(line) => Some(Event::Line(line)),
+            None => self.rx.recv().ok(),
+        }
+    }
+
+    fn thinking(&self) -> bool {
+        self.searcher.is_none()
+    }
+
+    // returns false on `quit`
+    fn command(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        let command = match tokens.next() {
+            Some(command) => command,
+            None => return true,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "otim" | "name" | "draw" | "white" | "black" => (),
+            "protover" => println!(
+                "feature myname=\"chess\" ping=1 setboard=1 usermove=1 playother=0 san=0 colors=0 analyze=0 sigint=0 sigterm=0 reuse=1 egt=\"syzygy\" done=1"
+            ),
+            "new" => {
+                self.abandon();
+                self.state = State::default();
+                self.undo.clear();
+                self.engine = Some(Kind::Black);
+                self.depth = None;
+                self.game_over = false;
+                if let Some(searcher) = self.searcher.as_mut() {
+                    searcher.new_game();
+                }
+            }
+            "force" => {
+                self.abandon();
+                self.engine = None;
+            }
+            "go" => {
+                self.abandon();
+                self.engine = Some(self.state.side);
+                self.think();
+            }
+            "usermove" => self.user_move(args.first().copied().unwrap_or_default()),
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "setboard" => {
+                self.abandon();
+                match State::from_fen(&args.join(" ")) {
+                    Ok(state) => {
+                        self.state = state;
+                        self.undo.clear();
+                        self.game_over = false;
+                    }
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+            }
+            "level" => match parse_level(&args) {
+                Some(level) => {
+                    self.level = Some(level);
+                    self.movetime = None;
+                }
+                None => println!("Error (bad level): {}", args.join(" ")),
+            },
+            "st" => self.movetime = args.first().and_then(|s| parse_seconds(s)),
+            "sd" => self.depth = args.first().and_then(|s| s.parse().ok()),
+            "time" => {
+                self.clock = args
+                    .first()
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .map(|cs| Duration::from_millis(cs * 10))
+            }
+            "egtpath" if args.first() == Some(&"syzygy") => {
+                self.abandon();
+                let tablebase = match Tablebase::open(args[1..].join(" ")) {
+                    Ok(tablebase) => Some(Arc::new(tablebase)),
+                    Err(e) => {
+                        println!("tellusererror {}", e);
+                        None
+                    }
+                };
+                if let Some(searcher) = self.searcher.as_mut() {
+                    searcher.set_tablebase(tablebase);
+                }
+            }
+            "post" => self.post.store(true, Ordering::Relaxed),
+            "nopost" => self.post.store(false, Ordering::Relaxed),
+            "result" => {
+                self.abandon();
+                self.game_over = true;
+                self.engine = None;
+            }
+            // move now
+            "?" => self.signals.stop.store(true, Ordering::Relaxed),
+            "ping" => {
+                let reply = format!("pong {}", args.first().copied().unwrap_or_default());
+                if self.thinking() {
+                    self.pending_pings.push(reply);
+                } else {
+                    println!("{}", reply);
+                }
+            }
+            "quit" => return false,
+            // without usermove=1 moves come on their own
+            _ if self.state.parse_move(command).is_some() => self.user_move(command),
+            _ => println!("Error (unknown command): {}", command),
+        }
+        true
+    }
+
+    // stops a running search and throws its result away
+    fn abandon(&mut self) {
+        if !self.thinking() {
+            return;
+        }
+        self.signals.stop.store(true, Ordering::Relaxed);
+        while let Ok(event) = self.rx.recv() {
+            match event {
+                Event::Done(searcher, _) => {
+                    self.searcher = Some(*searcher);
+                    break;
+                }
+                Event::Line(line) => self.queued.push_back(line),
+            }
+        }
+        self.flush_pings();
+    }
+
+    fn user_move(&mut self, s: &str) {
+        self.abandon();
+        match self.state.parse_move(s) {
+            Some(mv) if !self.game_over => {
+                self.undo.push(self.state.make_move(mv));
+                if !self.check_game_over() {
+                    self.think();
+                }
+            }
+            _ => println!("Illegal move: {}", s),
+        }
+    }
+
+    fn take_back(&mut self, moves: usize) {
+        self.abandon();
+        for _ in 0..moves {
+            if let Some(undo) = self.undo.pop() {
+                self.state.unmake_move(undo);
+                self.game_over = false;
+            }
+        }
+    }
+
+    // prints the result and returns true if the game just ended
+    fn check_game_over(&mut self) -> bool {
+        if let Some(outcome) = self.state.outcome().or(self.state.claimable_draw()) {
+            println!("{} {{{}}}", outcome.result(), outcome.reason());
+            self.game_over = true;
+        }
+        self.game_over
+    }
+
+    fn limits(&self) -> Limits {
+        let mut limits = Limits {
+            depth: self.depth,
+            movetime: self.movetime,
+            ..Limits::default()
+        };
+        if let (None, Some(level)) = (self.movetime, &self.level) {
+            limits.time = Some(self.clock.unwrap_or(level.base));
+            limits.increment = level.increment;
+            if level.moves > 0 {
+                let played = self.state.fullmove.saturating_sub(1);
+                limits.moves_to_go = Some(level.moves - played % level.moves);
+            }
+        }
+        if limits == Limits::default() {
+            // no time control given at all
+            limits.movetime = Some(Duration::from_secs(5));
+        }
+        limits
+    }
+
+    // starts thinking if it is the engine's turn
+    fn think(&mut self) {
+        if self.game_over || self.engine != Some(self.state.side) {
+            return;
+        }
+        let mut searcher = match self.searcher.take() {
+            Some(searcher) => searcher,
+            None => return,
+        };
+
+        let limits = self.limits();
+        let post = self.post.clone();
+        self.signals.stop.store(false, Ordering::Relaxed);
+        searcher.set_state(self.state.clone());
+        searcher.set_reporter(Box::new(move |result| {
+            if post.load(Ordering::Relaxed) {
+                print_thinking(result);
+            }
+        }));
+
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let result = searcher.search(&limits);
+            events.send(Event::Done(Box::new(searcher), result)).ok();
+        });
+    }
+
+    fn finish(&mut self, searcher: Box<Searcher>, result: SearchResult) {
+        self.searcher = Some(*searcher);
+        if let Some(mv) = result.best_move {
+            self.undo.push(self.state.make_move(mv));
+            println!("move {}", mv);
+            self.check_game_over();
+        }
+        self.flush_pings();
+    }
+
+    fn flush_pings(&mut self) {
+        for reply in self.pending_pings.drain(..) {
+            println!("{}", reply);
+        }
+    }
+}
+
+// thinking output: ply, score, time in centiseconds, nodes, principal variation
+fn print_thinking(result: &SearchResult) {
+    let score = match mate_in(result.score) {
+        Some(moves) if moves > 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => result.score,
+    };
+    let pv: Vec<String> = result.pv.iter().map(|mv| mv.to_string()).collect();
+    println!(
+        "{} {} {} {} {}",
+        result.depth,
+        score,
+        result.time.as_millis() / 10,
+        result.nodes,
+        pv.join(" ")
+    );
+    io::stdout().flush().ok();
+}
+
+// "5" or "0.5" seconds
+fn parse_seconds(s: &str) -> Option<Duration> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+// level MPS BASE INC, where base is minutes or minutes:seconds
+fn parse_level(args: &[&str]) -> Option<Level> {
+    if args.len() != 3 {
+        return None;
+    }
+    let moves = args[0].parse().ok()?;
+    let base = match args[1].split_once(':') {
+        Some((minutes, seconds)) => {
+            Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?)
+        }
+        None => Duration::from_secs(args[1].parse::<u64>().ok()? * 60),
+    };
+    let increment = parse_seconds(args[2])?;
+    Some(Level {
+        moves,
+        base,
+        increment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(
+            parse_level(&["40", "5", "0"]),
+            Some(Level {
+                moves: 40,
+                base: Duration::from_secs(300),
+                increment: Duration::ZERO,
+            })
+        );
+        assert_eq!(
+            parse_level(&["0", "2:30", "0.5"]),
+            Some(Level {
+                moves: 0,
+                base: Duration::from_secs(150),
+                increment: Duration::from_millis(500),
+            })
+        );
+        assert_eq!(parse_level(&["0", "x", "1"]), None);
+        assert_eq!(parse_level(&["0", "5", "-1"]), None);
+        assert_eq!(parse_seconds("inf"), None);
+        assert_eq!(parse_seconds("NaN"), None);
+    }
+
+    #[test]
+    fn test_commands() {
+        let (events, rx) = mpsc::channel();
+        let mut xboard = XBoard::new(events, rx);
+        xboard.command("force");
+        xboard.command("usermove e2e4");
+        // illegal moves are refused, moves also come without usermove
+        xboard.command("usermove e2e4");
+        xboard.command("e7e5");
+        assert_eq!(xboard.undo.len(), 2);
+        assert_eq!(xboard.state.side, Kind::White);
+        xboard.command("undo");
+        assert_eq!(xboard.state.side, Kind::Black);
+        xboard.command("remove");
+        assert_eq!(xboard.state.to_fen(), State::default().to_fen());
+        assert!(!xboard.thinking());
+
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        xboard.command(&format!("setboard {}", fen));
+        xboard.command("setboard not a position");
+        assert_eq!(xboard.state.to_fen(), fen);
+
+        // go makes the engine play the side to move, here mate in one
+        xboard.command("sd 2");
+        xboard.command("go");
+        assert!(xboard.thinking());
+        match xboard.next_event() {
+            Some(Event::Done(searcher, result)) => xboard.finish(searcher, result),
+            _ => panic!("Expected the search to finish"),
+        }
+        assert_eq!(xboard.engine, Some(Kind::White));
+        assert_eq!(xboard.state.side, Kind::Black);
+        assert!(xboard.game_over);
+    }
+}
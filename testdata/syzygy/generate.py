@@ -0,0 +1,342 @@
+#!/usr/bin/env python3
+# writes KRvK.rtbw and KRvK.rtbz, small Syzygy tables for the tablebase tests. the endgame
+# is solved here by retrograde analysis and stored in the layout the Syzygy probing code
+# reads: the strong side's pieces first, both sides to move in the WDL table and only the
+# side with the rook to move in the DTZ table. values are huffman coded with one pair symbol.
+# the layout follows the reader in src/tablebase.rs, so the tables test the format and the
+# probing around it but do not validate the reader against tables from the Syzygy generator
+import heapq
+import os
+import struct
+from collections import Counter, deque
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+WIN_PLIES, LOSS_PLIES, SINGLE_VALUE = 4, 8, 128
+BLOCK_BITS, SPAN_BITS = 6, 10
+# pieces in table order: white king, white rook, black king
+PIECES = [6, 4, 14]
+SIZE = 31332
+
+
+def rank(sq):
+    return sq // 8
+
+
+def file(sq):
+    return sq % 8
+
+
+def touching(a, b):
+    return abs(rank(a) - rank(b)) <= 1 and abs(file(a) - file(b)) <= 1
+
+
+def king_moves(sq):
+    for dr in (-1, 0, 1):
+        for df in (-1, 0, 1):
+            r, f = rank(sq) + dr, file(sq) + df
+            if (dr or df) and 0 <= r < 8 and 0 <= f < 8:
+                yield r * 8 + f
+
+
+def rook_moves(sq, blockers):
+    for dr, df in ((1, 0), (-1, 0), (0, 1), (0, -1)):
+        r, f = rank(sq) + dr, file(sq) + df
+        while 0 <= r < 8 and 0 <= f < 8:
+            to = r * 8 + f
+            yield to
+            if to in blockers:
+                break
+            r, f = r + dr, f + df
+
+
+def rook_attacks(rook, target, blockers):
+    return any(sq == target for sq in rook_moves(rook, blockers))
+
+
+# positions are (white king, rook, black king, black to move)
+def legal(wk, wr, bk, btm):
+    if len({wk, wr, bk}) < 3 or touching(wk, bk):
+        return False
+    # the side that just moved can't be in check
+    return btm or not rook_attacks(wr, bk, {wk})
+
+
+def children(wk, wr, bk, btm):
+    if not btm:
+        for to in king_moves(wk):
+            if to != wr and not touching(to, bk):
+                yield (to, wr, bk, True)
+        for to in rook_moves(wr, {wk, bk}):
+            if to not in (wk, bk):
+                yield (wk, to, bk, True)
+    else:
+        for to in king_moves(bk):
+            if touching(to, wk):
+                continue
+            if to == wr:
+                # taking the rook draws
+                yield None
+            elif not rook_attacks(wr, to, {wk}):
+                yield (wk, wr, to, False)
+
+
+def solve():
+    positions = [
+        (wk, wr, bk, btm)
+        for wk in range(64)
+        for wr in range(64)
+        for bk in range(64)
+        for btm in (False, True)
+        if legal(wk, wr, bk, btm)
+    ]
+    parents = {position: [] for position in positions}
+    remaining = {}
+    # plies to mate: positive for wins, negative for losses, mated positions count -1
+    dtz = {}
+    queue = deque()
+    for position in positions:
+        moves = list(children(*position))
+        for child in moves:
+            if child is not None:
+                parents[child].append(position)
+        if position[3]:
+            remaining[position] = len(moves)
+            if not moves and rook_attacks(position[1], position[2], {position[0]}):
+                dtz[position] = -1
+                queue.append(position)
+    while queue:
+        position = queue.popleft()
+        value = dtz[position]
+        for parent in parents[position]:
+            if parent in dtz:
+                continue
+            if parent[3]:
+                remaining[parent] -= 1
+                if remaining[parent] == 0:
+                    dtz[parent] = -(value + 1)
+                    queue.append(parent)
+            else:
+                dtz[parent] = 1 if value == -1 else 1 - value
+                queue.append(parent)
+    return positions, dtz
+
+
+def encoding():
+    map_b1h1h7, map_a1d1d4 = [0] * 64, [0] * 64
+    code = 0
+    for sq in range(64):
+        if rank(sq) < file(sq):
+            map_b1h1h7[sq] = code
+            code += 1
+    code, diagonal = 0, []
+    for sq in range(28):
+        if rank(sq) < file(sq) and file(sq) <= 3:
+            map_a1d1d4[sq] = code
+            code += 1
+        elif rank(sq) == file(sq) and file(sq) <= 3:
+            diagonal.append(sq)
+    for sq in diagonal:
+        map_a1d1d4[sq] = code
+        code += 1
+    return map_b1h1h7, map_a1d1d4
+
+
+MAP_B1H1H7, MAP_A1D1D4 = encoding()
+
+
+def index(squares):
+    squares = list(squares)
+    if file(squares[0]) > 3:
+        squares = [sq ^ 7 for sq in squares]
+    if rank(squares[0]) > 3:
+        squares = [sq ^ 56 for sq in squares]
+    for i in range(3):
+        off = rank(squares[i]) - file(squares[i])
+        if off == 0:
+            continue
+        if off > 0:
+            squares[i:] = [((sq >> 3) | (sq << 3)) & 63 for sq in squares[i:]]
+        break
+    s0, s1, s2 = squares
+    adjust1 = int(s1 > s0)
+    adjust2 = int(s2 > s0) + int(s2 > s1)
+    if rank(s0) != file(s0):
+        return (MAP_A1D1D4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    if rank(s1) != file(s1):
+        return (6 * 63 + rank(s0) * 28 + MAP_B1H1H7[s1]) * 62 + s2 - adjust2
+    if rank(s2) != file(s2):
+        return (
+            6 * 63 * 62
+            + 4 * 28 * 62
+            + rank(s0) * 7 * 28
+            + (rank(s1) - adjust1) * 28
+            + MAP_B1H1H7[s2]
+        )
+    return (
+        6 * 63 * 62
+        + 4 * 28 * 62
+        + 4 * 7 * 28
+        + rank(s0) * 7 * 6
+        + (rank(s1) - adjust1) * 6
+        + (rank(s2) - adjust2)
+    )
+
+
+def fill(values):
+    # indices no position maps to get the most common value
+    common = Counter(value for value in values if value is not None).most_common(1)[0][0]
+    return [common if value is None else value for value in values]
+
+
+def store(values, idx, value):
+    if values[idx] is not None and values[idx] != value:
+        raise ValueError("positions sharing index {} differ".format(idx))
+    values[idx] = value
+
+
+def huffman_lengths(counts):
+    if len(counts) == 1:
+        return {symbol: 1 for symbol in counts}
+    heap = [(count, i, [symbol]) for i, (symbol, count) in enumerate(counts.items())]
+    heapq.heapify(heap)
+    lengths = Counter()
+    tiebreak = len(heap)
+    while len(heap) > 1:
+        a, _, first = heapq.heappop(heap)
+        b, _, second = heapq.heappop(heap)
+        for symbol in first + second:
+            lengths[symbol] += 1
+        heapq.heappush(heap, (a + b, tiebreak, first + second))
+        tiebreak += 1
+    return lengths
+
+
+# the sizes header and the index, block lengths and data of one sub-table
+def compress(values, flags):
+    if len(set(values)) == 1:
+        return bytes([flags | SINGLE_VALUE, values[0]]), b"", b"", b""
+
+    # one pair symbol for the most common pair of neighbours
+    pair = Counter(zip(values, values[1:])).most_common(1)[0][0]
+    tokens, i = [], 0
+    while i < len(values):
+        if tuple(values[i : i + 2]) == pair:
+            tokens.append(pair)
+            i += 2
+        else:
+            tokens.append(values[i])
+            i += 1
+    counts = Counter(tokens)
+    # the pair's halves need symbols of their own even if they never appear alone
+    for half in pair:
+        counts[half] = max(counts[half], 1)
+    lengths = huffman_lengths(counts)
+
+    min_len, max_len = min(lengths.values()), max(lengths.values())
+    by_length = [
+        sorted((s for s in lengths if lengths[s] == length), key=str)
+        for length in range(min_len, max_len + 1)
+    ]
+    # canonical code: the longest codes come first, both in symbol numbers and code values
+    offsets = [0] * len(by_length)
+    for i in range(len(by_length) - 2, -1, -1):
+        offsets[i] = offsets[i + 1] + len(by_length[i + 1])
+    bases = [0] * len(by_length)
+    for i in range(len(by_length) - 2, -1, -1):
+        total = bases[i + 1] + len(by_length[i + 1])
+        assert total % 2 == 0
+        bases[i] = total // 2
+    numbers, codes = {}, {}
+    for i, symbols in enumerate(by_length):
+        for j, symbol in enumerate(symbols):
+            numbers[symbol] = offsets[i] + j
+            codes[symbol] = (bases[i] + j, min_len + i)
+
+    tree = [None] * len(numbers)
+    for symbol, number in numbers.items():
+        left, right = (numbers[symbol[0]], numbers[symbol[1]]) if symbol == pair else (symbol, 0xFFF)
+        tree[number] = bytes([left & 0xFF, (left >> 8) | (right & 0xF) << 4, right >> 4])
+
+    block_bits = 8 << BLOCK_BITS
+    blocks, lengths_of_blocks = [], []
+    bits, count = "", 0
+    for token in tokens:
+        code, length = codes[token]
+        if len(bits) + length > block_bits:
+            blocks.append(bits)
+            lengths_of_blocks.append(count - 1)
+            bits, count = "", 0
+        bits += format(code, "0{}b".format(length))
+        count += 2 if token == pair else 1
+    blocks.append(bits)
+    lengths_of_blocks.append(count - 1)
+    data = b"".join(
+        int(block.ljust(block_bits, "0"), 2).to_bytes(block_bits // 8, "big") for block in blocks
+    )
+
+    starts = [0]
+    for length in lengths_of_blocks:
+        starts.append(starts[-1] + length + 1)
+    span = 1 << SPAN_BITS
+    sparse = b""
+    for k in range(-(-len(values) // span)):
+        middle = k * span + span // 2
+        block = max(b for b in range(len(blocks)) if starts[b] <= middle)
+        sparse += struct.pack("<IH", block, middle - starts[block])
+
+    header = bytes([flags, BLOCK_BITS, SPAN_BITS, 0]) + struct.pack("<I", len(blocks))
+    header += bytes([max_len, min_len])
+    header += b"".join(struct.pack("<H", offset) for offset in offsets)
+    header += struct.pack("<H", len(tree)) + b"".join(tree)
+    if len(tree) % 2:
+        header += b"\0"
+    return header, sparse, b"".join(struct.pack("<H", n) for n in lengths_of_blocks), data
+
+
+def table(magic, split, tables):
+    out = magic + bytes([split, 0x00]) + bytes(PIECES[i] | PIECES[i] << 4 for i in range(3))
+    out += b"\0" * (len(out) & 1)
+    parts = [compress(values, flags) for values, flags in tables]
+    for header, _, _, _ in parts:
+        out += header
+    out += b"\0" * (len(out) & 1)
+    for _, sparse, _, _ in parts:
+        out += sparse
+    for _, _, lengths, _ in parts:
+        out += lengths
+    for _, _, _, data in parts:
+        out += b"\0" * (-len(out) % 64) + data
+    return out
+
+
+def main():
+    positions, dtz = solve()
+    wdl = [[None] * SIZE, [None] * SIZE]
+    plies = [None] * SIZE
+    for position in positions:
+        wk, wr, bk, btm = position
+        idx = index((wk, wr, bk))
+        value = dtz.get(position, 0)
+        # loss, draw and win stored as 0, 2 and 4
+        store(wdl[btm], idx, 2 + 2 * (value > 0) - 2 * (value < 0))
+        if not btm:
+            store(plies, idx, max(value - 1, 0))
+
+    here = os.path.dirname(os.path.abspath(__file__))
+    with open(os.path.join(here, "KRvK.rtbw"), "wb") as f:
+        f.write(table(WDL_MAGIC, 1, [(fill(wdl[0]), 0), (fill(wdl[1]), 0)]))
+    with open(os.path.join(here, "KRvK.rtbz"), "wb") as f:
+        f.write(table(DTZ_MAGIC, 0, [(fill(plies), WIN_PLIES | LOSS_PLIES)]))
+
+    # a few solved values for the tests
+    for fen, position in [
+        ("8/8/8/4k3/8/8/8/K6R w", (0, 7, 36, False)),
+        ("8/8/8/4k3/8/8/8/K6R b", (0, 7, 36, True)),
+    ]:
+        print(fen, dtz.get(position, 0))
+    print("longest win", max(dtz.values()), "plies")
+
+
+if __name__ == "__main__":
+    main()
@@ -53,9 +53,18 @@ interface API_Board {
 
 class Game {
     state: State;
+    // the server's game this page plays, from `?game=<id>` or created on load
+    id: number | null;
+    // pushes the moves of both sides as they are played
+    socket: WebSocket | null;
+    // the secret of our seat, kept per game so a reload keeps it. spectators have none
+    token: string | null;
     
     constructor() {
         this.state = new IdleState();
+        this.id = null;
+        this.socket = null;
+        this.token = null;
     }
 
     setState(state: State) {
@@ -104,11 +113,80 @@ class Game {
     }
 
     run() {
-        this.setState(new IdleState());
+        let params = new URLSearchParams(window.location.search);
+        let id = params.get("game");
+        if (id) {
+            this.id = parseInt(id);
+            this.token = localStorage.getItem(`token-${this.id}`);
+            if (this.token) {
+                this._connect();
+                this.setState(new IdleState());
+                return;
+            }
+            // sit down at an open seat, or watch when there is none
+            fetch(`/games/${this.id}/join`, {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({})
+            }).then(response => response.ok ? response.json() : null).then(seat => {
+                if (seat) {
+                    this._seat(seat.token);
+                }
+                this._connect();
+                this.setState(new IdleState());
+            });
+            return;
+        }
+
+        // `?opponent=engine` and `?color=black` are passed on to the new game
+        fetch("/games", {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                opponent: params.get("opponent") || "human",
+                color: params.get("color") || "white"
+            })
+        }).then(response => response.json()).then(data => {
+            this.id = data.id;
+            this._seat(data.token);
+            params.set("game", data.id);
+            window.history.replaceState(null, "", `?${params}`);
+            this._connect();
+            this.setState(new IdleState());
+        });
+    }
+
+    private _seat(token: string) {
+        this.token = token;
+        localStorage.setItem(`token-${this.id}`, token);
+    }
+
+    private _connect() {
+        let protocol = window.location.protocol == "https:" ? "wss" : "ws";
+        let query = this.token ? `?token=${this.token}` : "";
+        this.socket = new WebSocket(`${protocol}://${window.location.host}/games/${this.id}/ws${query}`);
+        this.socket.onmessage = (message) => {
+            let event = JSON.parse(message.data);
+            switch (event.type) {
+                case "board":
+                    this.setBoard(event.board);
+                    break;
+                case "end":
+                    console.log(`${event.result} ${event.reason}`);
+                    break;
+                case "error":
+                    console.warn(event.message);
+                    break;
+            }
+        };
     }
 
     private _refresh() {
-        fetch("/board").then(response => response.json()).then(data => {
+        fetch(`/games/${this.id}/board`).then(response => response.json()).then(data => {
             this.setBoard(data);            
         });
     }
@@ -125,7 +203,7 @@ class Game {
     }
 
     select(pos: string) {
-        fetch(`/moves/${pos}`).then(response => response.json()).then(data => {            
+        fetch(`/games/${this.id}/moves/${pos}`).then(response => response.json()).then(data => {            
             this.state
             this._refresh();
 
@@ -135,10 +213,17 @@ class Game {
     }
     
     handle_move(from: string, to: string) {
-        fetch("/move", {
+        if (this.socket && this.socket.readyState == WebSocket.OPEN) {
+            // the new board arrives over the socket
+            this.socket.send(JSON.stringify({type: "move", from: from, to: to}));
+            this._refresh();
+            return;
+        }
+        fetch(`/games/${this.id}/move`, {
             method: 'POST',
             headers: {
                 'Content-Type': 'application/json',
+                'Authorization': `Bearer ${this.token}`,
             },
             body: JSON.stringify({
                 from: from,